@@ -1,7 +1,8 @@
-DROP SCHEMA IF EXISTS cheechat CASCADE;
-CREATE SCHEMA cheechat;
+-- The schema as it was before the versioned migrations were introduced.
+-- Every statement is idempotent, so existing databases created from the old schema.sql get adopted.
+CREATE SCHEMA IF NOT EXISTS cheechat;
 
-CREATE TABLE cheechat.users (
+CREATE TABLE IF NOT EXISTS cheechat.users (
 	id  BIGSERIAL PRIMARY KEY,
 	email       VARCHAR(200) NOT NULL,
 	first_name  VARCHAR(200) NOT NULL,
@@ -11,7 +12,7 @@ CREATE TABLE cheechat.users (
 	UNIQUE (username)
 );
 
-CREATE TABLE cheechat.chats (
+CREATE TABLE IF NOT EXISTS cheechat.chats (
                                id BIGSERIAL PRIMARY KEY,
                                user1_id BIGINT NOT NULL,
                                user2_id BIGINT NOT NULL,
@@ -22,7 +23,7 @@ CREATE TABLE cheechat.chats (
                                CONSTRAINT unique_chat UNIQUE (user1_id, user2_id)
 );
 
-CREATE TABLE cheechat.chat_messages (
+CREATE TABLE IF NOT EXISTS cheechat.chat_messages (
                           id BIGSERIAL PRIMARY KEY,
                           chat_id BIGINT NOT NULL,
                           sender_id BIGINT NOT NULL,
@@ -32,7 +33,7 @@ CREATE TABLE cheechat.chat_messages (
                           FOREIGN KEY (sender_id) REFERENCES cheechat.users(id)
 );
 
-CREATE INDEX idx_chats_user1_id ON cheechat.chats(user1_id);
-CREATE INDEX idx_chats_user2_id ON cheechat.chats(user2_id);
+CREATE INDEX IF NOT EXISTS idx_chats_user1_id ON cheechat.chats(user1_id);
+CREATE INDEX IF NOT EXISTS idx_chats_user2_id ON cheechat.chats(user2_id);
 
-CREATE INDEX idx_chat_messages_chat_id_created_at ON cheechat.chat_messages(chat_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_chat_messages_chat_id_created_at ON cheechat.chat_messages(chat_id, created_at DESC);
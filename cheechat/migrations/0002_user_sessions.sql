@@ -0,0 +1,14 @@
+-- The login sessions of the users, so they can review and revoke them.
+-- Idempotent, the table may come from the old schema.sql.
+CREATE TABLE IF NOT EXISTS cheechat.user_sessions (
+                          id BIGSERIAL PRIMARY KEY,
+                          user_id BIGINT NOT NULL,
+                          user_agent TEXT,
+                          ip_address VARCHAR(64),
+                          created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                          last_active_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                          revoked_at TIMESTAMP,
+                          FOREIGN KEY (user_id) REFERENCES cheechat.users(id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_sessions_user_id ON cheechat.user_sessions(user_id);
@@ -0,0 +1,14 @@
+-- The optional TOTP two-factor authentication and its one-time recovery codes.
+-- Idempotent, the columns and the table may come from the old schema.sql.
+ALTER TABLE cheechat.users ADD COLUMN IF NOT EXISTS totp_secret VARCHAR(64);
+ALTER TABLE cheechat.users ADD COLUMN IF NOT EXISTS totp_enabled BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE IF NOT EXISTS cheechat.recovery_codes (
+                          id BIGSERIAL PRIMARY KEY,
+                          user_id BIGINT NOT NULL,
+                          code_hash VARCHAR(255) NOT NULL,
+                          used_at TIMESTAMP,
+                          FOREIGN KEY (user_id) REFERENCES cheechat.users(id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_recovery_codes_user_id ON cheechat.recovery_codes(user_id);
@@ -0,0 +1,21 @@
+-- The failed login attempts by account and IP address, and the security audit log.
+-- Idempotent, the tables may come from the old schema.sql.
+CREATE TABLE IF NOT EXISTS cheechat.login_failures (
+                          key VARCHAR(128) PRIMARY KEY,
+                          failures INT NOT NULL DEFAULT 0,
+                          locked_until TIMESTAMP,
+                          last_failure_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS cheechat.audit_log (
+                          id BIGSERIAL PRIMARY KEY,
+                          user_id BIGINT NOT NULL,
+                          event VARCHAR(50) NOT NULL,
+                          ip_address VARCHAR(64),
+                          user_agent TEXT,
+                          details TEXT,
+                          created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                          FOREIGN KEY (user_id) REFERENCES cheechat.users(id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_user_id_created_at ON cheechat.audit_log(user_id, created_at DESC);
@@ -0,0 +1,5 @@
+-- The profile fields of the users, and the deletion of the accounts, which get anonymized.
+-- Idempotent, the columns may come from the old schema.sql.
+ALTER TABLE cheechat.users ADD COLUMN IF NOT EXISTS avatar_url VARCHAR(500);
+ALTER TABLE cheechat.users ADD COLUMN IF NOT EXISTS status_text VARCHAR(200);
+ALTER TABLE cheechat.users ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMP;
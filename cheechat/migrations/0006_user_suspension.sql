@@ -0,0 +1,3 @@
+-- Suspended (banned) users can't log in until the suspension is lifted.
+ALTER TABLE cheechat.users ADD COLUMN suspended_at TIMESTAMP;
+ALTER TABLE cheechat.users ADD COLUMN suspension_reason TEXT;
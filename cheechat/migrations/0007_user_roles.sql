@@ -0,0 +1,5 @@
+-- The role of the user grants the moderation and administration permissions.
+ALTER TABLE cheechat.users ADD COLUMN role VARCHAR(20) NOT NULL DEFAULT 'user'
+    CHECK (role IN ('user', 'moderator', 'admin'));
+
+CREATE INDEX IF NOT EXISTS idx_users_role ON cheechat.users (role) WHERE role <> 'user';
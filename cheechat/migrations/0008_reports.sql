@@ -0,0 +1,26 @@
+-- The reports of messages and users, reviewed by the moderators.
+-- The reported message is copied, so the evidence is kept when the message gets deleted.
+CREATE TABLE cheechat.reports (
+                          id BIGSERIAL PRIMARY KEY,
+                          reporter_id BIGINT NOT NULL,
+                          reported_user_id BIGINT NOT NULL,
+                          chat_id BIGINT,
+                          message_id BIGINT,
+                          message_snapshot TEXT,
+                          message_sent_at TIMESTAMP,
+                          reason TEXT NOT NULL,
+                          status VARCHAR(20) NOT NULL DEFAULT 'open' CHECK (status IN ('open', 'actioned', 'dismissed')),
+                          resolution TEXT,
+                          resolved_by BIGINT,
+                          resolved_at TIMESTAMP,
+                          created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                          FOREIGN KEY (reporter_id) REFERENCES cheechat.users(id),
+                          FOREIGN KEY (reported_user_id) REFERENCES cheechat.users(id),
+                          FOREIGN KEY (chat_id) REFERENCES cheechat.chats(id) ON DELETE SET NULL,
+                          FOREIGN KEY (message_id) REFERENCES cheechat.chat_messages(id) ON DELETE SET NULL,
+                          FOREIGN KEY (resolved_by) REFERENCES cheechat.users(id)
+);
+
+CREATE INDEX idx_reports_status_created_at ON cheechat.reports(status, created_at);
+CREATE INDEX idx_reports_reported_user_id ON cheechat.reports(reported_user_id);
+CREATE INDEX idx_reports_message_id ON cheechat.reports(message_id);
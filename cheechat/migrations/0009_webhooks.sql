@@ -0,0 +1,33 @@
+-- The webhooks registered by the users and the persistent queue of their deliveries.
+CREATE TABLE cheechat.webhooks (
+                          id BIGSERIAL PRIMARY KEY,
+                          user_id BIGINT NOT NULL,
+                          url VARCHAR(500) NOT NULL,
+                          secret VARCHAR(64) NOT NULL,
+                          events VARCHAR(50)[] NOT NULL,
+                          chat_id BIGINT,
+                          active BOOLEAN NOT NULL DEFAULT TRUE,
+                          created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                          FOREIGN KEY (user_id) REFERENCES cheechat.users(id),
+                          FOREIGN KEY (chat_id) REFERENCES cheechat.chats(id) ON DELETE CASCADE
+);
+
+CREATE TABLE cheechat.webhook_deliveries (
+                          id BIGSERIAL PRIMARY KEY,
+                          webhook_id BIGINT NOT NULL,
+                          event VARCHAR(50) NOT NULL,
+                          payload TEXT NOT NULL,
+                          status VARCHAR(20) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'delivered', 'failed')),
+                          attempts INT NOT NULL DEFAULT 0,
+                          next_attempt_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                          last_response_status INT,
+                          last_error TEXT,
+                          created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                          delivered_at TIMESTAMP,
+                          FOREIGN KEY (webhook_id) REFERENCES cheechat.webhooks(id) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_webhooks_user_id ON cheechat.webhooks(user_id);
+CREATE INDEX idx_webhooks_chat_id ON cheechat.webhooks(chat_id);
+CREATE INDEX idx_webhook_deliveries_pending ON cheechat.webhook_deliveries(next_attempt_at) WHERE status = 'pending';
+CREATE INDEX idx_webhook_deliveries_webhook_id_created_at ON cheechat.webhook_deliveries(webhook_id, created_at DESC);
@@ -0,0 +1,16 @@
+-- Bot users are owned by a regular user and authenticate with API tokens instead of passwords.
+ALTER TABLE cheechat.users ADD COLUMN is_bot BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE cheechat.users ADD COLUMN bot_owner_id BIGINT REFERENCES cheechat.users(id);
+
+CREATE TABLE cheechat.api_tokens (
+                          id BIGSERIAL PRIMARY KEY,
+                          user_id BIGINT NOT NULL,
+                          token_hash VARCHAR(64) UNIQUE NOT NULL,
+                          created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                          last_used_at TIMESTAMP,
+                          revoked_at TIMESTAMP,
+                          FOREIGN KEY (user_id) REFERENCES cheechat.users(id)
+);
+
+CREATE INDEX idx_users_bot_owner_id ON cheechat.users(bot_owner_id) WHERE bot_owner_id IS NOT NULL;
+CREATE INDEX idx_api_tokens_user_id ON cheechat.api_tokens(user_id);
@@ -0,0 +1,9 @@
+-- The users that cleared the history of a chat don't see the messages sent before.
+CREATE TABLE cheechat.chat_history_clears (
+                          chat_id BIGINT NOT NULL,
+                          user_id BIGINT NOT NULL,
+                          cleared_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                          PRIMARY KEY (chat_id, user_id),
+                          FOREIGN KEY (chat_id) REFERENCES cheechat.chats(id),
+                          FOREIGN KEY (user_id) REFERENCES cheechat.users(id)
+);
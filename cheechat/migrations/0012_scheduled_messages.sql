@@ -0,0 +1,21 @@
+-- The messages the users schedule to be sent to a chat at a later time.
+CREATE TABLE cheechat.scheduled_messages (
+                          id BIGSERIAL PRIMARY KEY,
+                          chat_id BIGINT NOT NULL,
+                          sender_id BIGINT NOT NULL,
+                          message TEXT NOT NULL,
+                          send_at TIMESTAMP NOT NULL,
+                          status VARCHAR(20) NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'sent', 'cancelled', 'failed')),
+                          attempts INT NOT NULL DEFAULT 0,
+                          claimed_until TIMESTAMP,
+                          message_id BIGINT,
+                          last_error TEXT,
+                          created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                          sent_at TIMESTAMP,
+                          FOREIGN KEY (chat_id) REFERENCES cheechat.chats(id) ON DELETE CASCADE,
+                          FOREIGN KEY (sender_id) REFERENCES cheechat.users(id),
+                          FOREIGN KEY (message_id) REFERENCES cheechat.chat_messages(id) ON DELETE SET NULL
+);
+
+CREATE INDEX idx_scheduled_messages_pending ON cheechat.scheduled_messages(send_at) WHERE status = 'pending';
+CREATE INDEX idx_scheduled_messages_sender_id_send_at ON cheechat.scheduled_messages(sender_id, send_at);
@@ -0,0 +1,4 @@
+-- The chats may have their messages disappear after a while, next to the server wide retention policy.
+ALTER TABLE cheechat.chats ADD COLUMN message_ttl_secs BIGINT CHECK (message_ttl_secs > 0);
+
+CREATE INDEX idx_chat_messages_created_at ON cheechat.chat_messages(created_at);
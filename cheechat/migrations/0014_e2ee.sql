@@ -0,0 +1,27 @@
+-- The opt-in end-to-end encryption of the direct chats: the public keys of the users,
+-- and the chats and messages that only carry ciphertext.
+ALTER TABLE cheechat.chats ADD COLUMN encrypted BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE cheechat.chat_messages ADD COLUMN payload_type VARCHAR(20) NOT NULL DEFAULT 'text'
+    CHECK (payload_type IN ('text', 'ciphertext'));
+
+CREATE TABLE cheechat.user_keys (
+                          user_id BIGINT PRIMARY KEY,
+                          identity_key TEXT NOT NULL,
+                          signed_prekey_id BIGINT NOT NULL,
+                          signed_prekey TEXT NOT NULL,
+                          signed_prekey_signature TEXT NOT NULL,
+                          updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                          FOREIGN KEY (user_id) REFERENCES cheechat.users(id)
+);
+
+-- Every one-time prekey is handed out once, along with the key bundle of its user.
+CREATE TABLE cheechat.one_time_prekeys (
+                          id BIGSERIAL PRIMARY KEY,
+                          user_id BIGINT NOT NULL,
+                          key_id BIGINT NOT NULL,
+                          public_key TEXT NOT NULL,
+                          created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+                          FOREIGN KEY (user_id) REFERENCES cheechat.users(id),
+                          CONSTRAINT unique_one_time_prekey UNIQUE (user_id, key_id)
+);
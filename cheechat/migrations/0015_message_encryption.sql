@@ -0,0 +1,4 @@
+-- The message bodies may be encrypted at rest: the body is encrypted with its own data key,
+-- which is stored wrapped with the master key of the given id. Plain text messages have no key.
+ALTER TABLE cheechat.chat_messages ADD COLUMN key_id VARCHAR(64);
+ALTER TABLE cheechat.chat_messages ADD COLUMN data_key TEXT;
@@ -0,0 +1,8 @@
+-- The reported message snapshots, the webhook payloads and the scheduled messages are encrypted at rest
+-- like the chat messages: each body has its own data key, wrapped with the master key of the given id.
+ALTER TABLE cheechat.reports ADD COLUMN IF NOT EXISTS key_id VARCHAR(64);
+ALTER TABLE cheechat.reports ADD COLUMN IF NOT EXISTS data_key TEXT;
+ALTER TABLE cheechat.webhook_deliveries ADD COLUMN IF NOT EXISTS key_id VARCHAR(64);
+ALTER TABLE cheechat.webhook_deliveries ADD COLUMN IF NOT EXISTS data_key TEXT;
+ALTER TABLE cheechat.scheduled_messages ADD COLUMN IF NOT EXISTS key_id VARCHAR(64);
+ALTER TABLE cheechat.scheduled_messages ADD COLUMN IF NOT EXISTS data_key TEXT;
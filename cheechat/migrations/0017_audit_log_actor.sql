@@ -0,0 +1,2 @@
+-- The moderator or admin that acted on the user, none for the events of the user itself.
+ALTER TABLE cheechat.audit_log ADD COLUMN IF NOT EXISTS actor_id BIGINT REFERENCES cheechat.users(id);
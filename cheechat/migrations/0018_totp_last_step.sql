@@ -0,0 +1,2 @@
+-- The time step of the last accepted code of the authenticator app, so every code is accepted once.
+ALTER TABLE cheechat.users ADD COLUMN IF NOT EXISTS totp_last_step BIGINT;
@@ -0,0 +1,3 @@
+-- The reports of end-to-end encrypted messages carry the plaintext submitted by the reporter,
+-- which the server can't verify against the stored ciphertext.
+ALTER TABLE cheechat.reports ADD COLUMN IF NOT EXISTS e2ee BOOLEAN NOT NULL DEFAULT FALSE;
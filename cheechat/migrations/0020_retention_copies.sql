@@ -0,0 +1,10 @@
+-- The retention policy also covers the copies of the messages: the webhook payloads, the snapshots of the
+-- reports and the scheduled messages. The sweeper looks them up by chat, for the TTL of the chats, and by age.
+ALTER TABLE cheechat.webhook_deliveries ADD COLUMN IF NOT EXISTS chat_id BIGINT REFERENCES cheechat.chats(id) ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_created_at ON cheechat.webhook_deliveries(created_at) WHERE status <> 'pending';
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_chat_id_created_at ON cheechat.webhook_deliveries(chat_id, created_at) WHERE status <> 'pending';
+CREATE INDEX IF NOT EXISTS idx_reports_message_sent_at ON cheechat.reports(message_sent_at) WHERE message_snapshot IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_reports_chat_id_message_sent_at ON cheechat.reports(chat_id, message_sent_at) WHERE message_snapshot IS NOT NULL;
+CREATE INDEX IF NOT EXISTS idx_scheduled_messages_created_at ON cheechat.scheduled_messages(created_at) WHERE status <> 'pending';
+CREATE INDEX IF NOT EXISTS idx_scheduled_messages_chat_id_created_at ON cheechat.scheduled_messages(chat_id, created_at) WHERE status <> 'pending';
@@ -0,0 +1,5 @@
+-- The payloads of the webhook deliveries are now bound to the delivery and the webhook when encrypted,
+-- the pending ones encrypted before can't be decrypted anymore and are given up on.
+UPDATE cheechat.webhook_deliveries
+SET status = 'failed', last_error = 'The payload was encrypted by a previous version and could not be decrypted'
+WHERE status = 'pending' AND key_id IS NOT NULL;
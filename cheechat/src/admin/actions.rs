@@ -0,0 +1,54 @@
+use actix::Addr;
+use sqlx::postgres::PgPool;
+
+use crate::audit;
+use crate::audit::AuditEvent;
+use crate::bots;
+use crate::chat::{ChatServer, RevokeSessions};
+use crate::errors::ApiError;
+use crate::users::{self, AuthUser, ClientInfo, User};
+
+/// Retrieves the existing user that the current user is allowed to moderate.
+pub async fn get_moderated_user(db_pool: &PgPool, auth_user: &AuthUser, user_id: i64) -> Result<User, ApiError> {
+    let user = users::get_user_by_id(db_pool, user_id)
+        .await?
+        .filter(|user| user.deleted_at.is_none())
+        .ok_or(ApiError::NotFound)?;
+
+    if !auth_user.can_moderate(user.id, user.role()) {
+        return Err(ApiError::Forbidden);
+    }
+
+    Ok(user)
+}
+
+/// Suspends the user and closes the active sessions of the user, and of its bots, on the chat server.
+/// The bots can't authenticate while their owner is suspended.
+///
+/// The audit log records the moderator and the client it acted from.
+pub async fn suspend_account(
+    db_pool: &PgPool,
+    chat_server: &Addr<ChatServer>,
+    moderator: &AuthUser,
+    client: &ClientInfo,
+    user_id: i64,
+    reason: Option<&str>,
+) -> Result<(), ApiError> {
+    users::suspend_user(db_pool, user_id, reason).await?;
+    audit::add_moderation_event(db_pool, user_id, moderator.id, AuditEvent::AccountSuspended, client, reason).await?;
+
+    chat_server.do_send(RevokeSessions {
+        user_id,
+        credentials: None,
+        reason: "Account suspended".into(),
+    });
+    for bot in bots::get_bots(db_pool, user_id).await? {
+        chat_server.do_send(RevokeSessions {
+            user_id: bot.id,
+            credentials: None,
+            reason: "Owner suspended".into(),
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,24 @@
+use actix_web::web;
+
+mod actions;
+mod services;
+mod models;
+
+pub use actions::*;
+pub use models::*;
+
+/// Registers the moderation routes, the scope requires at least a moderator.
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(services::search_users);
+    cfg.service(services::get_user);
+    cfg.service(services::suspend_user);
+    cfg.service(services::unsuspend_user);
+    cfg.service(services::inspect_chat);
+}
+
+/// Registers the administration routes, the scope requires an admin.
+pub fn init_admin_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(services::delete_user);
+    cfg.service(services::set_user_role);
+    cfg.service(services::get_stats);
+}
@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+
+use crate::chat::{ChatMessage, ChatStats, PayloadType, ServerStats};
+use crate::users::{Role, User, UserStats};
+
+/// The details of a user as seen by the moderators.
+#[derive(Serialize, Debug)]
+pub struct UserDetails {
+    pub id: i64,
+    pub email: String,
+    pub first_name: String,
+    pub last_name: String,
+    pub username: String,
+    pub role: Role,
+    pub is_bot: bool,
+    pub bot_owner_id: Option<i64>,
+    pub two_factor_enabled: bool,
+    pub suspended_at: Option<i64>,
+    pub suspension_reason: Option<String>,
+    pub deleted_at: Option<i64>,
+}
+
+impl UserDetails {
+    pub fn from_user(user: User) -> Self {
+        Self {
+            id: user.id,
+            role: user.role(),
+            email: user.email,
+            first_name: user.first_name,
+            last_name: user.last_name,
+            username: user.username,
+            is_bot: user.is_bot,
+            bot_owner_id: user.bot_owner_id,
+            two_factor_enabled: user.totp_enabled,
+            suspended_at: user.suspended_at.map(|dt| dt.assume_utc().unix_timestamp()),
+            suspension_reason: user.suspension_reason,
+            deleted_at: user.deleted_at.map(|dt| dt.assume_utc().unix_timestamp()),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchUsersQuery {
+    /// Matches the username, the email or the name of the users.
+    pub q: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SuspendUser {
+    pub reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SetRole {
+    pub role: Role,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MessageDetails {
+    pub id: i64,
+    pub sender_id: i64,
+    /// The content, or the opaque ciphertext of the end-to-end encrypted messages.
+    pub message: String,
+    pub payload_type: PayloadType,
+    pub sent_at: i64,
+}
+
+impl MessageDetails {
+    pub fn from_message(message: ChatMessage) -> Self {
+        Self {
+            id: message.id,
+            sender_id: message.sender_id,
+            payload_type: message.payload_type(),
+            message: message.message,
+            sent_at: message.created_at.assume_utc().unix_timestamp(),
+        }
+    }
+}
+
+/// A conversation with its participants and messages, for the moderators to review.
+#[derive(Serialize, Debug)]
+pub struct ChatInspection {
+    pub id: i64,
+    pub created_at: i64,
+    pub participants: Vec<UserDetails>,
+    pub messages: Vec<MessageDetails>,
+}
+
+/// The stored counters of the deployment along with the live counters of the chat server.
+#[derive(Serialize, Debug)]
+pub struct AdminStats {
+    pub users: UserStats,
+    pub chats: ChatStats,
+    pub server: ServerStats,
+}
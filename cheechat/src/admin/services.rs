@@ -0,0 +1,187 @@
+use actix::Addr;
+use actix_web::web::Path;
+use actix_web::{delete, get, post, put, web, Error, HttpRequest, HttpResponse};
+use sqlx::postgres::PgPool;
+
+use crate::admin::actions::{get_moderated_user, suspend_account};
+use crate::admin::models::{AdminStats, ChatInspection, MessageDetails, SearchUsersQuery, SetRole, SuspendUser, UserDetails};
+use crate::audit;
+use crate::audit::AuditEvent;
+use crate::chat::{self, ChatServer, GetServerStats, MessageCipher, RevokeSessions};
+use crate::reports;
+use crate::errors::ApiError;
+use crate::users::{self, AuthUser, ClientInfo};
+
+const DEFAULT_USERS_LIMIT: i64 = 50;
+const MAX_USERS_LIMIT: i64 = 200;
+
+/// Searches the users, including the suspended and deleted ones
+#[get("/users")]
+pub async fn search_users(
+    query_params: web::Query<SearchUsersQuery>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, Error> {
+    let limit = query_params.limit
+        .unwrap_or(DEFAULT_USERS_LIMIT)
+        .clamp(1, MAX_USERS_LIMIT);
+    let offset = query_params.offset.unwrap_or(0).max(0);
+    let query = query_params.q.as_deref()
+        .map(str::trim)
+        .filter(|query| !query.is_empty());
+
+    let users = users::search_users(db_pool.get_ref(), query, limit, offset).await?;
+
+    let users: Vec<_> = users.into_iter().map(UserDetails::from_user).collect();
+    Ok(HttpResponse::Ok().json(users))
+}
+
+/// Gets the details of the user
+#[get("/users/{user_id}")]
+pub async fn get_user(path: Path<i64>, db_pool: web::Data<PgPool>) -> Result<HttpResponse, Error> {
+    let user = users::get_user_by_id(db_pool.get_ref(), path.into_inner())
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    Ok(HttpResponse::Ok().json(UserDetails::from_user(user)))
+}
+
+/// Suspends the user and closes the active sessions
+#[post("/users/{user_id}/suspend")]
+pub async fn suspend_user(
+    req: HttpRequest,
+    auth_user: AuthUser,
+    path: Path<i64>,
+    request: web::Json<SuspendUser>,
+    db_pool: web::Data<PgPool>,
+    chat_server: web::Data<Addr<ChatServer>>,
+) -> Result<HttpResponse, Error> {
+    let user = get_moderated_user(db_pool.get_ref(), &auth_user, path.into_inner()).await?;
+    let reason = request.reason.as_deref()
+        .map(str::trim)
+        .filter(|reason| !reason.is_empty());
+
+    let client = ClientInfo::from_request(&req);
+    suspend_account(db_pool.get_ref(), chat_server.get_ref(), &auth_user, &client, user.id, reason).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Lifts the suspension of the user
+#[post("/users/{user_id}/unsuspend")]
+pub async fn unsuspend_user(
+    req: HttpRequest,
+    auth_user: AuthUser,
+    path: Path<i64>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, Error> {
+    let user = get_moderated_user(db_pool.get_ref(), &auth_user, path.into_inner()).await?;
+
+    users::unsuspend_user(db_pool.get_ref(), user.id).await?;
+    let client = ClientInfo::from_request(&req);
+    audit::add_moderation_event(db_pool.get_ref(), user.id, auth_user.id, AuditEvent::AccountUnsuspended, &client, None).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Deletes (anonymizes) the account of the user
+#[delete("/users/{user_id}")]
+pub async fn delete_user(
+    req: HttpRequest,
+    auth_user: AuthUser,
+    path: Path<i64>,
+    db_pool: web::Data<PgPool>,
+    chat_server: web::Data<Addr<ChatServer>>,
+) -> Result<HttpResponse, Error> {
+    let user = get_moderated_user(db_pool.get_ref(), &auth_user, path.into_inner()).await?;
+
+    let user_ids = users::anonymize_user(db_pool.get_ref(), user.id, &users::unusable_password_hash()).await?;
+    let client = ClientInfo::from_request(&req);
+    audit::add_moderation_event(db_pool.get_ref(), user.id, auth_user.id, AuditEvent::AccountDeleted, &client, None).await?;
+
+    // The bots of the user got deleted too
+    for user_id in user_ids {
+        chat_server.do_send(RevokeSessions {
+            user_id,
+            credentials: None,
+            reason: "Account deleted".into(),
+        });
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Sets the role of the user
+#[put("/users/{user_id}/role")]
+pub async fn set_user_role(
+    req: HttpRequest,
+    auth_user: AuthUser,
+    path: Path<i64>,
+    request: web::Json<SetRole>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, Error> {
+    let user = get_moderated_user(db_pool.get_ref(), &auth_user, path.into_inner()).await?;
+
+    users::set_user_role(db_pool.get_ref(), user.id, request.role).await?;
+    audit::add_moderation_event(
+        db_pool.get_ref(),
+        user.id,
+        auth_user.id,
+        AuditEvent::RoleChanged,
+        &ClientInfo::from_request(&req),
+        Some(request.role.as_str()),
+    ).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Gets the conversation with all the messages, to review the reported chats.
+/// The chats that were never reported stay private, even to the moderators.
+#[get("/chats/{chat_id}")]
+pub async fn inspect_chat(
+    path: Path<i64>,
+    db_pool: web::Data<PgPool>,
+    cipher: web::Data<MessageCipher>,
+) -> Result<HttpResponse, Error> {
+    let chat_id = path.into_inner();
+    if !reports::is_chat_reported(db_pool.get_ref(), chat_id).await? {
+        return Err(ApiError::NotFound.into());
+    }
+    let chat = chat::get_chat_by_id(db_pool.get_ref(), chat_id)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    let mut participants = Vec::new();
+    for user_id in [chat.user1_id, chat.user2_id] {
+        if let Some(user) = users::get_user_by_id(db_pool.get_ref(), user_id).await? {
+            participants.push(UserDetails::from_user(user));
+        }
+    }
+
+    let messages = chat::get_messages(db_pool.get_ref(), cipher.get_ref(), chat_id).await?
+        .into_iter()
+        .map(MessageDetails::from_message)
+        .collect();
+
+    Ok(HttpResponse::Ok().json(ChatInspection {
+        id: chat.id,
+        created_at: chat.created_at.assume_utc().unix_timestamp(),
+        participants,
+        messages,
+    }))
+}
+
+/// Gets the stored counters and the live counters of the chat server
+#[get("/stats")]
+pub async fn get_stats(
+    db_pool: web::Data<PgPool>,
+    chat_server: web::Data<Addr<ChatServer>>,
+) -> Result<HttpResponse, Error> {
+
+    let users = users::get_user_stats(db_pool.get_ref()).await?;
+    let chats = chat::get_chat_stats(db_pool.get_ref()).await?;
+    let server = chat_server.send(GetServerStats)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(AdminStats { users, chats, server }))
+}
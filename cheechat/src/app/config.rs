@@ -1,9 +1,493 @@
-use confik::Configuration;
+use std::error::Error;
+use std::path::Path;
 
-#[derive(Debug, Default, Configuration)]
+use confik::{ConfigurationBuilder, Configuration, EnvSource, FileSource, Source};
+use serde::{Serialize, Serializer};
+
+/// The configuration file read when none is given, if it exists.
+pub const DEFAULT_CONFIG_FILE: &str = "cheechat.toml";
+
+const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, Default, Configuration, Serialize)]
 pub struct AppConfig {
     pub app_name: String,
     pub server_addr: String,
+    #[serde(serialize_with = "redact_url")]
     pub database_url: String,
+    #[serde(serialize_with = "redact_url")]
     pub redis_addr: String,
-}
\ No newline at end of file
+    /// The settings of the http server: workers, timeouts, limits and tls.
+    #[confik(default)]
+    pub server: ServerConfig,
+    /// The connection pool of the database.
+    #[confik(default)]
+    pub database: DatabaseConfig,
+    /// The origins the browsers may call the API from.
+    #[confik(default)]
+    pub cors: CorsConfig,
+    /// Whether the pending database migrations are applied on startup.
+    #[confik(default = true)]
+    pub auto_migrate: bool,
+    /// The content filters applied to the chat messages before they get stored and broadcast.
+    #[confik(default)]
+    pub filters: FilterConfig,
+    /// The server wide retention policy of the chat messages.
+    #[confik(default)]
+    pub retention: RetentionConfig,
+    /// The master keys of the encryption of the messages at rest.
+    #[confik(default)]
+    pub encryption: EncryptionConfig,
+    /// The key and the cookie settings of the login sessions.
+    #[confik(default)]
+    pub session: SessionConfig,
+    /// The format of the logs and the export of the spans.
+    #[confik(default)]
+    pub logging: LoggingConfig,
+    /// The endpoints of the internal counters of the server.
+    #[confik(default)]
+    pub monitoring: MonitoringConfig,
+}
+
+impl AppConfig {
+    /// Loads the configuration from the layers, each one overriding the previous ones: the defaults,
+    /// the TOML file, the environment variables and the overrides of the command line, then validates it.
+    pub fn load(file: Option<&Path>, overrides: serde_json::Value) -> Result<Self, String> {
+        let default_file = Path::new(DEFAULT_CONFIG_FILE);
+        let file = file.or_else(|| default_file.exists().then_some(default_file));
+
+        let mut builder = Self::builder();
+        if let Some(file) = file {
+            builder.override_with(FileSource::new(file));
+        }
+        let config = builder
+            .override_with(EnvSource::new())
+            .override_with(OverrideSource(overrides))
+            .try_build()
+            .map_err(|err| error_chain(&err))?;
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the settings beyond their types, e.g. the ranges and the combinations.
+    pub fn validate(&self) -> Result<(), String> {
+        self.server.validate()?;
+        self.database.validate()?;
+        self.cors.validate()?;
+        self.logging.validate()
+    }
+}
+
+#[derive(Debug, Configuration, Serialize)]
+pub struct ServerConfig {
+    /// The number of worker threads, defaults to the number of physical cores.
+    pub workers: Option<usize>,
+    #[confik(default = 5u64)]
+    pub keep_alive_secs: u64,
+    /// How long a client has to send the request headers.
+    #[confik(default = 5u64)]
+    pub client_request_timeout_secs: u64,
+    /// How long the shutdown waits for the in-flight requests and the pending chat messages.
+    #[confik(default = 30u64)]
+    pub shutdown_timeout_secs: u64,
+    /// The maximum size of the request bodies, e.g. the json payloads.
+    #[confik(default = 262_144usize)]
+    pub max_body_bytes: usize,
+    /// The maximum size of the websocket frames, the connections sending bigger ones get closed.
+    #[confik(default = 65_536usize)]
+    pub max_frame_bytes: usize,
+    /// Serves https when both the certificate chain and the private key are set.
+    #[confik(default)]
+    pub tls: TlsConfig,
+    /// The IP addresses of the reverse proxies whose `X-Forwarded-For` header is trusted.
+    /// None trusts the address of the connection only.
+    #[confik(default)]
+    pub trusted_proxies: Vec<String>,
+}
+
+impl ServerConfig {
+    fn validate(&self) -> Result<(), String> {
+        if self.workers == Some(0) {
+            return Err("server.workers must be positive".into());
+        }
+        if self.max_body_bytes == 0 || self.max_frame_bytes == 0 {
+            return Err("server.max_body_bytes and server.max_frame_bytes must be positive".into());
+        }
+        if self.tls.cert_path.is_some() != self.tls.key_path.is_some() {
+            return Err("server.tls needs both cert_path and key_path".into());
+        }
+        if let Some(proxy) = self.trusted_proxies.iter().find(|proxy| proxy.parse::<std::net::IpAddr>().is_err()) {
+            return Err(format!("server.trusted_proxies: {proxy} is not an IP address"));
+        }
+        Ok(())
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            workers: None,
+            keep_alive_secs: 5,
+            client_request_timeout_secs: 5,
+            shutdown_timeout_secs: 30,
+            max_body_bytes: 262_144,
+            max_frame_bytes: 65_536,
+            tls: TlsConfig::default(),
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Configuration, Serialize)]
+pub struct TlsConfig {
+    /// The PEM file with the certificate chain.
+    pub cert_path: Option<String>,
+    /// The PEM file with the private key.
+    pub key_path: Option<String>,
+}
+
+#[derive(Debug, Configuration, Serialize)]
+pub struct DatabaseConfig {
+    #[confik(default = 10u32)]
+    pub max_connections: u32,
+    #[confik(default = 0u32)]
+    pub min_connections: u32,
+    /// How long a query waits for a free connection before failing.
+    #[confik(default = 30u64)]
+    pub acquire_timeout_secs: u64,
+    /// How long an unused connection stays open, zero keeps them open.
+    #[confik(default = 600u64)]
+    pub idle_timeout_secs: u64,
+}
+
+impl DatabaseConfig {
+    fn validate(&self) -> Result<(), String> {
+        if self.max_connections == 0 || self.min_connections > self.max_connections {
+            return Err("database.max_connections must be positive and at least database.min_connections".into());
+        }
+        if self.acquire_timeout_secs == 0 {
+            return Err("database.acquire_timeout_secs must be positive".into());
+        }
+        Ok(())
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout_secs: 30,
+            idle_timeout_secs: 600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Configuration, Serialize)]
+pub struct CorsConfig {
+    /// The allowed origins, e.g. `https://chat.example.com`, none allows only the requests from the same origin.
+    /// Any origin (`*`) is refused, the browsers send the session cookie along with the requests.
+    #[confik(default)]
+    pub allowed_origins: Vec<String>,
+    /// How long the browsers cache the preflight responses.
+    #[confik(default = 3600usize)]
+    pub max_age_secs: usize,
+}
+
+impl CorsConfig {
+    fn validate(&self) -> Result<(), String> {
+        for origin in &self.allowed_origins {
+            if origin == "*" {
+                return Err("cors.allowed_origins: * is not allowed, the requests carry the session cookie".into());
+            }
+            let valid = origin.parse::<actix_web::http::Uri>()
+                .is_ok_and(|uri| matches!(uri.scheme_str(), Some("http" | "https")) && uri.host().is_some()
+                    && matches!(uri.path(), "" | "/") && uri.query().is_none())
+                && !origin.ends_with('/');
+            if !valid {
+                return Err(format!("cors.allowed_origins: {origin} is not an origin like https://example.com"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            max_age_secs: 3600,
+        }
+    }
+}
+
+#[derive(Debug, Configuration, Serialize)]
+pub struct LoggingConfig {
+    /// Either json, one object per line, or text.
+    #[confik(default = "json")]
+    pub format: String,
+    /// The levels by target, e.g. `info,sqlx=warn`. `RUST_LOG` takes precedence.
+    #[confik(default = "info")]
+    pub filter: String,
+    /// The OpenTelemetry collector the spans get exported to over OTLP/gRPC, e.g. `http://localhost:4317`.
+    pub otlp_endpoint: Option<String>,
+    #[confik(default = "cheechat")]
+    pub service_name: String,
+}
+
+impl LoggingConfig {
+    fn validate(&self) -> Result<(), String> {
+        if !matches!(self.format.as_str(), "json" | "text") {
+            return Err(format!("logging.format must be json or text, not {}", self.format));
+        }
+        Ok(())
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            format: "json".into(),
+            filter: "info".into(),
+            otlp_endpoint: None,
+            service_name: "cheechat".into(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Configuration, Serialize)]
+pub struct MonitoringConfig {
+    /// Serves `/metrics` and `/status` without a session, keep them off the public network.
+    #[confik(default = false)]
+    pub enabled: bool,
+}
+
+#[derive(Debug, Configuration, Serialize)]
+pub struct FilterConfig {
+    /// The maximum number of characters of a message, zero disables the check.
+    #[confik(default = 4000usize)]
+    pub max_length: usize,
+    /// The words that get masked, matched as whole words regardless of the case.
+    #[confik(default)]
+    pub profanity_words: Vec<String>,
+    /// A file with additional profanity words, one per line.
+    pub profanity_words_file: Option<String>,
+    /// Whether the messages with profanity get rejected instead of masked.
+    #[confik(default = false)]
+    pub reject_profanity: bool,
+    /// Whether the messages with links get rejected, unless the link points to an allowed domain.
+    #[confik(default = false)]
+    pub block_links: bool,
+    /// The domains (and their subdomains) that the links may point to when they are blocked.
+    #[confik(default)]
+    pub allowed_link_domains: Vec<String>,
+    /// How many times in a row a user may send the same message within the spam window, zero disables the check.
+    #[confik(default = 3usize)]
+    pub spam_max_repeats: usize,
+    #[confik(default = 30u64)]
+    pub spam_window_secs: u64,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        Self {
+            max_length: 4000,
+            profanity_words: Vec::new(),
+            profanity_words_file: None,
+            reject_profanity: false,
+            block_links: false,
+            allowed_link_domains: Vec::new(),
+            spam_max_repeats: 3,
+            spam_window_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Configuration, Serialize)]
+pub struct RetentionConfig {
+    /// The maximum age of the messages in days, the older ones and their copies get deleted in every chat.
+    /// Zero keeps them forever.
+    #[confik(default = 0u64)]
+    pub max_message_age_days: u64,
+    /// How often the expired messages get deleted.
+    #[confik(default = 60u64)]
+    pub sweep_interval_secs: u64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            max_message_age_days: 0,
+            sweep_interval_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Default, Configuration, Serialize)]
+pub struct EncryptionConfig {
+    /// The id of the master key the new messages get encrypted with, none stores them in plain text.
+    pub active_key_id: Option<String>,
+    /// The master keys formatted as `id:hex`, 32 bytes each. The previous keys stay listed until
+    /// the messages got re-encrypted with the active one.
+    #[confik(default)]
+    #[serde(serialize_with = "redact_master_keys")]
+    pub keys: Vec<String>,
+    /// A directory with more master keys, one `{id}.key` file per key containing the hex encoded key.
+    pub keys_dir: Option<String>,
+}
+
+#[derive(Debug, Configuration, Serialize)]
+pub struct SessionConfig {
+    /// The hex encoded key (64 bytes) the session cookies get encrypted with, see `cheechat generate-session-key`.
+    /// Without it a random key is generated on every start, so the restarts log everybody out.
+    #[serde(serialize_with = "redact_secret")]
+    pub key: Option<String>,
+    /// The keys used before the current one, their cookies are still accepted and get re-encrypted with the current key.
+    #[confik(default)]
+    #[serde(serialize_with = "redact_secrets")]
+    pub previous_keys: Vec<String>,
+    #[confik(default = "id")]
+    pub cookie_name: String,
+    /// Whether the cookie is only sent over https.
+    #[confik(default = true)]
+    pub cookie_secure: bool,
+    /// One of strict, lax or none, none requires a secure cookie.
+    #[confik(default = "lax")]
+    pub cookie_same_site: String,
+    pub cookie_domain: Option<String>,
+    /// How long the cookie and the session state live, none ends the session when the browser gets closed.
+    pub ttl_secs: Option<i64>,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            key: None,
+            previous_keys: Vec::new(),
+            cookie_name: "id".into(),
+            cookie_secure: true,
+            cookie_same_site: "lax".into(),
+            cookie_domain: None,
+            ttl_secs: None,
+        }
+    }
+}
+
+/// The settings given on the command line, as a json object shaped like the configuration.
+#[derive(Debug)]
+struct OverrideSource(serde_json::Value);
+
+impl Source for OverrideSource {
+    fn allows_secrets(&self) -> bool {
+        true
+    }
+
+    fn provide<T: ConfigurationBuilder>(&self) -> Result<T, Box<dyn Error + Sync + Send>> {
+        Ok(serde_json::from_value(self.0.clone())?)
+    }
+}
+
+/// Formats the error with its causes, the errors of confik only name the failed source.
+fn error_chain(err: &dyn Error) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        message.push_str(&format!(": {err}"));
+        source = err.source();
+    }
+    message
+}
+
+/// Hides the password of the connection url.
+fn redact_url<S: Serializer>(url: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    let redacted = url.split_once("://")
+        .and_then(|(scheme, rest)| {
+            let (user_info, host) = rest.rsplit_once('@')?;
+            let (user, _) = user_info.split_once(':')?;
+            Some(format!("{scheme}://{user}:{REDACTED}@{host}"))
+        });
+    serializer.serialize_str(redacted.as_deref().unwrap_or(url))
+}
+
+fn redact_secret<S: Serializer>(secret: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    match secret {
+        Some(_) => serializer.serialize_some(REDACTED),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn redact_secrets<S: Serializer>(secrets: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(secrets.iter().map(|_| REDACTED))
+}
+
+/// Hides the master keys but keeps their ids.
+fn redact_master_keys<S: Serializer>(keys: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(keys.iter().map(|key| match key.split_once(':') {
+        Some((id, _)) => format!("{id}:{REDACTED}"),
+        None => REDACTED.to_owned(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redacted(url: &str) -> String {
+        redact_url(url, serde_json::value::Serializer).unwrap().as_str().unwrap().to_owned()
+    }
+
+    fn cors(origin: &str) -> Result<(), String> {
+        CorsConfig { allowed_origins: vec![origin.into()], ..CorsConfig::default() }.validate()
+    }
+
+    #[test]
+    fn the_defaults_are_valid() {
+        assert_eq!(AppConfig::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn redacts_the_password_of_the_urls() {
+        assert_eq!(redacted("postgres://chat:s3cr@t@db:5432/chat"), "postgres://chat:<redacted>@db:5432/chat");
+        assert_eq!(redacted("redis://:s3cret@cache:6379"), "redis://:<redacted>@cache:6379");
+        assert_eq!(redacted("postgres://chat@db/chat"), "postgres://chat@db/chat");
+        assert_eq!(redacted("redis://cache:6379"), "redis://cache:6379");
+    }
+
+    #[test]
+    fn cors_accepts_the_origins_only() {
+        assert_eq!(cors("https://chat.example.com"), Ok(()));
+        assert_eq!(cors("http://localhost:3000"), Ok(()));
+        for origin in ["*", "chat.example.com", "ftp://example.com", "https://example.com/", "https://example.com/app"] {
+            assert!(cors(origin).is_err(), "{origin} was accepted");
+        }
+    }
+
+    #[test]
+    fn server_needs_the_whole_tls_pair_and_ip_proxies() {
+        let mut server = ServerConfig::default();
+        server.tls.cert_path = Some("cert.pem".into());
+        assert!(server.validate().is_err());
+        server.tls.key_path = Some("key.pem".into());
+        assert_eq!(server.validate(), Ok(()));
+
+        server.trusted_proxies = vec!["proxy.local".into()];
+        assert!(server.validate().is_err());
+        server.trusted_proxies = vec!["10.0.0.2".into()];
+        server.workers = Some(0);
+        assert!(server.validate().is_err());
+    }
+
+    #[test]
+    fn database_pool_bounds_must_be_ordered() {
+        assert!(DatabaseConfig { max_connections: 0, ..DatabaseConfig::default() }.validate().is_err());
+        assert!(DatabaseConfig { min_connections: 11, ..DatabaseConfig::default() }.validate().is_err());
+        assert!(DatabaseConfig { acquire_timeout_secs: 0, ..DatabaseConfig::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn logging_format_is_json_or_text() {
+        assert_eq!(LoggingConfig { format: "text".into(), ..LoggingConfig::default() }.validate(), Ok(()));
+        assert!(LoggingConfig { format: "xml".into(), ..LoggingConfig::default() }.validate().is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashSet;
+
+use derive_more::{Display, Error, From};
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use sqlx::postgres::PgPool;
+
+/// The versioned migrations of the database schema, embedded in the binary.
+pub static MIGRATOR: Migrator = sqlx::migrate!();
+
+#[derive(Debug)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+}
+
+/// The state of the database schema compared to the migrations of the binary.
+#[derive(Debug)]
+pub struct SchemaStatus {
+    pub migrations: Vec<MigrationStatus>,
+    /// The applied versions that this binary doesn't know, the schema is newer than the binary.
+    pub unknown_versions: Vec<i64>,
+}
+
+impl SchemaStatus {
+    pub fn pending(&self) -> usize {
+        self.migrations.iter().filter(|migration| !migration.applied).count()
+    }
+
+    pub fn is_up_to_date(&self) -> bool {
+        self.pending() == 0 && self.unknown_versions.is_empty()
+    }
+}
+
+#[derive(Debug, Display, Error, From)]
+pub enum SchemaError {
+    #[display(fmt = "Migration error: {}", _0)]
+    Migrate(MigrateError),
+    #[display(fmt = "The database schema is newer than the binary, unknown migrations: {:?}", _0)]
+    #[from(ignore)]
+    NewerThanBinary(#[error(not(source))] Vec<i64>),
+    #[display(fmt = "There are {} pending migrations, run `cheechat migrate` or enable auto_migrate", _0)]
+    #[from(ignore)]
+    Pending(#[error(not(source))] usize),
+}
+
+/// Compares the applied migrations of the database with the embedded ones.
+pub async fn status(pool: &PgPool) -> Result<SchemaStatus, MigrateError> {
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table().await?;
+
+    let applied: HashSet<i64> = conn.list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|migration| migration.version)
+        .collect();
+
+    let migrations: Vec<_> = MIGRATOR.iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| MigrationStatus {
+            version: migration.version,
+            description: migration.description.to_string(),
+            applied: applied.contains(&migration.version),
+        })
+        .collect();
+
+    let mut unknown_versions: Vec<i64> = applied.into_iter()
+        .filter(|version| !MIGRATOR.version_exists(*version))
+        .collect();
+    unknown_versions.sort_unstable();
+
+    Ok(SchemaStatus { migrations, unknown_versions })
+}
+
+/// Applies the pending migrations.
+pub async fn run(pool: &PgPool) -> Result<(), SchemaError> {
+    let status = status(pool).await?;
+    if !status.unknown_versions.is_empty() {
+        return Err(SchemaError::NewerThanBinary(status.unknown_versions));
+    }
+
+    MIGRATOR.run(pool).await?;
+    Ok(())
+}
+
+/// Makes sure the server can run against the database schema:
+///
+/// - Refuses to run against a schema newer than the binary
+/// - Applies the pending migrations if allowed, otherwise refuses to run
+pub async fn ensure_compatible(pool: &PgPool, auto_migrate: bool) -> Result<(), SchemaError> {
+    let status = status(pool).await?;
+
+    if !status.unknown_versions.is_empty() {
+        return Err(SchemaError::NewerThanBinary(status.unknown_versions));
+    }
+    match status.pending() {
+        0 => Ok(()),
+        _ if auto_migrate => run(pool).await,
+        pending => Err(SchemaError::Pending(pending)),
+    }
+}
@@ -1,59 +1,183 @@
-use crate::app::config::AppConfig;
-use crate::users::authenticate_user;
-use crate::{chat, users};
+use crate::app::config::{AppConfig, CorsConfig};
+use crate::users::AuthUser;
+use crate::{admin, audit, bots, chat, docs, e2ee, health, metrics, reports, scheduled, users, webhooks};
 use actix::Actor;
 use actix_cors::Cors;
 use actix_session::storage::RedisSessionStore;
-use actix_session::{Session, SessionMiddleware};
+use actix_web::middleware::from_fn;
 use actix_web::{get, web, App, Error, HttpResponse, HttpServer};
 use serde::{Deserialize, Serialize};
-use sqlx::postgres::PgPoolOptions;
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing_actix_web::TracingLogger;
 
 pub mod config;
+pub mod migrations;
+pub mod session;
+pub mod shutdown;
+pub mod telemetry;
+pub mod tls;
+
+/// Creates the postgres db pool.
+pub async fn create_db_pool(config: &AppConfig) -> Result<PgPool, sqlx::Error> {
+    let database = &config.database;
+    PgPoolOptions::new()
+        .max_connections(database.max_connections)
+        .min_connections(database.min_connections)
+        .acquire_timeout(Duration::from_secs(database.acquire_timeout_secs))
+        .idle_timeout((database.idle_timeout_secs > 0).then(|| Duration::from_secs(database.idle_timeout_secs)))
+        .connect(&config.database_url)
+        .await
+}
 
 /// Initiates the server and run it.
 pub async fn run(config: AppConfig) -> std::io::Result<()> {
+    let uptime = health::Uptime::start();
+
     // Create the postgres db pool
-    let db_pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&config.database_url)
-        .await.unwrap();
+    let db_pool = create_db_pool(&config).await.map_err(std::io::Error::other)?;
+
+    // Refuse to run against an incompatible schema
+    migrations::ensure_compatible(&db_pool, config.auto_migrate)
+        .await
+        .map_err(std::io::Error::other)?;
+
+    // Create the redis session store, the cookies are encrypted with the configured key
+    let session_keys = session::SessionKeys::from_config(&config.session)?;
+    let store = RedisSessionStore::new(config.redis_addr.clone())
+        .await
+        .map_err(std::io::Error::other)?;
 
-    // Create the redis session store
-    let private_key = actix_web::cookie::Key::generate();
-    let store = RedisSessionStore::new(config.redis_addr)
+    // A separate redis connection for the readiness probe, the session store doesn't expose its own
+    let redis = redis::Client::open(config.redis_addr.as_str())
+        .map_err(std::io::Error::other)?
+        .get_connection_manager()
         .await
-        .unwrap();
+        .map_err(std::io::Error::other)?;
 
     // Create the chat server actor
-    let chat_server = chat::ChatServer::new(db_pool.clone()).start();
+    let filters = chat::FilterPipeline::from_config(&config.filters)?;
+    let cipher = Arc::new(chat::MessageCipher::from_config(&config.encryption)?);
+    let chat_server = chat::ChatServer::new(db_pool.clone(), filters, chat::CommandRegistry::default(), cipher.clone()).start();
+    let shutdown_chat_server = chat_server.clone();
+
+    // Start the webhook delivery worker, it lives as long as the server
+    let _webhook_worker = webhooks::WebhookWorker::new(db_pool.clone(), cipher.clone()).start();
+
+    // Start the sweeper of the expired messages
+    let _message_sweeper = chat::MessageSweeper::new(db_pool.clone(), chat_server.clone(), &config.retention).start();
+
+    // Start the scheduled message worker, it sends the due messages through the chat server
+    let _scheduled_worker = scheduled::ScheduledMessageWorker::new(db_pool.clone(), chat_server.clone(), cipher.clone()).start();
+
+    let tls_config = tls::load_server_config(&config.server.tls)?;
+    let cors_config = config.cors.clone();
+    let max_body_bytes = config.server.max_body_bytes;
+    let monitoring_enabled = config.monitoring.enabled;
+    let ws_config = chat::WebsocketConfig { max_frame_size: config.server.max_frame_bytes };
+    let trusted_proxies = users::TrustedProxies::from_config(&config.server.trusted_proxies)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err))?;
 
     let server = HttpServer::new(move || {
         App::new()
-            .wrap(Cors::permissive()) // Permissive cors for simplicity
-            .wrap(SessionMiddleware::builder(store.clone(), private_key.clone()).build())
+            .wrap(cors(&cors_config))
+            .wrap(session_keys.middleware(store.clone()))
+            .wrap(from_fn(session::rotate_session_cookie))
+            .wrap(from_fn(metrics::track_requests))
+            .wrap(from_fn(telemetry::request_id_header))
+            .wrap(TracingLogger::default())
             .app_data(web::Data::new(db_pool.clone()))
             .app_data(web::Data::new(chat_server.clone()))
+            .app_data(web::Data::from(cipher.clone()))
+            .app_data(web::Data::new(session_keys.clone()))
+            .app_data(web::Data::new(redis.clone()))
+            .app_data(web::Data::new(uptime))
+            .app_data(web::Data::new(ws_config))
+            .app_data(web::Data::new(trusted_proxies.clone()))
+            .app_data(web::JsonConfig::default().limit(max_body_bytes))
+            .app_data(web::PayloadConfig::new(max_body_bytes))
             .configure(users::init_routes)
-            .configure(chat::init_routes)
-            .service(index)
+            .configure(health::init_routes)
+            .configure(|cfg| {
+                if monitoring_enabled {
+                    health::init_status_routes(cfg);
+                    metrics::init_routes(cfg);
+                }
+            })
+            .configure(docs::init_routes)
+            .service(
+                // Every route registered in this scope requires an authenticated user
+                web::scope("")
+                    .wrap(from_fn(users::require_auth))
+                    .service(
+                        web::scope("/admin")
+                            .wrap(from_fn(users::require_moderator))
+                            .configure(admin::init_routes)
+                            .configure(reports::init_moderation_routes)
+                            .service(
+                                // Registered last, so the moderation routes above match first
+                                web::scope("")
+                                    .wrap(from_fn(users::require_admin))
+                                    .configure(admin::init_admin_routes)
+                            )
+                    )
+                    .configure(users::init_protected_routes)
+                    .configure(audit::init_routes)
+                    .configure(chat::init_routes)
+                    .configure(reports::init_routes)
+                    .configure(webhooks::init_routes)
+                    .configure(bots::init_routes)
+                    .configure(scheduled::init_routes)
+                    .configure(e2ee::init_routes)
+                    .service(index)
+            )
     })
-        .bind(config.server_addr.clone())?
+        .keep_alive(Duration::from_secs(config.server.keep_alive_secs))
+        .client_request_timeout(Duration::from_secs(config.server.client_request_timeout_secs))
+        .shutdown_timeout(config.server.shutdown_timeout_secs)
+        // The signals are handled below, so the websocket sessions get closed before the workers stop
+        .disable_signals();
+    let server = match config.server.workers {
+        Some(workers) => server.workers(workers),
+        None => server,
+    };
+
+    let scheme = if tls_config.is_some() { "https" } else { "http" };
+    let server = match tls_config {
+        Some(tls_config) => server.bind_rustls_0_23(config.server_addr.clone(), tls_config)?,
+        None => server.bind(config.server_addr.clone())?,
+    }
         .run();
-    println!("Server running at http://{}/", config.server_addr);
+    tracing::info!("Server running at {scheme}://{}/", config.server_addr);
+
+    let deadline = Duration::from_secs(config.server.shutdown_timeout_secs);
+    actix_web::rt::spawn(shutdown::on_signal(server.handle(), shutdown_chat_server, deadline));
 
     server.await
 }
 
+/// Builds the cors middleware, the browsers send the session cookie to the allowed origins.
+fn cors(config: &CorsConfig) -> Cors {
+    let mut cors = Cors::default()
+        .allow_any_method()
+        .allow_any_header()
+        .supports_credentials()
+        .max_age(config.max_age_secs);
+    for origin in &config.allowed_origins {
+        cors = cors.allowed_origin(origin);
+    }
+    cors
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct IndexResponse {
     user_id: i64,
 }
 
 #[get("/")]
-pub async fn index(session: Session) -> actix_web::Result<HttpResponse, Error> {
-    let user_id = authenticate_user(session).await?;
-    Ok(HttpResponse::Ok().json(IndexResponse { user_id}))
+pub async fn index(auth_user: AuthUser) -> actix_web::Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(IndexResponse { user_id: auth_user.id }))
 }
 
 
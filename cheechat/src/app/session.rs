@@ -0,0 +1,173 @@
+use std::io;
+
+use actix_session::config::{BrowserSession, PersistentSession};
+use actix_session::storage::RedisSessionStore;
+use actix_session::SessionMiddleware;
+use actix_web::body::MessageBody;
+use actix_web::cookie::time::Duration;
+use actix_web::cookie::{Cookie, CookieJar, Key, SameSite};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{web, Error};
+
+use crate::app::config::SessionConfig;
+
+/// The keys of the session cookies along with their settings.
+///
+/// The cookies are encrypted with the current key. The ones still encrypted with a previous key
+/// are re-encrypted on the fly by [`rotate_session_cookie`], so rotating the key doesn't log anybody out.
+#[derive(Clone)]
+pub struct SessionKeys {
+    key: Key,
+    previous_keys: Vec<Key>,
+    cookie_name: String,
+    cookie_secure: bool,
+    cookie_same_site: SameSite,
+    cookie_domain: Option<String>,
+    ttl: Option<Duration>,
+}
+
+impl SessionKeys {
+    pub fn from_config(config: &SessionConfig) -> io::Result<Self> {
+        let key = match &config.key {
+            Some(key) => parse_key(key)?,
+            None => {
+                tracing::warn!("No session key is configured, the sessions won't survive a restart");
+                Key::generate()
+            }
+        };
+        let previous_keys = config.previous_keys.iter()
+            .map(|key| parse_key(key))
+            .collect::<io::Result<_>>()?;
+
+        let cookie_same_site = match config.cookie_same_site.to_ascii_lowercase().as_str() {
+            "strict" => SameSite::Strict,
+            "lax" => SameSite::Lax,
+            "none" if config.cookie_secure => SameSite::None,
+            "none" => return Err(invalid_config("SameSite=None requires a secure session cookie")),
+            other => return Err(invalid_config(&format!("Unknown SameSite value {other}"))),
+        };
+        let ttl = match config.ttl_secs {
+            Some(ttl_secs) if ttl_secs <= 0 => return Err(invalid_config("The session ttl must be positive")),
+            ttl_secs => ttl_secs.map(Duration::seconds),
+        };
+
+        Ok(Self {
+            key,
+            previous_keys,
+            cookie_name: config.cookie_name.clone(),
+            cookie_secure: config.cookie_secure,
+            cookie_same_site,
+            cookie_domain: config.cookie_domain.clone(),
+            ttl,
+        })
+    }
+
+    /// Generates a new hex encoded session key.
+    pub fn generate_key() -> String {
+        hex::encode(Key::generate().master())
+    }
+
+    /// Builds the session middleware with the current key and the cookie settings.
+    pub fn middleware(&self, store: RedisSessionStore) -> SessionMiddleware<RedisSessionStore> {
+        let builder = SessionMiddleware::builder(store, self.key.clone())
+            .cookie_name(self.cookie_name.clone())
+            .cookie_secure(self.cookie_secure)
+            .cookie_same_site(self.cookie_same_site)
+            .cookie_domain(self.cookie_domain.clone());
+
+        let builder = match self.ttl {
+            Some(ttl) => builder.session_lifecycle(PersistentSession::default().session_ttl(ttl)),
+            None => builder.session_lifecycle(BrowserSession::default()),
+        };
+        builder.build()
+    }
+
+    /// Re-encrypts the session cookie with the current key if it was encrypted with a previous one.
+    fn reseal(&self, cookie: Cookie<'static>) -> Option<Cookie<'static>> {
+        let jar = CookieJar::new();
+        if self.previous_keys.is_empty() || jar.private(&self.key).decrypt(cookie.clone()).is_some() {
+            return None;
+        }
+        let decrypted = self.previous_keys.iter()
+            .find_map(|key| jar.private(key).decrypt(cookie.clone()))?;
+
+        let mut jar = CookieJar::new();
+        jar.private_mut(&self.key).add(self.cookie(decrypted.value().to_owned()));
+        jar.get(&self.cookie_name).cloned()
+    }
+
+    fn cookie(&self, value: String) -> Cookie<'static> {
+        let mut cookie = Cookie::build(self.cookie_name.clone(), value)
+            .path("/")
+            .secure(self.cookie_secure)
+            .http_only(true)
+            .same_site(self.cookie_same_site)
+            .finish();
+        if let Some(domain) = &self.cookie_domain {
+            cookie.set_domain(domain.clone());
+        }
+        if let Some(ttl) = self.ttl {
+            cookie.set_max_age(ttl);
+        }
+        cookie
+    }
+}
+
+/// Middleware that re-encrypts the session cookies of the previous keys with the current one.
+///
+/// The request gets the re-encrypted cookie so the session middleware, wrapped inside it, accepts it,
+/// and the response sets it, unless the session middleware already set a new one.
+pub async fn rotate_session_cookie(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let keys = req
+        .app_data::<web::Data<SessionKeys>>()
+        .expect("The session keys are not registered as app data")
+        .clone();
+
+    // The cookie header is parsed by hand, the request caches the parsed cookies
+    let Some(resealed) = session_cookie(&req, &keys.cookie_name).and_then(|cookie| keys.reseal(cookie)) else {
+        return next.call(req).await;
+    };
+    let prefix = format!("{}=", keys.cookie_name);
+    let cookies: Vec<String> = req.headers()
+        .get_all(header::COOKIE)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';').map(str::trim))
+        .filter(|pair| !pair.is_empty() && !pair.starts_with(&prefix))
+        .map(str::to_owned)
+        .chain([format!("{}={}", resealed.name(), resealed.value())])
+        .collect();
+    if let Ok(value) = HeaderValue::from_str(&cookies.join("; ")) {
+        req.headers_mut().insert(header::COOKIE, value);
+    }
+
+    let mut res = next.call(req).await?;
+    let already_set = res.response().cookies().any(|cookie| cookie.name() == keys.cookie_name);
+    if !already_set {
+        res.response_mut().add_cookie(&resealed)?;
+    }
+
+    Ok(res)
+}
+
+fn session_cookie(req: &ServiceRequest, name: &str) -> Option<Cookie<'static>> {
+    req.headers()
+        .get_all(header::COOKIE)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';').map(str::trim))
+        .filter_map(|pair| Cookie::parse_encoded(pair.to_owned()).ok())
+        .find(|cookie| cookie.name() == name)
+}
+
+fn parse_key(key: &str) -> io::Result<Key> {
+    let bytes = hex::decode(key.trim()).map_err(|_| invalid_config("The session key must be hex encoded"))?;
+    Key::try_from(bytes.as_slice()).map_err(|_| invalid_config("The session key must be at least 64 bytes"))
+}
+
+fn invalid_config(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
@@ -0,0 +1,58 @@
+use std::future::{poll_fn, Future};
+use std::pin::pin;
+use std::task::Poll;
+use std::time::Duration;
+
+use actix::Addr;
+use actix_web::dev::ServerHandle;
+use actix_web::rt::signal;
+
+use crate::chat::{ChatServer, Shutdown};
+
+/// The close reason the websocket clients get, so they reconnect instead of reporting an error.
+const RESTART_REASON: &str = "server restarting";
+
+/// Waits for SIGINT, or SIGTERM on unix, then shuts the server down gracefully within the deadline:
+///
+/// - Stops accepting connections, the in-flight requests get completed
+/// - Closes the websocket sessions with a restart close frame
+/// - Waits for the chat messages being stored, and their webhook events
+pub async fn on_signal(server: ServerHandle, chat_server: Addr<ChatServer>, deadline: Duration) {
+    wait_for_signal().await;
+    tracing::info!("Shutting down, waiting up to {}s for the pending work", deadline.as_secs());
+
+    // The workers stop on their own once their connections are closed, or at the deadline
+    let stopped = server.stop(true);
+
+    let drained = chat_server.send(Shutdown { reason: RESTART_REASON.into() });
+    match actix_web::rt::time::timeout(deadline, drained).await {
+        Ok(Ok(())) => tracing::info!("The pending chat messages are stored"),
+        Ok(Err(err)) => tracing::error!("Error shutting down the chat server: {err}"),
+        Err(_) => tracing::warn!("The deadline passed before the pending chat messages were stored"),
+    }
+
+    stopped.await;
+}
+
+async fn wait_for_signal() {
+    let mut interrupt = pin!(signal::ctrl_c());
+
+    #[cfg(unix)]
+    let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())
+        .map_err(|err| tracing::error!("Error listening for SIGTERM: {err}"))
+        .ok();
+
+    poll_fn(|cx| {
+        if interrupt.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(());
+        }
+        #[cfg(unix)]
+        if let Some(terminate) = &mut terminate {
+            if terminate.poll_recv(cx).is_ready() {
+                return Poll::Ready(());
+            }
+        }
+        Poll::Pending
+    })
+        .await
+}
@@ -0,0 +1,114 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpMessage};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::{Config, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use tracing::Level;
+use tracing_actix_web::RequestId;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter, Layer};
+
+use crate::app::config::LoggingConfig;
+use crate::metrics;
+
+/// Keeps the OpenTelemetry exporter alive, the buffered spans get flushed when it is dropped.
+pub struct Telemetry {
+    tracer_provider: Option<TracerProvider>,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(tracer_provider) = self.tracer_provider.take() {
+            if let Err(err) = tracer_provider.shutdown() {
+                eprintln!("Error flushing the spans: {err}");
+            }
+        }
+    }
+}
+
+/// Sets up the logs, as JSON lines or as text, and the export of the spans to an OpenTelemetry collector.
+///
+/// `RUST_LOG` overrides the configured filter. The records of the `log` crate, e.g. of sqlx, are captured too.
+/// The spans of the database queries always reach the metrics, whatever the filter is.
+pub fn init(config: &LoggingConfig) -> Result<Telemetry, String> {
+    // The logs go to stderr, the output of the commands to stdout
+    let fmt_layer = match config.format.as_str() {
+        "text" => fmt::layer().with_writer(std::io::stderr).boxed(),
+        _ => fmt::layer()
+            .with_writer(std::io::stderr)
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+    };
+
+    let tracer_provider = match &config.otlp_endpoint {
+        Some(endpoint) => Some(otlp_tracer_provider(endpoint, &config.service_name)?),
+        None => None,
+    };
+    let otel_layer = match &tracer_provider {
+        Some(provider) => Some(tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer("cheechat"))
+            .with_filter(env_filter(config)?)),
+        None => None,
+    };
+    let query_layer = metrics::QueryLatencyLayer
+        .with_filter(Targets::new().with_target(metrics::QUERY_TARGET, Level::TRACE));
+
+    tracing_subscriber::registry()
+        .with(query_layer)
+        .with(fmt_layer.with_filter(env_filter(config)?))
+        .with(otel_layer)
+        .try_init()
+        .map_err(|err| format!("The logging is already set up: {err}"))?;
+
+    Ok(Telemetry { tracer_provider })
+}
+
+/// The filter of the logs and of the exported spans, each layer gets its own.
+fn env_filter(config: &LoggingConfig) -> Result<EnvFilter, String> {
+    EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(&config.filter))
+        .map_err(|err| format!("Invalid log filter: {err}"))
+}
+
+/// Middleware that returns the id of the request in the `x-request-id` header, so the clients can quote it
+/// when reporting an error, the logs of the request are tagged with it.
+///
+/// The id is generated by the [`tracing_actix_web::TracingLogger`] wrapped around it.
+pub async fn request_id_header(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let request_id = req.extensions().get::<RequestId>().copied();
+
+    let mut res = next.call(req).await?;
+    if let Some(value) = request_id.and_then(|id| HeaderValue::from_str(&id.to_string()).ok()) {
+        res.headers_mut().insert(HeaderName::from_static("x-request-id"), value);
+    }
+
+    Ok(res)
+}
+
+/// Exports the spans in batches over OTLP/gRPC, e.g. to a local collector at `http://localhost:4317`.
+fn otlp_tracer_provider(endpoint: &str, service_name: &str) -> Result<TracerProvider, String> {
+    let exporter = opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(endpoint);
+
+    // The actix runtimes are single threaded, the exporter gets a thread of its own
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .with_trace_config(Config::default()
+            .with_resource(Resource::new([KeyValue::new("service.name", service_name.to_owned())])))
+        .install_batch(runtime::TokioCurrentThread)
+        .map_err(|err| format!("Invalid OpenTelemetry exporter: {err}"))
+}
@@ -0,0 +1,37 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::Arc;
+
+use rustls::crypto::ring;
+use rustls::ServerConfig;
+
+use crate::app::config::TlsConfig;
+
+/// Loads the certificate chain and the private key, none if tls is not configured.
+pub fn load_server_config(config: &TlsConfig) -> io::Result<Option<ServerConfig>> {
+    let (Some(cert_path), Some(key_path)) = (&config.cert_path, &config.key_path) else {
+        return Ok(None);
+    };
+
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))
+        .collect::<io::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(invalid_pem(&format!("No certificate found in {cert_path}")));
+    }
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key_path)?))?
+        .ok_or_else(|| invalid_pem(&format!("No private key found in {key_path}")))?;
+
+    // The provider is set explicitly, so it doesn't depend on the features enabled by the other crates
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|err| invalid_pem(&err.to_string()))?;
+
+    Ok(Some(config))
+}
+
+fn invalid_pem(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
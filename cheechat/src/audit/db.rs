@@ -0,0 +1,72 @@
+use crate::audit::models::{AuditEvent, AuditLogEntry};
+use crate::errors::ApiError;
+use crate::users::ClientInfo;
+use sqlx::postgres::PgPool;
+use tracing::instrument;
+
+/// Writes the event of the user to the audit log.
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn add_event(
+    pool: &PgPool,
+    user_id: i64,
+    event: AuditEvent,
+    client: &ClientInfo,
+    details: Option<&str>,
+) -> Result<i64, ApiError> {
+    let row = sqlx::query!(
+        "INSERT INTO cheechat.audit_log (user_id, event, ip_address, user_agent, details) VALUES ($1, $2, $3, $4, $5) RETURNING id",
+        user_id,
+        event.as_str(),
+        client.ip_address,
+        client.user_agent,
+        details
+    )
+        .fetch_one(pool)
+        .await?;
+
+    Ok(row.id)
+}
+
+/// Writes the event of the user, caused by the given moderator or admin, to the audit log.
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id, actor_id = actor_id))]
+pub async fn add_moderation_event(
+    pool: &PgPool,
+    user_id: i64,
+    actor_id: i64,
+    event: AuditEvent,
+    client: &ClientInfo,
+    details: Option<&str>,
+) -> Result<i64, ApiError> {
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO cheechat.audit_log (user_id, actor_id, event, ip_address, user_agent, details)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id
+        "#,
+        user_id,
+        actor_id,
+        event.as_str(),
+        client.ip_address,
+        client.user_agent,
+        details
+    )
+        .fetch_one(pool)
+        .await?;
+
+    Ok(row.id)
+}
+
+/// Retrieves the recent audit log events of the user
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn get_recent_events(pool: &PgPool, user_id: i64, limit: i64) -> Result<Vec<AuditLogEntry>, ApiError> {
+    let events = sqlx::query_as!(
+        AuditLogEntry,
+        "SELECT * FROM cheechat.audit_log WHERE user_id = $1 ORDER BY created_at DESC LIMIT $2",
+        user_id,
+        limit
+    )
+        .fetch_all(pool)
+        .await?;
+
+    Ok(events)
+}
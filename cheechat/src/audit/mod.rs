@@ -0,0 +1,18 @@
+use actix_web::web;
+use utoipa::OpenApi;
+
+mod services;
+mod db;
+mod models;
+
+pub use db::*;
+pub use models::*;
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(services::get_security_events);
+}
+
+/// The OpenAPI description of the routes.
+#[derive(OpenApi)]
+#[openapi(paths(services::get_security_events))]
+pub struct ApiDoc;
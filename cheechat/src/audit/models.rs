@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+use sqlx;
+use sqlx::types::time::PrimitiveDateTime;
+use utoipa::{IntoParams, ToSchema};
+
+/// The security related events that get written to the audit log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditEvent {
+    Login,
+    LoginFailed,
+    AccountLocked,
+    Logout,
+    SessionRevoked,
+    PasswordChanged,
+    TwoFactorEnabled,
+    TwoFactorDisabled,
+    AccountSuspended,
+    AccountUnsuspended,
+    AccountDeleted,
+    RoleChanged,
+}
+
+impl AuditEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditEvent::Login => "login",
+            AuditEvent::LoginFailed => "login_failed",
+            AuditEvent::AccountLocked => "account_locked",
+            AuditEvent::Logout => "logout",
+            AuditEvent::SessionRevoked => "session_revoked",
+            AuditEvent::PasswordChanged => "password_changed",
+            AuditEvent::TwoFactorEnabled => "two_factor_enabled",
+            AuditEvent::TwoFactorDisabled => "two_factor_disabled",
+            AuditEvent::AccountSuspended => "account_suspended",
+            AuditEvent::AccountUnsuspended => "account_unsuspended",
+            AuditEvent::AccountDeleted => "account_deleted",
+            AuditEvent::RoleChanged => "role_changed",
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct AuditLogEntry {
+    pub id: i64,
+    pub user_id: i64,
+    pub event: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub details: Option<String>,
+    pub created_at: PrimitiveDateTime,
+    /// The moderator or admin that acted on the user, the client info is theirs.
+    pub actor_id: Option<i64>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct SecurityEvent {
+    pub id: i64,
+    pub event: String,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub details: Option<String>,
+    pub created_at: i64,
+}
+
+impl SecurityEvent {
+    pub fn from_entry(entry: AuditLogEntry) -> Self {
+        Self {
+            id: entry.id,
+            event: entry.event,
+            ip_address: entry.ip_address,
+            user_agent: entry.user_agent,
+            details: entry.details,
+            created_at: entry.created_at.assume_utc().unix_timestamp(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SecurityEventsQuery {
+    pub limit: Option<i64>,
+}
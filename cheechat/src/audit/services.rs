@@ -0,0 +1,30 @@
+use crate::audit::db::get_recent_events;
+use crate::audit::models::{SecurityEvent, SecurityEventsQuery};
+use crate::users::AuthUser;
+use actix_web::{get, web, Error, HttpResponse};
+use sqlx::postgres::PgPool;
+
+const DEFAULT_EVENTS_LIMIT: i64 = 50;
+const MAX_EVENTS_LIMIT: i64 = 200;
+
+/// Gets the recent security events of the current user
+#[utoipa::path(
+    tag = "users",
+    params(SecurityEventsQuery),
+    responses((status = 200, body = Vec<SecurityEvent>)),
+)]
+#[get("/users/me/security-events")]
+pub async fn get_security_events(
+    auth_user: AuthUser,
+    query_params: web::Query<SecurityEventsQuery>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, Error> {
+    let limit = query_params.limit
+        .unwrap_or(DEFAULT_EVENTS_LIMIT)
+        .clamp(1, MAX_EVENTS_LIMIT);
+
+    let events = get_recent_events(db_pool.get_ref(), auth_user.id, limit).await?;
+
+    let events: Vec<_> = events.into_iter().map(SecurityEvent::from_entry).collect();
+    Ok(HttpResponse::Ok().json(events))
+}
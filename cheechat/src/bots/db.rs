@@ -0,0 +1,60 @@
+use crate::errors::ApiError;
+use crate::users::User;
+use sqlx::postgres::PgPool;
+use tracing::instrument;
+
+/// Adds a bot user owned by the given user. The display name is stored as the first name.
+#[instrument(target = "cheechat::db", skip_all, fields(owner_id = owner_id))]
+pub async fn add_bot(
+    pool: &PgPool,
+    owner_id: i64,
+    username: &str,
+    display_name: &str,
+    unusable_password: &str,
+) -> Result<User, ApiError> {
+    let bot = sqlx::query_as!(
+        User,
+        r#"
+        INSERT INTO cheechat.users (username, password, first_name, last_name, email, is_bot, bot_owner_id)
+        VALUES ($1, $2, $3, '', '', TRUE, $4)
+        RETURNING *
+        "#,
+        username,
+        unusable_password,
+        display_name,
+        owner_id
+    )
+        .fetch_one(pool)
+        .await?;
+
+    Ok(bot)
+}
+
+/// Retrieves the bots of the user
+#[instrument(target = "cheechat::db", skip_all, fields(owner_id = owner_id))]
+pub async fn get_bots(pool: &PgPool, owner_id: i64) -> Result<Vec<User>, ApiError> {
+    let bots = sqlx::query_as!(
+        User,
+        "SELECT * FROM cheechat.users WHERE bot_owner_id = $1 AND is_bot AND deleted_at IS NULL ORDER BY id",
+        owner_id
+    )
+        .fetch_all(pool)
+        .await?;
+
+    Ok(bots)
+}
+
+/// Retrieves the bot of the user based on the id
+#[instrument(target = "cheechat::db", skip_all, fields(owner_id = owner_id, bot_id = bot_id))]
+pub async fn get_bot(pool: &PgPool, owner_id: i64, bot_id: i64) -> Result<Option<User>, ApiError> {
+    let bot = sqlx::query_as!(
+        User,
+        "SELECT * FROM cheechat.users WHERE id = $1 AND bot_owner_id = $2 AND is_bot AND deleted_at IS NULL",
+        bot_id,
+        owner_id
+    )
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(bot)
+}
@@ -0,0 +1,29 @@
+use actix_web::web;
+use utoipa::OpenApi;
+
+mod services;
+mod db;
+mod models;
+
+pub use db::*;
+pub use models::*;
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(services::create_bot);
+    cfg.service(services::get_bots);
+    cfg.service(services::regenerate_token);
+    cfg.service(services::delete_bot);
+}
+
+/// The OpenAPI description of the routes.
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        services::create_bot,
+        services::get_bots,
+        services::regenerate_token,
+        services::delete_bot,
+    ),
+    tags((name = "bots", description = "The bots of the users, they authenticate with their API token")),
+)]
+pub struct ApiDoc;
@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::users::User;
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct CreateBot {
+    pub username: String,
+    pub display_name: String,
+}
+
+impl CreateBot {
+    pub fn validate(&self) -> Result<(), String> {
+        let fields = [
+            ("username", &self.username, 50),
+            ("display_name", &self.display_name, 200),
+        ];
+        for (field, value, max_length) in fields {
+            if value.trim().is_empty() {
+                return Err(format!("The {field} can't be empty"));
+            }
+            if value.chars().count() > max_length {
+                return Err(format!("The {field} can't be longer than {max_length} characters"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct BotInfo {
+    pub id: i64,
+    pub username: String,
+    pub display_name: String,
+}
+
+impl BotInfo {
+    pub fn from_user(user: User) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            display_name: user.first_name,
+        }
+    }
+}
+
+/// The bot along with its API token, which is only shown once.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct BotToken {
+    #[serde(flatten)]
+    pub bot: BotInfo,
+    pub token: String,
+}
@@ -0,0 +1,128 @@
+use actix::Addr;
+use actix_web::web::Path;
+use actix_web::{delete, get, post, web, Error, HttpResponse};
+use sqlx::postgres::PgPool;
+
+use crate::bots::db;
+use crate::bots::models::{BotInfo, BotToken, CreateBot};
+use crate::chat::{ChatServer, RevokeSessions};
+use crate::errors::ApiError;
+use crate::users::{self, AuthUser, Credential};
+
+/// Creates a bot owned by the current user, the API token is only returned here
+#[utoipa::path(
+    tag = "bots",
+    request_body = CreateBot,
+    responses(
+        (status = 201, body = BotToken),
+        (status = 400, description = "Invalid or taken username", body = String, content_type = "text/plain"),
+        (status = 403, description = "Bots can't create bots"),
+    ),
+)]
+#[post("/bots")]
+pub async fn create_bot(
+    auth_user: AuthUser,
+    request: web::Json<CreateBot>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, Error> {
+    if auth_user.user.is_bot {
+        return Err(ApiError::Forbidden.into());
+    }
+    request.validate().map_err(ApiError::BadRequest)?;
+
+    let bot = db::add_bot(
+        db_pool.get_ref(),
+        auth_user.id,
+        request.username.trim(),
+        request.display_name.trim(),
+        &users::unusable_password_hash(),
+    )
+        .await
+        .map_err(|err| match err {
+            ApiError::DbError(sqlx::Error::Database(ref db_err)) if db_err.is_unique_violation() => {
+                ApiError::BadRequest("The username is already taken".into())
+            }
+            err => err,
+        })?;
+
+    let token = users::generate_api_token();
+    users::add_api_token(db_pool.get_ref(), bot.id, &users::hash_api_token(&token)).await?;
+
+    Ok(HttpResponse::Created().json(BotToken { bot: BotInfo::from_user(bot), token }))
+}
+
+/// Gets the bots of the current user
+#[utoipa::path(
+    tag = "bots",
+    responses((status = 200, body = Vec<BotInfo>)),
+)]
+#[get("/bots")]
+pub async fn get_bots(auth_user: AuthUser, db_pool: web::Data<PgPool>) -> Result<HttpResponse, Error> {
+    let bots = db::get_bots(db_pool.get_ref(), auth_user.id).await?;
+
+    let bots: Vec<_> = bots.into_iter().map(BotInfo::from_user).collect();
+    Ok(HttpResponse::Ok().json(bots))
+}
+
+/// Replaces the API token of the bot, the connections opened with the previous one get closed
+#[utoipa::path(
+    tag = "bots",
+    params(("bot_id" = i64, Path, description = "The id of the bot")),
+    responses(
+        (status = 200, body = BotToken),
+        (status = 404, description = "No such bot"),
+    ),
+)]
+#[post("/bots/{bot_id}/token")]
+pub async fn regenerate_token(
+    auth_user: AuthUser,
+    path: Path<i64>,
+    db_pool: web::Data<PgPool>,
+    chat_server: web::Data<Addr<ChatServer>>,
+) -> Result<HttpResponse, Error> {
+    let bot = db::get_bot(db_pool.get_ref(), auth_user.id, path.into_inner())
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    let revoked = users::revoke_api_tokens(db_pool.get_ref(), bot.id).await?;
+    chat_server.do_send(RevokeSessions {
+        user_id: bot.id,
+        credentials: Some(revoked.into_iter().map(Credential::ApiToken).collect()),
+        reason: "Token revoked".into(),
+    });
+
+    let token = users::generate_api_token();
+    users::add_api_token(db_pool.get_ref(), bot.id, &users::hash_api_token(&token)).await?;
+
+    Ok(HttpResponse::Ok().json(BotToken { bot: BotInfo::from_user(bot), token }))
+}
+
+/// Deletes the bot, its chats are kept like for the deleted users
+#[utoipa::path(
+    tag = "bots",
+    params(("bot_id" = i64, Path, description = "The id of the bot")),
+    responses(
+        (status = 204, description = "The bot is deleted"),
+        (status = 404, description = "No such bot"),
+    ),
+)]
+#[delete("/bots/{bot_id}")]
+pub async fn delete_bot(
+    auth_user: AuthUser,
+    path: Path<i64>,
+    db_pool: web::Data<PgPool>,
+    chat_server: web::Data<Addr<ChatServer>>,
+) -> Result<HttpResponse, Error> {
+    let bot = db::get_bot(db_pool.get_ref(), auth_user.id, path.into_inner())
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    users::anonymize_user(db_pool.get_ref(), bot.id, &users::unusable_password_hash()).await?;
+    chat_server.do_send(RevokeSessions {
+        user_id: bot.id,
+        credentials: None,
+        reason: "Bot deleted".into(),
+    });
+
+    Ok(HttpResponse::NoContent().finish())
+}
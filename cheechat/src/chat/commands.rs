@@ -0,0 +1,239 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use sqlx::PgPool;
+
+use crate::chat::db::clear_history;
+use crate::errors::ApiError;
+
+/// The details of the message that invoked a command.
+pub struct CommandContext<'a> {
+    pub chat_id: i64,
+    pub sender_id: i64,
+    pub sender_username: &'a str,
+    /// The text after the command name, trimmed.
+    pub args: &'a str,
+    pub db_pool: &'a Arc<PgPool>,
+}
+
+/// A task of a command that runs asynchronously, e.g. against the database.
+pub type CommandTask = Pin<Box<dyn Future<Output = Result<String, ApiError>>>>;
+
+/// What the chat server does with the message after the command ran.
+pub enum CommandOutcome {
+    /// The content is sent to the chat as a regular message.
+    Send(String),
+    /// The reply is sent only to the session that issued the command, nothing is stored.
+    Reply(String),
+    /// The task runs and its result is sent only to the session that issued the command.
+    Task(CommandTask),
+}
+
+/// A slash command that the users type in the chat, e.g. `/shrug`.
+pub trait SlashCommand {
+    /// The name of the command, without the slash.
+    fn name(&self) -> &'static str;
+
+    /// The usage shown in the help, e.g. `/me <action>`.
+    fn usage(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    fn execute(&self, context: &CommandContext<'_>) -> CommandOutcome;
+}
+
+/// The registry of the available slash commands.
+///
+/// The messages that start with a slash are commands, a double slash escapes the slash.
+pub struct CommandRegistry {
+    commands: Vec<Box<dyn SlashCommand>>,
+}
+
+impl Default for CommandRegistry {
+    /// The registry with the built-in commands.
+    fn default() -> Self {
+        Self { commands: Vec::new() }
+            .with(MeCommand)
+            .with(ShrugCommand)
+            .with(ClearHistoryCommand)
+    }
+}
+
+impl CommandRegistry {
+    /// Registers the command, replacing any command with the same name.
+    pub fn with(mut self, command: impl SlashCommand + 'static) -> Self {
+        self.commands.retain(|registered| registered.name() != command.name());
+        self.commands.push(Box::new(command));
+        self
+    }
+
+    /// Runs the command of the message if it is one, otherwise the message is sent as it is.
+    /// The args of the context get filled in from the content.
+    pub fn dispatch(&self, context: &CommandContext<'_>, content: &str) -> CommandOutcome {
+        if let Some(escaped) = content.strip_prefix("//") {
+            return CommandOutcome::Send(format!("/{escaped}"));
+        }
+        let Some(command_line) = content.strip_prefix('/') else {
+            return CommandOutcome::Send(content.to_owned());
+        };
+
+        let (name, args) = command_line
+            .split_once(char::is_whitespace)
+            .unwrap_or((command_line, ""));
+        if name == "help" {
+            return CommandOutcome::Reply(self.help());
+        }
+
+        match self.commands.iter().find(|command| command.name() == name) {
+            Some(command) => command.execute(&CommandContext { args: args.trim(), ..*context }),
+            None => CommandOutcome::Reply(format!("Unknown command /{name}, type /help for the available commands")),
+        }
+    }
+
+    fn help(&self) -> String {
+        let mut help = String::from("Available commands:");
+        for command in &self.commands {
+            help.push_str(&format!("\n{} - {}", command.usage(), command.description()));
+        }
+        help.push_str("\n/help - Shows this help");
+        help
+    }
+}
+
+/// `/me <action>` sends the action in the third person, e.g. `* alice waves`.
+pub struct MeCommand;
+
+impl SlashCommand for MeCommand {
+    fn name(&self) -> &'static str {
+        "me"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/me <action>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Sends the action in the third person"
+    }
+
+    fn execute(&self, context: &CommandContext<'_>) -> CommandOutcome {
+        if context.args.is_empty() {
+            return CommandOutcome::Reply(format!("Usage: {}", self.usage()));
+        }
+        CommandOutcome::Send(format!("* {} {}", context.sender_username, context.args))
+    }
+}
+
+/// `/shrug [message]` appends a shrug to the message.
+pub struct ShrugCommand;
+
+impl SlashCommand for ShrugCommand {
+    fn name(&self) -> &'static str {
+        "shrug"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/shrug [message]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Appends ¯\\_(ツ)_/¯ to the message"
+    }
+
+    fn execute(&self, context: &CommandContext<'_>) -> CommandOutcome {
+        match context.args {
+            "" => CommandOutcome::Send("¯\\_(ツ)_/¯".into()),
+            args => CommandOutcome::Send(format!("{args} ¯\\_(ツ)_/¯")),
+        }
+    }
+}
+
+/// `/clear-history` hides the current messages of the chat for the user, the other participant keeps them.
+pub struct ClearHistoryCommand;
+
+impl SlashCommand for ClearHistoryCommand {
+    fn name(&self) -> &'static str {
+        "clear-history"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/clear-history"
+    }
+
+    fn description(&self) -> &'static str {
+        "Clears the history of the chat for you"
+    }
+
+    fn execute(&self, context: &CommandContext<'_>) -> CommandOutcome {
+        let db_pool = context.db_pool.clone();
+        let (chat_id, sender_id) = (context.chat_id, context.sender_id);
+
+        CommandOutcome::Task(Box::pin(async move {
+            clear_history(&db_pool, chat_id, sender_id).await?;
+            Ok("The chat history was cleared for you, reconnect to refresh it".into())
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dispatch(content: &str) -> CommandOutcome {
+        let db_pool = Arc::new(PgPool::connect_lazy("postgres://localhost/cheechat").unwrap());
+        let context = CommandContext { chat_id: 1, sender_id: 2, sender_username: "alice", args: "", db_pool: &db_pool };
+        CommandRegistry::default().dispatch(&context, content)
+    }
+
+    fn sent(outcome: CommandOutcome) -> String {
+        match outcome {
+            CommandOutcome::Send(content) => content,
+            _ => panic!("The message was not sent"),
+        }
+    }
+
+    fn replied(outcome: CommandOutcome) -> String {
+        match outcome {
+            CommandOutcome::Reply(reply) => reply,
+            _ => panic!("The command didn't reply"),
+        }
+    }
+
+    #[actix_web::test]
+    async fn sends_the_regular_messages_as_they_are() {
+        assert_eq!(sent(dispatch("hello /me")), "hello /me");
+    }
+
+    #[actix_web::test]
+    async fn double_slash_escapes_the_command() {
+        assert_eq!(sent(dispatch("//me waves")), "/me waves");
+    }
+
+    #[actix_web::test]
+    async fn runs_the_command_with_the_trimmed_args() {
+        assert_eq!(sent(dispatch("/me   waves  ")), "* alice waves");
+        assert_eq!(sent(dispatch("/shrug")), "¯\\_(ツ)_/¯");
+        assert_eq!(sent(dispatch("/shrug\tfine")), "fine ¯\\_(ツ)_/¯");
+        assert_eq!(replied(dispatch("/me")), "Usage: /me <action>");
+    }
+
+    #[actix_web::test]
+    async fn replies_to_the_unknown_commands_and_the_help() {
+        assert!(replied(dispatch("/dance now")).starts_with("Unknown command /dance"));
+        let help = replied(dispatch("/help"));
+        assert!(help.contains("/me <action>") && help.contains("/clear-history") && help.ends_with("/help - Shows this help"));
+    }
+
+    #[actix_web::test]
+    async fn clear_history_runs_as_a_task() {
+        assert!(matches!(dispatch("/clear-history"), CommandOutcome::Task(_)));
+    }
+
+    #[test]
+    fn registering_a_command_replaces_the_one_with_the_same_name() {
+        let registry = CommandRegistry::default().with(ShrugCommand);
+        assert_eq!(registry.commands.len(), 3);
+        assert_eq!(registry.commands.last().map(|command| command.name()), Some("shrug"));
+    }
+}
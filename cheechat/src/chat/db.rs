@@ -1,9 +1,12 @@
-use crate::chat::models::{Chat, ChatMessage, ChatOverview};
+use crate::chat::encryption::{MessageCipher, SealContext, SealedMessage, SealedRow};
+use crate::chat::models::{Chat, ChatMessage, ChatOverview, ChatStats, ExpiredMessage, Expiry, PayloadType};
 use crate::errors::ApiError;
 use crate::users::UserInfo;
 use sqlx::postgres::PgPool;
+use tracing::instrument;
 
 /// Retrieves the chat for the provided users
+#[instrument(target = "cheechat::db", skip_all, fields(user1_id = user1_id, user2_id = user2_id))]
 pub async fn get_chat(pool: &PgPool, (user1_id, user2_id): (i64, i64)) -> Result<Option<Chat>, ApiError> {
     assert!(user1_id < user2_id);
 
@@ -14,7 +17,33 @@ pub async fn get_chat(pool: &PgPool, (user1_id, user2_id): (i64, i64)) -> Result
     Ok(chat)
 }
 
+/// Retrieves the chat based on the id
+#[instrument(target = "cheechat::db", skip_all, fields(chat_id = chat_id))]
+pub async fn get_chat_by_id(pool: &PgPool, chat_id: i64) -> Result<Option<Chat>, ApiError> {
+    let chat = sqlx::query_as!(Chat, "SELECT * FROM cheechat.chats WHERE id=$1", chat_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(chat)
+}
+
+/// Retrieves the chat based on the id, if the user participates in it
+#[instrument(target = "cheechat::db", skip_all, fields(chat_id = chat_id, user_id = user_id))]
+pub async fn get_participated_chat(pool: &PgPool, chat_id: i64, user_id: i64) -> Result<Option<Chat>, ApiError> {
+    let chat = sqlx::query_as!(
+        Chat,
+        "SELECT * FROM cheechat.chats WHERE id = $1 AND (user1_id = $2 OR user2_id = $2)",
+        chat_id,
+        user_id
+    )
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(chat)
+}
+
 /// Adds the chat for the provided users
+#[instrument(target = "cheechat::db", skip_all, fields(user1_id = user1_id, user2_id = user2_id))]
 pub async fn add_chat(pool: &PgPool, (user1_id, user2_id): (i64, i64)) -> Result<i64, ApiError> {
     assert!(user1_id < user2_id);
 
@@ -26,8 +55,15 @@ pub async fn add_chat(pool: &PgPool, (user1_id, user2_id): (i64, i64)) -> Result
     Ok(row.id)
 }
 
-/// Get the recent messages of the chat
-pub async fn get_recent_messages(pool: &PgPool, chat_id: i64, limit: i64) -> Result<Vec<ChatMessage>, ApiError> {
+/// Get the recent messages of the chat, as seen by the user (after the history was cleared)
+#[instrument(target = "cheechat::db", skip_all, fields(chat_id = chat_id, user_id = user_id))]
+pub async fn get_recent_messages(
+    pool: &PgPool,
+    cipher: &MessageCipher,
+    chat_id: i64,
+    user_id: i64,
+    limit: i64,
+) -> Result<Vec<ChatMessage>, ApiError> {
     let messages = sqlx::query_as!(
         ChatMessage,
         r#"
@@ -35,31 +71,175 @@ pub async fn get_recent_messages(pool: &PgPool, chat_id: i64, limit: i64) -> Res
 FROM (
     SELECT *
     FROM cheechat.chat_messages
-    WHERE chat_id = $1
+    WHERE chat_id = $1 AND created_at > COALESCE(
+        (SELECT cleared_at FROM cheechat.chat_history_clears WHERE chat_id = $1 AND user_id = $2),
+        '-infinity'
+    )
     ORDER BY created_at DESC
-    LIMIT $2
+    LIMIT $3
 ) AS latest_messages
 ORDER BY created_at ASC;
         "#,
         chat_id,
+        user_id,
+        limit
+    )
+        .fetch_all(pool)
+        .await?;
+
+    Ok(decrypt_messages(cipher, messages))
+}
+
+/// Clears the history of the chat for the user, the messages are kept for the other participant
+#[instrument(target = "cheechat::db", skip_all, fields(chat_id = chat_id, user_id = user_id))]
+pub async fn clear_history(pool: &PgPool, chat_id: i64, user_id: i64) -> Result<(), ApiError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO cheechat.chat_history_clears (chat_id, user_id) VALUES ($1, $2)
+        ON CONFLICT (chat_id, user_id) DO UPDATE SET cleared_at = CURRENT_TIMESTAMP
+        "#,
+        chat_id,
+        user_id
+    )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Get all the messages of the chat, oldest first.
+///
+/// The history cleared by the participants with `/clear-history` is included on purpose: this is the view of
+/// the moderators inspecting a reported chat and of the operators exporting it, the clears only hide the messages
+/// from the participant that issued them (see [`get_recent_messages`]).
+#[instrument(target = "cheechat::db", skip_all, fields(chat_id = chat_id))]
+pub async fn get_messages(pool: &PgPool, cipher: &MessageCipher, chat_id: i64) -> Result<Vec<ChatMessage>, ApiError> {
+    let messages = sqlx::query_as!(
+        ChatMessage,
+        "SELECT * FROM cheechat.chat_messages WHERE chat_id = $1 ORDER BY created_at ASC",
+        chat_id
+    )
+        .fetch_all(pool)
+        .await?;
+
+    Ok(decrypt_messages(cipher, messages))
+}
+
+/// Decrypts the bodies of the messages, the unreadable ones are left out, e.g. the ones wrapped with
+/// a retired master key, so a single row doesn't prevent reading the chat.
+fn decrypt_messages(cipher: &MessageCipher, messages: Vec<ChatMessage>) -> Vec<ChatMessage> {
+    messages.into_iter()
+        .filter_map(|mut message| {
+            cipher.decrypt_message(&mut message)
+                .map_err(|_| tracing::error!("Error decrypting the message {} of chat {}", message.id, message.chat_id))
+                .ok()
+                .map(|_| message)
+        })
+        .collect()
+}
+
+/// Retrieves the chat message based on the id
+#[instrument(target = "cheechat::db", skip_all, fields(message_id = message_id))]
+pub async fn get_message(pool: &PgPool, cipher: &MessageCipher, message_id: i64) -> Result<Option<ChatMessage>, ApiError> {
+    let mut message = sqlx::query_as!(ChatMessage, "SELECT * FROM cheechat.chat_messages WHERE id = $1", message_id)
+        .fetch_optional(pool)
+        .await?;
+
+    if let Some(message) = message.as_mut() {
+        cipher.decrypt_message(message)?;
+    }
+    Ok(message)
+}
+
+/// Deletes the chat message and returns whether it existed
+#[instrument(target = "cheechat::db", skip_all, fields(message_id = message_id))]
+pub async fn delete_message(pool: &PgPool, message_id: i64) -> Result<bool, ApiError> {
+    let row = sqlx::query!("DELETE FROM cheechat.chat_messages WHERE id = $1 RETURNING id", message_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.is_some())
+}
+
+/// Retrieves the plain text messages that are not encrypted with the given master key, after the given id
+#[instrument(target = "cheechat::db", skip_all, fields(after_id = after_id))]
+pub async fn get_messages_to_reseal(pool: &PgPool, key_id: &str, after_id: i64, limit: i64) -> Result<Vec<SealedRow>, ApiError> {
+    let messages = sqlx::query!(
+        r#"
+        SELECT id, chat_id, sender_id, message, key_id, data_key FROM cheechat.chat_messages
+        WHERE id > $2 AND payload_type = 'text' AND key_id IS DISTINCT FROM $1
+        ORDER BY id
+        LIMIT $3
+        "#,
+        key_id,
+        after_id,
         limit
     )
+        .map(|row| SealedRow {
+            id: row.id,
+            context: SealContext::ChatMessage { chat_id: row.chat_id, sender_id: row.sender_id },
+            sealed: SealedMessage { message: row.message, key_id: row.key_id, data_key: row.data_key },
+        })
         .fetch_all(pool)
         .await?;
 
     Ok(messages)
 }
 
+/// Replaces the stored body of the message, e.g. once re-encrypted with another master key
+#[instrument(target = "cheechat::db", skip_all, fields(message_id = message_id))]
+pub async fn update_sealed_message(pool: &PgPool, message_id: i64, sealed: &SealedMessage) -> Result<(), ApiError> {
+    sqlx::query!(
+        "UPDATE cheechat.chat_messages SET message = $2, key_id = $3, data_key = $4 WHERE id = $1",
+        message_id,
+        sealed.message,
+        sealed.key_id,
+        sealed.data_key
+    )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
 /// Adds chat message to the chat
-pub async fn add_message(pool: &PgPool, chat_id: i64, sender_id: i64, message: &str) -> Result<i64, ApiError> {
+///
+/// The ciphertext messages are only accepted in the end-to-end encrypted chats, and the plain text ones only in the others.
+/// The plain text messages get encrypted at rest with the active master key, if any.
+#[instrument(target = "cheechat::db", skip_all, fields(chat_id = chat_id, sender_id = sender_id))]
+pub async fn add_message(
+    pool: &PgPool,
+    cipher: &MessageCipher,
+    chat_id: i64,
+    sender_id: i64,
+    message: &str,
+    payload_type: PayloadType,
+) -> Result<i64, ApiError> {
+    // The ciphertext of the end-to-end encrypted chats is stored as it is
+    let sealed = match payload_type {
+        PayloadType::Text => cipher.encrypt(message, &SealContext::ChatMessage { chat_id, sender_id })?,
+        PayloadType::Ciphertext => SealedMessage { message: message.to_owned(), key_id: None, data_key: None },
+    };
     let row = sqlx::query!(
-        "INSERT INTO cheechat.chat_messages (chat_id, sender_id, message) VALUES ($1, $2, $3) RETURNING id",
+        r#"
+        INSERT INTO cheechat.chat_messages (chat_id, sender_id, message, payload_type, key_id, data_key)
+        SELECT $1, $2, $3, $4::VARCHAR, $5, $6 FROM cheechat.chats
+        WHERE id = $1 AND encrypted = ($4::VARCHAR = 'ciphertext')
+        RETURNING id
+        "#,
         chat_id,
         sender_id,
-        message
+        sealed.message,
+        payload_type.as_str(),
+        sealed.key_id,
+        sealed.data_key
     )
-        .fetch_one(pool)
-        .await?;
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| match payload_type {
+            PayloadType::Text => ApiError::BadRequest("The chat is end-to-end encrypted, the messages must be ciphertext".into()),
+            PayloadType::Ciphertext => ApiError::BadRequest("The chat is not end-to-end encrypted".into()),
+        })?;
 
     let _update_chat = update_chat_last_message(pool, chat_id).await?;
 
@@ -69,22 +249,95 @@ pub async fn add_message(pool: &PgPool, chat_id: i64, sender_id: i64, message: &
 /// Updates the chat when a new chat message is added
 async fn update_chat_last_message(pool: &PgPool, chat_id: i64) -> Result<(), ApiError> {
     let _query = sqlx::query!("UPDATE cheechat.chats SET last_message_at = CURRENT_TIMESTAMP WHERE id = $1", chat_id)
-        .fetch_one(pool)
+        .execute(pool)
         .await?;
 
     Ok(())
 }
 
+/// Sets the time after which the messages of the chat disappear, none keeps them
+#[instrument(target = "cheechat::db", skip_all, fields(chat_id = chat_id))]
+pub async fn set_message_ttl(pool: &PgPool, chat_id: i64, message_ttl_secs: Option<i64>) -> Result<(), ApiError> {
+    sqlx::query!("UPDATE cheechat.chats SET message_ttl_secs = $2 WHERE id = $1", chat_id, message_ttl_secs)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Switches the chat to end-to-end encryption, there is no way back
+#[instrument(target = "cheechat::db", skip_all, fields(chat_id = chat_id))]
+pub async fn enable_encryption(pool: &PgPool, chat_id: i64) -> Result<(), ApiError> {
+    sqlx::query!("UPDATE cheechat.chats SET encrypted = TRUE WHERE id = $1", chat_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Deletes up to the given number of messages expired by the rule.
+///
+/// Each rule gets its own query, so the TTL of the chats is looked up per chat and the maximum age
+/// goes through the index of the creation time.
+#[instrument(target = "cheechat::db", skip_all)]
+pub async fn delete_expired_messages(pool: &PgPool, expiry: Expiry, limit: i64) -> Result<Vec<ExpiredMessage>, ApiError> {
+    let messages = match expiry {
+        Expiry::ChatTtl => sqlx::query_as!(
+            ExpiredMessage,
+            r#"
+            DELETE FROM cheechat.chat_messages
+            WHERE id IN (
+                SELECT messages.id
+                FROM cheechat.chats AS chats
+                JOIN cheechat.chat_messages AS messages ON messages.chat_id = chats.id
+                    AND messages.created_at <= CURRENT_TIMESTAMP - chats.message_ttl_secs::FLOAT8 * INTERVAL '1 second'
+                WHERE chats.message_ttl_secs IS NOT NULL
+                LIMIT $1
+            )
+            RETURNING id, chat_id
+            "#,
+            limit
+        )
+            .fetch_all(pool)
+            .await?,
+        Expiry::MaxAge(max_age_secs) => sqlx::query_as!(
+            ExpiredMessage,
+            r#"
+            DELETE FROM cheechat.chat_messages
+            WHERE id IN (
+                SELECT id FROM cheechat.chat_messages
+                WHERE created_at < CURRENT_TIMESTAMP - $1::FLOAT8 * INTERVAL '1 second'
+                LIMIT $2
+            )
+            RETURNING id, chat_id
+            "#,
+            max_age_secs as f64,
+            limit
+        )
+            .fetch_all(pool)
+            .await?,
+    };
+
+    Ok(messages)
+}
+
 /// Retrieves the chat overviews for the given user
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
 pub async fn get_chat_overviews(
     pool: &PgPool,
+    cipher: &MessageCipher,
     user_id: i64,
 ) -> Result<Vec<ChatOverview>, ApiError> {
     let chat_overviews = sqlx::query!(
         r#"
         SELECT
             chats.id AS chat_id,
+            chats.message_ttl_secs,
+            chats.encrypted,
+            chat_messages.sender_id AS "last_message_sender_id: Option<i64>",
             chat_messages.message AS "last_message: Option<String>",
+            chat_messages.key_id AS "last_message_key_id: Option<String>",
+            chat_messages.data_key AS "last_message_data_key: Option<String>",
             chat_messages.created_at AS "last_message_at: Option<sqlx::types::time::PrimitiveDateTime>",
             CASE
                 WHEN chats.user1_id = $1 THEN users2.id
@@ -105,12 +358,28 @@ pub async fn get_chat_overviews(
             CASE
                 WHEN chats.user1_id = $1 THEN users2.username
                 ELSE users1.username
-            END AS other_user_username
+            END AS other_user_username,
+            CASE
+                WHEN chats.user1_id = $1 THEN users2.avatar_url
+                ELSE users1.avatar_url
+            END AS other_user_avatar_url,
+            CASE
+                WHEN chats.user1_id = $1 THEN users2.status_text
+                ELSE users1.status_text
+            END AS other_user_status_text,
+            CASE
+                WHEN chats.user1_id = $1 THEN users2.is_bot
+                ELSE users1.is_bot
+            END AS other_user_is_bot
         FROM cheechat.chats AS chats
         LEFT JOIN LATERAL (
-            SELECT message, created_at
+            SELECT sender_id, message, key_id, data_key, created_at
             FROM cheechat.chat_messages
-            WHERE chat_messages.chat_id = chats.id
+            WHERE chat_messages.chat_id = chats.id AND chat_messages.created_at > COALESCE(
+                (SELECT cleared_at FROM cheechat.chat_history_clears AS clears
+                    WHERE clears.chat_id = chats.id AND clears.user_id = $1),
+                '-infinity'
+            )
             ORDER BY created_at DESC
             LIMIT 1
         ) AS chat_messages ON true
@@ -123,14 +392,28 @@ pub async fn get_chat_overviews(
     )
         .map(|row| ChatOverview {
             chat_id: row.chat_id,
-            last_message: row.last_message,
+            // An unreadable last message doesn't prevent listing the chats
+            last_message: row.last_message.and_then(|message| {
+                let context = SealContext::ChatMessage {
+                    chat_id: row.chat_id,
+                    sender_id: row.last_message_sender_id.unwrap_or_default(),
+                };
+                cipher.decrypt(message, row.last_message_key_id.as_deref(), row.last_message_data_key.as_deref(), &context)
+                    .map_err(|_| tracing::error!("Error decrypting the last message of chat {}", row.chat_id))
+                    .ok()
+            }),
             last_message_at: row.last_message_at.map(|dt| dt.assume_utc().unix_timestamp()),
+            message_ttl_secs: row.message_ttl_secs,
+            encrypted: row.encrypted,
             other_user: UserInfo {
                 id: row.other_user_id.unwrap(),
                 email: row.other_user_email.unwrap(),
                 first_name: row.other_user_first_name.unwrap(),
                 last_name: row.other_user_last_name.unwrap(),
                 username: row.other_user_username.unwrap(),
+                avatar_url: row.other_user_avatar_url,
+                status_text: row.other_user_status_text,
+                is_bot: row.other_user_is_bot.unwrap_or(false),
             },
         })
         .fetch_all(pool)
@@ -139,3 +422,21 @@ pub async fn get_chat_overviews(
     Ok(chat_overviews)
 }
 
+/// Retrieves the chat counters
+#[instrument(target = "cheechat::db", skip_all)]
+pub async fn get_chat_stats(pool: &PgPool) -> Result<ChatStats, ApiError> {
+    let stats = sqlx::query_as!(
+        ChatStats,
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM cheechat.chats) AS "chats!",
+            (SELECT COUNT(*) FROM cheechat.chat_messages) AS "messages!",
+            (SELECT COUNT(*) FROM cheechat.chat_messages
+                WHERE created_at > CURRENT_TIMESTAMP - INTERVAL '1 day') AS "messages_last_day!"
+        "#
+    )
+        .fetch_one(pool)
+        .await?;
+
+    Ok(stats)
+}
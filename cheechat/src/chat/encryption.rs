@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::io;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, Nonce, OsRng, Payload};
+use aes_gcm::Aes256Gcm;
+
+use crate::app::config::EncryptionConfig;
+use crate::chat::models::ChatMessage;
+use crate::errors::ApiError;
+
+/// The size in bytes of the master keys and the data keys.
+const KEY_SIZE: usize = 32;
+/// The size in bytes of the nonces, stored in front of every ciphertext.
+const NONCE_SIZE: usize = 12;
+
+/// The row a sealed body belongs to, authenticated along with the body and its data key,
+/// so a body can't be moved to another row or table and still be decrypted.
+#[derive(Debug, Clone)]
+pub enum SealContext {
+    ChatMessage { chat_id: i64, sender_id: i64 },
+    ScheduledMessage { chat_id: i64, sender_id: i64 },
+    Report { reporter_id: i64, reported_user_id: i64 },
+    WebhookDelivery { delivery_id: i64, webhook_id: i64, event: String },
+}
+
+impl SealContext {
+    fn aad(&self) -> String {
+        match self {
+            SealContext::ChatMessage { chat_id, sender_id } => format!("chat_message:{chat_id}:{sender_id}"),
+            SealContext::ScheduledMessage { chat_id, sender_id } => format!("scheduled_message:{chat_id}:{sender_id}"),
+            SealContext::Report { reporter_id, reported_user_id } => format!("report:{reporter_id}:{reported_user_id}"),
+            SealContext::WebhookDelivery { delivery_id, webhook_id, event } => {
+                format!("webhook_delivery:{delivery_id}:{webhook_id}:{event}")
+            }
+        }
+    }
+
+    /// The data keys are bound to the master key too.
+    fn data_key_aad(&self, key_id: &str) -> String {
+        format!("{key_id}:{}", self.aad())
+    }
+}
+
+/// A stored body to re-encrypt with the active master key, along with the row it belongs to.
+#[derive(Debug)]
+pub struct SealedRow {
+    pub id: i64,
+    pub context: SealContext,
+    pub sealed: SealedMessage,
+}
+
+/// The message body as it is stored.
+#[derive(Debug)]
+pub struct SealedMessage {
+    /// The hex encoded ciphertext, or the plain text if there is no key.
+    pub message: String,
+    /// The id of the master key that wraps the data key.
+    pub key_id: Option<String>,
+    /// The hex encoded data key of the message, wrapped with the master key.
+    pub data_key: Option<String>,
+}
+
+/// The envelope encryption of the message bodies at rest.
+///
+/// Every message is encrypted with its own random data key, which is in turn encrypted (wrapped)
+/// with one of the master keys of the configuration, so rotating the master key only re-wraps
+/// the data keys. The master keys never leave the server and are never stored in the database.
+pub struct MessageCipher {
+    /// The id of the master key the new messages get encrypted with, none stores them in plain text.
+    active_key_id: Option<String>,
+    master_keys: HashMap<String, Aes256Gcm>,
+}
+
+impl MessageCipher {
+    /// Loads the master keys of the configuration and of the key files.
+    pub fn from_config(config: &EncryptionConfig) -> io::Result<Self> {
+        let mut master_keys = HashMap::new();
+
+        for entry in &config.keys {
+            let (key_id, key) = entry.split_once(':')
+                .ok_or_else(|| invalid_key("The encryption keys must be formatted as id:hex"))?;
+            master_keys.insert(key_id.trim().to_owned(), parse_key(key)?);
+        }
+        if let Some(keys_dir) = &config.keys_dir {
+            for entry in std::fs::read_dir(keys_dir)? {
+                let path = entry?.path();
+                if path.extension().is_some_and(|extension| extension == "key") {
+                    let key_id = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+                    master_keys.insert(key_id, parse_key(&std::fs::read_to_string(&path)?)?);
+                }
+            }
+        }
+
+        if let Some(key_id) = &config.active_key_id {
+            if !master_keys.contains_key(key_id) {
+                return Err(invalid_key(&format!("The active encryption key {key_id} is not configured")));
+            }
+        }
+
+        Ok(Self { active_key_id: config.active_key_id.clone(), master_keys })
+    }
+
+    /// Generates a new hex encoded master key.
+    pub fn generate_key() -> String {
+        hex::encode(Aes256Gcm::generate_key(OsRng))
+    }
+
+    pub fn active_key_id(&self) -> Option<&str> {
+        self.active_key_id.as_deref()
+    }
+
+    /// Encrypts the message with a new data key wrapped with the active master key, if any.
+    pub fn encrypt(&self, message: &str, context: &SealContext) -> Result<SealedMessage, ApiError> {
+        let Some((key_id, master_key)) = self.active_key() else {
+            return Ok(SealedMessage { message: message.to_owned(), key_id: None, data_key: None });
+        };
+
+        let data_key = Aes256Gcm::generate_key(OsRng);
+        let message = seal(&Aes256Gcm::new(&data_key), message.as_bytes(), context.aad().as_bytes())?;
+        let data_key = seal(master_key, &data_key, context.data_key_aad(key_id).as_bytes())?;
+
+        Ok(SealedMessage { message, key_id: Some(key_id.to_owned()), data_key: Some(data_key) })
+    }
+
+    /// Decrypts the stored message body, the ones without a key are returned as they are.
+    pub fn decrypt(
+        &self,
+        message: String,
+        key_id: Option<&str>,
+        data_key: Option<&str>,
+        context: &SealContext,
+    ) -> Result<String, ApiError> {
+        let Some(key_id) = key_id else {
+            return Ok(message);
+        };
+
+        let data_key = self.unwrap_data_key(key_id, data_key, context)?;
+        let data_key = Aes256Gcm::new_from_slice(&data_key).map_err(|_| ApiError::EncryptionError)?;
+        let message = open(&data_key, &message, context.aad().as_bytes())?;
+        String::from_utf8(message).map_err(|_| ApiError::EncryptionError)
+    }
+
+    /// Decrypts the body of the stored message in place.
+    pub fn decrypt_message(&self, message: &mut ChatMessage) -> Result<(), ApiError> {
+        let context = SealContext::ChatMessage { chat_id: message.chat_id, sender_id: message.sender_id };
+        let body = std::mem::take(&mut message.message);
+        message.message = self.decrypt(body, message.key_id.as_deref(), message.data_key.as_deref(), &context)?;
+        message.key_id = None;
+        message.data_key = None;
+        Ok(())
+    }
+
+    /// Seals the stored body with the active master key: the data key of an encrypted body
+    /// gets re-wrapped, the body stays as it is, and a plain text body gets encrypted.
+    pub fn reseal(&self, sealed: SealedMessage, context: &SealContext) -> Result<SealedMessage, ApiError> {
+        let Some(key_id) = sealed.key_id.as_deref() else {
+            return self.encrypt(&sealed.message, context);
+        };
+        let (active_key_id, master_key) = self.active_key().ok_or(ApiError::EncryptionError)?;
+
+        let data_key = self.unwrap_data_key(key_id, sealed.data_key.as_deref(), context)?;
+        let data_key = seal(master_key, &data_key, context.data_key_aad(active_key_id).as_bytes())?;
+
+        Ok(SealedMessage { message: sealed.message, key_id: Some(active_key_id.to_owned()), data_key: Some(data_key) })
+    }
+
+    fn active_key(&self) -> Option<(&str, &Aes256Gcm)> {
+        let key_id = self.active_key_id.as_deref()?;
+        self.master_keys.get(key_id).map(|master_key| (key_id, master_key))
+    }
+
+    /// Decrypts the data key of a message with the master key it was wrapped with.
+    fn unwrap_data_key(&self, key_id: &str, data_key: Option<&str>, context: &SealContext) -> Result<Vec<u8>, ApiError> {
+        let master_key = self.master_keys.get(key_id).ok_or_else(|| {
+            tracing::error!("The encryption key {key_id} is not configured");
+            ApiError::EncryptionError
+        })?;
+        open(master_key, data_key.ok_or(ApiError::EncryptionError)?, context.data_key_aad(key_id).as_bytes())
+    }
+}
+
+/// Encrypts the plaintext with a random nonce and returns the hex encoded nonce and ciphertext.
+fn seal(cipher: &Aes256Gcm, plaintext: &[u8], aad: &[u8]) -> Result<String, ApiError> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, Payload { msg: plaintext, aad })
+        .map_err(|_| ApiError::EncryptionError)?;
+
+    Ok(hex::encode([nonce.as_slice(), &ciphertext].concat()))
+}
+
+/// Decrypts the hex encoded nonce and ciphertext.
+fn open(cipher: &Aes256Gcm, sealed: &str, aad: &[u8]) -> Result<Vec<u8>, ApiError> {
+    let sealed = hex::decode(sealed).map_err(|_| ApiError::EncryptionError)?;
+    if sealed.len() < NONCE_SIZE {
+        return Err(ApiError::EncryptionError);
+    }
+
+    let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+    cipher.decrypt(Nonce::<Aes256Gcm>::from_slice(nonce), Payload { msg: ciphertext, aad })
+        .map_err(|_| ApiError::EncryptionError)
+}
+
+/// Parses the hex encoded master key.
+fn parse_key(key: &str) -> io::Result<Aes256Gcm> {
+    let key = hex::decode(key.trim()).map_err(|_| invalid_key("The encryption keys must be hex encoded"))?;
+    if key.len() != KEY_SIZE {
+        return Err(invalid_key(&format!("The encryption keys must be {KEY_SIZE} bytes long")));
+    }
+
+    Aes256Gcm::new_from_slice(&key).map_err(|_| invalid_key("Invalid encryption key"))
+}
+
+fn invalid_key(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher(active_key_id: Option<&str>, keys: &[(&str, &str)]) -> MessageCipher {
+        MessageCipher::from_config(&EncryptionConfig {
+            active_key_id: active_key_id.map(str::to_owned),
+            keys: keys.iter().map(|(key_id, key)| format!("{key_id}:{key}")).collect(),
+            keys_dir: None,
+        }).unwrap()
+    }
+
+    fn context() -> SealContext {
+        SealContext::ChatMessage { chat_id: 1, sender_id: 2 }
+    }
+
+    fn open_sealed(cipher: &MessageCipher, sealed: SealedMessage, context: &SealContext) -> Result<String, ApiError> {
+        cipher.decrypt(sealed.message, sealed.key_id.as_deref(), sealed.data_key.as_deref(), context)
+    }
+
+    #[test]
+    fn decrypts_the_encrypted_messages() {
+        let cipher = cipher(Some("k1"), &[("k1", &MessageCipher::generate_key())]);
+        let sealed = cipher.encrypt("hello", &context()).unwrap();
+
+        assert_ne!(sealed.message, "hello");
+        assert_eq!(sealed.key_id.as_deref(), Some("k1"));
+        assert_eq!(open_sealed(&cipher, sealed, &context()).unwrap(), "hello");
+    }
+
+    #[test]
+    fn refuses_the_bodies_moved_to_another_row() {
+        let cipher = cipher(Some("k1"), &[("k1", &MessageCipher::generate_key())]);
+        let sealed = cipher.encrypt("hello", &context()).unwrap();
+        let other_chat = SealContext::ChatMessage { chat_id: 3, sender_id: 2 };
+
+        assert!(matches!(open_sealed(&cipher, sealed, &other_chat), Err(ApiError::EncryptionError)));
+    }
+
+    #[test]
+    fn stores_the_plain_text_without_an_active_key() {
+        let cipher = cipher(None, &[]);
+        let sealed = cipher.encrypt("hello", &context()).unwrap();
+
+        assert_eq!((sealed.message.as_str(), sealed.key_id.as_deref(), sealed.data_key.as_deref()), ("hello", None, None));
+        assert_eq!(open_sealed(&cipher, sealed, &context()).unwrap(), "hello");
+    }
+
+    #[test]
+    fn reseals_with_the_active_key() {
+        let (old_key, new_key) = (MessageCipher::generate_key(), MessageCipher::generate_key());
+        let sealed = cipher(Some("k1"), &[("k1", &old_key)]).encrypt("hello", &context()).unwrap();
+        let plain = cipher(None, &[]).encrypt("plain", &context()).unwrap();
+
+        let rotated = cipher(Some("k2"), &[("k1", &old_key), ("k2", &new_key)]);
+        let body = sealed.message.clone();
+        let resealed = rotated.reseal(sealed, &context()).unwrap();
+        assert_eq!((resealed.message.as_str(), resealed.key_id.as_deref()), (body.as_str(), Some("k2")));
+
+        // The old key is no longer needed once every body got resealed
+        let new_only = cipher(Some("k2"), &[("k2", &new_key)]);
+        assert_eq!(open_sealed(&new_only, resealed, &context()).unwrap(), "hello");
+        let encrypted = new_only.reseal(plain, &context()).unwrap();
+        assert_eq!(encrypted.key_id.as_deref(), Some("k2"));
+        assert_eq!(open_sealed(&new_only, encrypted, &context()).unwrap(), "plain");
+    }
+
+    #[test]
+    fn refuses_the_invalid_keys() {
+        let config = |keys: Vec<String>, active_key_id: Option<&str>| EncryptionConfig {
+            active_key_id: active_key_id.map(str::to_owned),
+            keys,
+            keys_dir: None,
+        };
+
+        assert!(MessageCipher::from_config(&config(vec![MessageCipher::generate_key()], None)).is_err());
+        assert!(MessageCipher::from_config(&config(vec!["k1:abcd".into()], None)).is_err());
+        assert!(MessageCipher::from_config(&config(vec![], Some("k1"))).is_err());
+    }
+}
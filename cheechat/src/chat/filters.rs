@@ -0,0 +1,377 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::app::config::FilterConfig;
+
+/// The chat message that is about to be stored and broadcast.
+#[derive(Debug)]
+pub struct OutgoingMessage<'a> {
+    pub chat_id: i64,
+    pub sender_id: i64,
+    pub content: &'a str,
+}
+
+/// The decision of a filter about a message.
+#[derive(Debug, PartialEq, Eq)]
+pub enum FilterOutcome {
+    /// The message is sent as it is.
+    Pass,
+    /// The message is sent with the rewritten content.
+    Rewrite(String),
+    /// The message is dropped, the reason is reported back to the sender.
+    Reject(String),
+}
+
+/// A filter of the outgoing chat messages.
+///
+/// The filters are owned by the chat server, so they may keep state across messages.
+pub trait ContentFilter {
+    fn name(&self) -> &'static str;
+
+    fn apply(&mut self, message: &OutgoingMessage<'_>) -> FilterOutcome;
+}
+
+/// The rejection of a message by one of the filters.
+#[derive(Debug)]
+pub struct Rejection {
+    pub filter: &'static str,
+    pub reason: String,
+}
+
+/// The chain of filters the messages go through, in order.
+#[derive(Default)]
+pub struct FilterPipeline {
+    filters: Vec<Box<dyn ContentFilter>>,
+}
+
+impl FilterPipeline {
+    /// Builds the pipeline of the built-in filters enabled in the configuration.
+    pub fn from_config(config: &FilterConfig) -> std::io::Result<Self> {
+        let mut pipeline = Self::default();
+
+        if config.max_length > 0 {
+            pipeline = pipeline.with(MaxLengthFilter { max_length: config.max_length });
+        }
+        if config.spam_max_repeats > 0 {
+            pipeline = pipeline.with(SpamFilter::new(
+                config.spam_max_repeats,
+                Duration::from_secs(config.spam_window_secs),
+            ));
+        }
+        if config.block_links {
+            pipeline = pipeline.with(LinkFilter::new(&config.allowed_link_domains));
+        }
+
+        let mut words = config.profanity_words.clone();
+        if let Some(path) = &config.profanity_words_file {
+            words.extend(std::fs::read_to_string(path)?.lines().map(str::to_owned));
+        }
+        if words.iter().any(|word| !word.trim().is_empty()) {
+            pipeline = pipeline.with(ProfanityFilter::new(&words, config.reject_profanity));
+        }
+
+        Ok(pipeline)
+    }
+
+    /// Appends the filter to the end of the pipeline.
+    pub fn with(mut self, filter: impl ContentFilter + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Runs the message through the filters and returns the content to send.
+    /// The pipeline stops at the first rejection.
+    pub fn apply(&mut self, chat_id: i64, sender_id: i64, content: String) -> Result<String, Rejection> {
+        let mut content = content;
+
+        for filter in self.filters.iter_mut() {
+            let message = OutgoingMessage { chat_id, sender_id, content: &content };
+            match filter.apply(&message) {
+                FilterOutcome::Pass => (),
+                FilterOutcome::Rewrite(rewritten) => content = rewritten,
+                FilterOutcome::Reject(reason) => return Err(Rejection { filter: filter.name(), reason }),
+            }
+        }
+
+        Ok(content)
+    }
+}
+
+/// Rejects the messages longer than the limit.
+pub struct MaxLengthFilter {
+    pub max_length: usize,
+}
+
+impl ContentFilter for MaxLengthFilter {
+    fn name(&self) -> &'static str {
+        "max_length"
+    }
+
+    fn apply(&mut self, message: &OutgoingMessage<'_>) -> FilterOutcome {
+        if message.content.chars().count() > self.max_length {
+            return FilterOutcome::Reject(format!("The message can't be longer than {} characters", self.max_length));
+        }
+        FilterOutcome::Pass
+    }
+}
+
+/// Masks the listed words, or rejects the messages that contain them.
+pub struct ProfanityFilter {
+    words: HashSet<String>,
+    reject: bool,
+}
+
+impl ProfanityFilter {
+    pub fn new(words: &[String], reject: bool) -> Self {
+        let words = words.iter()
+            .map(|word| word.trim().to_lowercase())
+            .filter(|word| !word.is_empty())
+            .collect();
+
+        Self { words, reject }
+    }
+}
+
+impl ContentFilter for ProfanityFilter {
+    fn name(&self) -> &'static str {
+        "profanity"
+    }
+
+    fn apply(&mut self, message: &OutgoingMessage<'_>) -> FilterOutcome {
+        let mut masked = String::with_capacity(message.content.len());
+        let mut word = String::new();
+        let mut found = false;
+
+        // Split the content in words, keeping the separators as they are
+        for c in message.content.chars().chain(std::iter::once('\0')) {
+            if c.is_alphanumeric() {
+                word.push(c);
+                continue;
+            }
+            if !word.is_empty() {
+                if self.words.contains(&word.to_lowercase()) {
+                    found = true;
+                    masked.extend(std::iter::repeat('*').take(word.chars().count()));
+                } else {
+                    masked.push_str(&word);
+                }
+                word.clear();
+            }
+            if c != '\0' {
+                masked.push(c);
+            }
+        }
+
+        match (found, self.reject) {
+            (false, _) => FilterOutcome::Pass,
+            (true, true) => FilterOutcome::Reject("The message contains inappropriate language".into()),
+            (true, false) => FilterOutcome::Rewrite(masked),
+        }
+    }
+}
+
+/// Rejects the messages with links, unless they point to one of the allowed domains.
+/// The bare domains count as links too, since the clients render them as such.
+pub struct LinkFilter {
+    allowed_domains: Vec<String>,
+}
+
+impl LinkFilter {
+    pub fn new(allowed_domains: &[String]) -> Self {
+        let allowed_domains = allowed_domains.iter()
+            .map(|domain| domain.trim().trim_start_matches('.').to_lowercase())
+            .filter(|domain| !domain.is_empty())
+            .collect();
+
+        Self { allowed_domains }
+    }
+
+    fn is_allowed(&self, host: &str) -> bool {
+        self.allowed_domains.iter()
+            .any(|domain| host == domain || host.ends_with(&format!(".{domain}")))
+    }
+}
+
+impl ContentFilter for LinkFilter {
+    fn name(&self) -> &'static str {
+        "links"
+    }
+
+    fn apply(&mut self, message: &OutgoingMessage<'_>) -> FilterOutcome {
+        let blocked = message.content
+            .split_whitespace()
+            .filter_map(link_host)
+            .any(|host| !self.is_allowed(&host));
+
+        if blocked {
+            return FilterOutcome::Reject("Links to this site are not allowed".into());
+        }
+        FilterOutcome::Pass
+    }
+}
+
+/// Extracts the lowercase host of the word if it looks like a link: a url, possibly wrapped in punctuation
+/// like `(https://x.io)`, or a bare domain like `x.io/path`. The email addresses are not links.
+fn link_host(word: &str) -> Option<String> {
+    let lowercase = word.to_lowercase();
+    let with_scheme = ["http://", "https://"].iter()
+        .find_map(|scheme| lowercase.find(scheme).map(|start| &lowercase[start + scheme.len()..]));
+    let rest = with_scheme.unwrap_or_else(|| lowercase.trim_start_matches(|c: char| !c.is_alphanumeric()));
+
+    // The browsers treat a backslash like a slash, so it ends the authority too
+    let authority = rest.split(['/', '\\', '?', '#']).next().unwrap_or_default();
+    if with_scheme.is_none() && authority.contains('@') {
+        return None;
+    }
+    let host = authority
+        .rsplit('@')
+        .next()
+        .and_then(|host| host.split(':').next())
+        .unwrap_or_default()
+        .trim_end_matches(|c: char| !c.is_alphanumeric());
+
+    (with_scheme.is_some() || is_domain(host)).then(|| host.to_owned())
+}
+
+/// Whether the host is a domain name with a top-level domain, e.g. `example.com` but not `e.g` or `1.5`.
+fn is_domain(host: &str) -> bool {
+    let labels: Vec<&str> = host.split('.').collect();
+    let tld = labels.last().copied().unwrap_or_default();
+
+    labels.len() >= 2
+        && labels.iter().all(|label| !label.is_empty() && label.chars().all(|c| c.is_alphanumeric() || c == '-'))
+        && tld.chars().count() >= 2
+        && tld.chars().all(char::is_alphabetic)
+}
+
+/// Rejects the same message sent over and over by a user within a short time.
+pub struct SpamFilter {
+    max_repeats: usize,
+    window: Duration,
+    recent: HashMap<i64, RecentMessage>,
+}
+
+struct RecentMessage {
+    content: String,
+    repeats: usize,
+    last_sent_at: Instant,
+}
+
+impl SpamFilter {
+    /// The number of tracked senders above which the expired entries get dropped.
+    const MAX_TRACKED_SENDERS: usize = 10_000;
+
+    pub fn new(max_repeats: usize, window: Duration) -> Self {
+        Self { max_repeats, window, recent: HashMap::new() }
+    }
+}
+
+impl ContentFilter for SpamFilter {
+    fn name(&self) -> &'static str {
+        "spam"
+    }
+
+    fn apply(&mut self, message: &OutgoingMessage<'_>) -> FilterOutcome {
+        let now = Instant::now();
+        let content = message.content.trim().to_lowercase();
+
+        if self.recent.len() > Self::MAX_TRACKED_SENDERS {
+            let window = self.window;
+            self.recent.retain(|_, recent| now.duration_since(recent.last_sent_at) < window);
+        }
+
+        let recent = self.recent.entry(message.sender_id).or_insert_with(|| RecentMessage {
+            content: String::new(),
+            repeats: 0,
+            last_sent_at: now,
+        });
+
+        if recent.content == content && now.duration_since(recent.last_sent_at) < self.window {
+            recent.repeats += 1;
+        } else {
+            recent.content = content;
+            recent.repeats = 1;
+        }
+        recent.last_sent_at = now;
+
+        if recent.repeats > self.max_repeats {
+            return FilterOutcome::Reject("Slow down, you are sending the same message over and over".into());
+        }
+        FilterOutcome::Pass
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(content: &str) -> OutgoingMessage<'_> {
+        OutgoingMessage { chat_id: 1, sender_id: 2, content }
+    }
+
+    #[test]
+    fn rejects_the_long_messages() {
+        let mut filter = MaxLengthFilter { max_length: 5 };
+
+        assert_eq!(filter.apply(&message("héllo")), FilterOutcome::Pass);
+        assert!(matches!(filter.apply(&message("hello!")), FilterOutcome::Reject(_)));
+    }
+
+    #[test]
+    fn masks_or_rejects_the_listed_words() {
+        let words = vec!["Darn".to_owned(), " ".to_owned()];
+
+        let mut mask = ProfanityFilter::new(&words, false);
+        assert_eq!(mask.apply(&message("Oh DARN, darnit!")), FilterOutcome::Rewrite("Oh ****, darnit!".into()));
+        assert_eq!(mask.apply(&message("all good")), FilterOutcome::Pass);
+
+        let mut reject = ProfanityFilter::new(&words, true);
+        assert!(matches!(reject.apply(&message("darn")), FilterOutcome::Reject(_)));
+    }
+
+    #[test]
+    fn finds_the_hosts_of_the_links() {
+        assert_eq!(link_host("https://Example.com/page"), Some("example.com".into()));
+        assert_eq!(link_host("(https://x.io)"), Some("x.io".into()));
+        assert_eq!(link_host("see:http://user@x.io:8080/"), Some("x.io".into()));
+        assert_eq!(link_host("www.x.io,"), Some("www.x.io".into()));
+        assert_eq!(link_host("\"x.io/path\""), Some("x.io".into()));
+        assert_eq!(link_host("https://evil.io\\@example.com"), Some("evil.io".into()));
+
+        for word in ["hello", "e.g.", "3.14", "bob@example.com", "end."] {
+            assert_eq!(link_host(word), None, "{word}");
+        }
+    }
+
+    #[test]
+    fn blocks_the_links_outside_the_allowed_domains() {
+        let mut filter = LinkFilter::new(&[".example.com".into()]);
+
+        assert_eq!(filter.apply(&message("docs at https://docs.example.com/a and example.com")), FilterOutcome::Pass);
+        assert!(matches!(filter.apply(&message("look (https://evil.io)")), FilterOutcome::Reject(_)));
+        assert!(matches!(filter.apply(&message("go to evil.io now")), FilterOutcome::Reject(_)));
+        assert!(matches!(filter.apply(&message("https://example.com.evil.io")), FilterOutcome::Reject(_)));
+        assert!(matches!(filter.apply(&message("https://evil.io\\@example.com")), FilterOutcome::Reject(_)));
+    }
+
+    #[test]
+    fn rejects_the_repeated_messages() {
+        let mut filter = SpamFilter::new(2, Duration::from_secs(60));
+
+        assert_eq!(filter.apply(&message("hi")), FilterOutcome::Pass);
+        assert_eq!(filter.apply(&message(" HI ")), FilterOutcome::Pass);
+        assert!(matches!(filter.apply(&message("hi")), FilterOutcome::Reject(_)));
+        assert_eq!(filter.apply(&message("something else")), FilterOutcome::Pass);
+    }
+
+    #[test]
+    fn stops_the_pipeline_at_the_first_rejection() {
+        let mut pipeline = FilterPipeline::default()
+            .with(ProfanityFilter::new(&["darn".into()], false))
+            .with(MaxLengthFilter { max_length: 4 });
+
+        assert_eq!(pipeline.apply(1, 2, "darn".into()).unwrap(), "****");
+        let rejection = pipeline.apply(1, 2, "darn it".into()).unwrap_err();
+        assert_eq!(rejection.filter, "max_length");
+    }
+}
@@ -1,14 +1,44 @@
 use actix_web::web;
-pub use server::ChatServer;
+use utoipa::OpenApi;
+pub use commands::{CommandContext, CommandOutcome, CommandRegistry, CommandTask, SlashCommand};
+pub use encryption::{MessageCipher, SealContext, SealedMessage, SealedRow};
+pub use filters::{ContentFilter, FilterOutcome, FilterPipeline, OutgoingMessage, Rejection};
+pub use sweeper::MessageSweeper;
+pub use session::WebsocketConfig;
+pub use server::{ChatServer, ClientMessage, DeleteMessage, Envelope, GetServerStats, Notice, NotifyChat, RevokeSessions, SendError, SendOutcome, ServerStats, Shutdown};
 
+mod commands;
+mod encryption;
+mod filters;
 mod server;
+mod sweeper;
 mod session;
 mod services;
 mod db;
 mod models;
 
+pub use db::*;
+pub use models::*;
+
 pub fn init_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(services::chat_route);
     cfg.service(services::init_chat);
     cfg.service(services::get_chats);
-}
\ No newline at end of file
+    cfg.service(services::send_message);
+    cfg.service(services::set_retention);
+}
+
+/// The OpenAPI description of the routes, along with the frames of the websocket.
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        services::chat_route,
+        services::init_chat,
+        services::get_chats,
+        services::send_message,
+        services::set_retention,
+    ),
+    components(schemas(Envelope)),
+    tags((name = "chats", description = "The chats of the current user and their messages")),
+)]
+pub struct ApiDoc;
\ No newline at end of file
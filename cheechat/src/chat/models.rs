@@ -1,5 +1,7 @@
 use crate::users::UserInfo;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use std::str::FromStr;
 use sqlx;
 use sqlx::types::time::PrimitiveDateTime;
 
@@ -10,6 +12,10 @@ pub struct Chat {
     pub user2_id: i64,
     pub created_at: PrimitiveDateTime,
     pub last_message_at: Option<PrimitiveDateTime>,
+    /// The time after which the messages disappear, or none to keep them.
+    pub message_ttl_secs: Option<i64>,
+    /// Whether the messages are end-to-end encrypted, the server only relays and stores the ciphertext.
+    pub encrypted: bool,
 }
 
 #[derive(sqlx::FromRow, Debug)]
@@ -19,18 +25,149 @@ pub struct ChatMessage {
     pub sender_id: i64,
     pub message: String,
     pub created_at: PrimitiveDateTime,
+    pub payload_type: String,
+    /// The master key that wraps the data key of the body, none for the plain text bodies.
+    pub key_id: Option<String>,
+    pub data_key: Option<String>,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+impl ChatMessage {
+    pub fn payload_type(&self) -> PayloadType {
+        self.payload_type.parse().unwrap_or_default()
+    }
+}
+
+/// How the content of a chat message is to be read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadType {
+    /// Plain text, readable by the server.
+    #[default]
+    Text,
+    /// The opaque ciphertext of an end-to-end encrypted chat, only the participants can read it.
+    Ciphertext,
+}
+
+impl PayloadType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PayloadType::Text => "text",
+            PayloadType::Ciphertext => "ciphertext",
+        }
+    }
+}
+
+impl FromStr for PayloadType {
+    type Err = String;
+
+    fn from_str(payload_type: &str) -> Result<Self, Self::Err> {
+        match payload_type {
+            "text" => Ok(PayloadType::Text),
+            "ciphertext" => Ok(PayloadType::Ciphertext),
+            payload_type => Err(format!("Unknown payload type: {payload_type}")),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
 pub struct ChatRequest {
     pub recipient: String,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct SendMessage {
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct MessageSent {
+    pub id: i64,
+    /// The content as stored, when a slash command or the content filters rewrote it, e.g. `/me` or masked words.
+    pub message: Option<String>,
+}
+
+/// The time after which the messages of the chat disappear, none keeps them.
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct SetRetention {
+    pub message_ttl_secs: Option<i64>,
+}
+
+impl SetRetention {
+    const MIN_TTL_SECS: i64 = 30;
+    const MAX_TTL_SECS: i64 = 365 * 24 * 3600;
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self.message_ttl_secs {
+            Some(ttl) if !(Self::MIN_TTL_SECS..=Self::MAX_TTL_SECS).contains(&ttl) => Err(format!(
+                "The message TTL must be between {} seconds and {} days",
+                Self::MIN_TTL_SECS,
+                Self::MAX_TTL_SECS / (24 * 3600)
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The rule the retention sweeper deletes the messages, and their copies, by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    /// The messages that outlived the TTL of their chat.
+    ChatTtl,
+    /// The messages older than the given seconds in every chat, the server wide retention policy.
+    MaxAge(i64),
+}
+
+/// A chat message deleted by the retention sweeper.
+#[derive(Debug)]
+pub struct ExpiredMessage {
+    pub id: i64,
+    pub chat_id: i64,
+}
+
+/// The reply of a slash command sent through the REST API.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct CommandReply {
+    pub reply: String,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
 pub struct ChatOverview {
     pub chat_id: i64,
     pub last_message: Option<String>,
     pub last_message_at: Option<i64>,
+    pub message_ttl_secs: Option<i64>,
+    pub encrypted: bool,
     pub other_user: UserInfo
 }
 
+/// The chat counters of the deployment.
+#[derive(Serialize, Debug)]
+pub struct ChatStats {
+    pub chats: i64,
+    pub messages: i64,
+    pub messages_last_day: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn retention(message_ttl_secs: Option<i64>) -> SetRetention {
+        SetRetention { message_ttl_secs }
+    }
+
+    #[test]
+    fn accepts_the_ttls_within_the_limits() {
+        assert_eq!(retention(None).validate(), Ok(()));
+        assert_eq!(retention(Some(SetRetention::MIN_TTL_SECS)).validate(), Ok(()));
+        assert_eq!(retention(Some(SetRetention::MAX_TTL_SECS)).validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_the_ttls_out_of_the_limits() {
+        assert!(retention(Some(0)).validate().is_err());
+        assert!(retention(Some(-60)).validate().is_err());
+        assert!(retention(Some(SetRetention::MIN_TTL_SECS - 1)).validate().is_err());
+        assert!(retention(Some(SetRetention::MAX_TTL_SECS + 1)).validate().is_err());
+    }
+}
@@ -1,5 +1,15 @@
+use crate::chat::commands::{CommandContext, CommandOutcome, CommandRegistry};
 use crate::chat::db::{add_message, get_recent_messages};
-use actix::{Actor, Context, Handler, Message, Recipient};
+use crate::chat::encryption::MessageCipher;
+use crate::chat::filters::{FilterPipeline, Rejection};
+use crate::chat::models::PayloadType;
+use crate::errors::ApiError;
+use crate::metrics;
+use crate::scheduled;
+use crate::users::Credential;
+use crate::webhooks;
+use crate::webhooks::WebhookEvent;
+use actix::{fut, Actor, ActorFutureExt, Context, ContextFutureSpawner, Handler, Message, MessageResult, Recipient, ResponseActFuture, ResponseFuture, WrapFuture};
 use chrono::{Utc};
 use rand::prelude::ThreadRng;
 use rand::Rng;
@@ -7,23 +17,98 @@ use serde::{Deserialize, Serialize};
 use sqlx::{PgPool};
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use tokio::sync::oneshot;
+use tracing::Instrument;
+use utoipa::ToSchema;
 
 /// The message that gets forwarded from the server to the active sessions.
-#[derive(Message, Debug, Serialize, Deserialize)]
+#[derive(Message, Debug, Serialize, Deserialize, ToSchema)]
 #[rtype(result = "()")]
 pub struct FwdMessage {
+    pub id: i64,
     pub message: String,
+    /// The ciphertext messages of the end-to-end encrypted chats are relayed as they are.
+    pub payload_type: PayloadType,
     pub sender_id: i64,
     pub sent_at: i64,
 }
 
+/// The envelope of the json frames the server writes to the websockets, a chat message or a notice,
+/// told apart by their `type`. The clients send their messages as plain text frames, or as ciphertext
+/// in the end-to-end encrypted chats.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum Envelope {
+    Message(MessageFrame),
+    Notice(Notice),
+}
+
+/// A chat message of the history, or a new one, tagged with the `message` type.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MessageFrame {
+    Message(FwdMessage),
+}
+
+impl From<FwdMessage> for Envelope {
+    fn from(message: FwdMessage) -> Self {
+        Envelope::Message(MessageFrame::Message(message))
+    }
+}
+
+impl From<Notice> for Envelope {
+    fn from(notice: Notice) -> Self {
+        Envelope::Notice(notice)
+    }
+}
+
+/// The control events that the server sends to the active sessions.
+#[derive(Message, Debug)]
+#[rtype(result = "()")]
+pub enum SessionEvent {
+    /// The session must close the websocket connection with the given reason.
+    Terminate { reason: String },
+    /// The session must close the websocket connection because the server is restarting.
+    Restart { reason: String },
+    /// The session must write the notice to the websocket connection.
+    Notice(Notice),
+}
+
+/// The notices that get written to the websocket next to the chat messages, tagged by their type.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Notice {
+    /// The message got deleted by a moderator.
+    MessageDeleted { message_id: i64 },
+    /// The message of the session got rejected by the content filters and was not sent.
+    MessageRejected { reason: String },
+    /// The message of the session got rewritten by a slash command or the content filters, e.g. `/me`
+    /// or masked words, and was stored and sent with the given content.
+    MessageFiltered { message_id: i64, message: String },
+    /// The reply of a slash command, only sent to the session that issued it.
+    CommandReply { message: String },
+    /// The messages reached the retention period of the chat, or of the server, and got deleted.
+    MessagesExpired { message_ids: Vec<i64> },
+    /// The time after which the messages of the chat disappear got changed, none keeps them.
+    RetentionChanged { message_ttl_secs: Option<i64> },
+    /// The chat switched to end-to-end encryption, the messages must be sent as ciphertext from now on.
+    EncryptionEnabled,
+}
+
 /// The connect request to the chat, from the chat session to the server.
 #[derive(Message)]
 #[rtype(usize)]
 pub struct Connect {
     // The address of the session actor, so it can receive the server messages.
     pub addr: Recipient<FwdMessage>,
-    pub chat_id: i64
+    // The address of the session actor, so it can receive the server control events.
+    pub control: Recipient<SessionEvent>,
+    pub chat_id: i64,
+    pub user_id: i64,
+    // The login session, or the API token, the websocket connection got opened with.
+    pub credential: Credential,
+    // The id of the request that opened the websocket connection, for the logs.
+    pub request_id: String,
 }
 
 /// The disconnect request to the chat, from the chat session to the server.
@@ -34,48 +119,217 @@ pub struct Disconnect {
     pub chat_id: i64,
 }
 
-/// The chat message from the chat session to the server.
+/// The chat message from a chat session, or from the REST API, to the server.
 #[derive(Message)]
-#[rtype(result = "()")]
+#[rtype(result = "Result<SendOutcome, SendError>")]
 pub struct ClientMessage {
-    pub session_id: usize,
+    /// The session that sent the message, if sent through a websocket connection.
+    pub session_id: Option<usize>,
     pub content: String,
     pub chat_id: i64,
     pub sender_id: i64,
+    pub sender_username: String,
+    /// The scheduled message being sent, if any. The scheduled messages don't run the slash commands.
+    pub scheduled_message_id: Option<i64>,
+    /// The ciphertext messages skip the slash commands and the content filters, the server can't read them.
+    pub payload_type: PayloadType,
+    /// The id of the request, or of the websocket connection, the message came from, for the logs.
+    pub request_id: Option<String>,
+}
+
+/// What became of a chat message that was not rejected.
+#[derive(Debug)]
+pub enum SendOutcome {
+    /// The message got stored and broadcast with the given id, along with its content if a command
+    /// or the filters rewrote it.
+    Sent(i64, Option<String>),
+    /// The message was a slash command with the given reply for the sender only.
+    Replied(String),
+}
+
+/// The reasons a chat message was not sent.
+#[derive(Debug)]
+pub enum SendError {
+    /// The content filters rejected the message with the given reason.
+    Rejected(String),
+    /// The message could not be stored.
+    Failed,
+    /// The scheduled message was cancelled or already sent, so it was not sent again.
+    Skipped,
+    /// The server is shutting down and no longer takes messages.
+    Unavailable,
+}
+
+/// Terminates the active sessions of the user, e.g. when the login sessions get revoked.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RevokeSessions {
+    pub user_id: i64,
+    /// The login sessions or API tokens to terminate, or all of them if not provided.
+    pub credentials: Option<Vec<Credential>>,
+    pub reason: String,
+}
+
+/// Notifies the active sessions of the chat that the message got deleted.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct DeleteMessage {
+    pub chat_id: i64,
+    pub message_id: i64,
+}
+
+/// Notifies the active sessions of the chat, e.g. about the expired messages.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct NotifyChat {
+    pub chat_id: i64,
+    pub notice: Notice,
+}
+
+/// Closes the active sessions, e.g. for a restart, and resolves once the pending message writes are done.
+///
+/// The server doesn't take new messages afterwards, and the sessions connecting later get closed right away.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Shutdown {
+    pub reason: String,
+}
+
+/// Retrieves the counters of the active sessions and chats.
+#[derive(Message)]
+#[rtype(result = "ServerStats")]
+pub struct GetServerStats;
+
+/// The live counters of the chat server.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ServerStats {
+    pub active_sessions: usize,
+    pub active_chats: usize,
+    pub connected_users: usize,
+    /// The chat messages being stored and their webhook events being queued.
+    pub pending_writes: usize,
+    pub shutting_down: bool,
+}
+
+/// The maximum size in bytes of the ciphertext messages, which are not checked by the content filters.
+const MAX_CIPHERTEXT_LENGTH: usize = 64 * 1024;
+
+/// The server side handle of an active session.
+struct SessionHandle {
+    addr: Recipient<FwdMessage>,
+    control: Recipient<SessionEvent>,
+    user_id: i64,
+    credential: Credential,
 }
 
 pub struct ChatServer {
     /// Storage of the active sessions and their addresses.
-    sessions: HashMap<usize, Recipient<FwdMessage>>,
+    sessions: HashMap<usize, SessionHandle>,
     /// Storage of the active chats
     chats: HashMap<i64, HashSet<usize>>,
     /// Thread safe random generator to generate session ids upon connection
     rng: ThreadRng,
     /// Thread safe database connection pool reference to store chat messages
     db_pool: Arc<PgPool>,
+    /// The content filters the messages go through before they get stored and broadcast
+    filters: FilterPipeline,
+    /// The slash commands the users can type in the chats
+    commands: CommandRegistry,
+    /// The encryption of the message bodies at rest
+    cipher: Arc<MessageCipher>,
+    /// The messages being stored, the command tasks running and the webhook events being queued
+    pending_writes: usize,
+    /// The shutdown requests waiting for the pending writes, along with the reason given to the sessions
+    shutdown: Option<(String, Vec<oneshot::Sender<()>>)>,
 }
 
 impl ChatServer {
-    pub fn new(db_pool: PgPool) -> Self {
+    pub fn new(db_pool: PgPool, filters: FilterPipeline, commands: CommandRegistry, cipher: Arc<MessageCipher>) -> Self {
         let db_pool = Arc::new(db_pool);
         ChatServer {
             sessions: HashMap::new(),
             chats: HashMap::new(),
             rng: rand::thread_rng(),
             db_pool,
+            filters,
+            commands,
+            cipher,
+            pending_writes: 0,
+            shutdown: None,
+        }
+    }
+
+    /// Marks a pending write as done, and releases the shutdown once they are all done.
+    fn finish_write(&mut self) {
+        self.pending_writes -= 1;
+        if self.pending_writes == 0 {
+            if let Some((_, waiters)) = &mut self.shutdown {
+                waiters.drain(..).for_each(|waiter| {
+                    let _ = waiter.send(());
+                });
+            }
         }
     }
 
-    /// Broadcasts the client message to all the chat sessions that participate on the chat.
-    fn broadcast_message(&self, message: &ClientMessage) {
+    /// Queues the stored message for the webhooks of the participants, as a pending write.
+    fn dispatch_webhook(&mut self, message_id: i64, msg: ClientMessage, ctx: &mut Context<Self>) {
+        self.pending_writes += 1;
+
+        let span = tracing::info_span!("webhook_dispatch", request_id = msg.request_id.as_deref(), message_id);
+        let db_pool = self.db_pool.clone();
+        let cipher = self.cipher.clone();
+        async move {
+            webhooks::dispatch(&db_pool, &cipher, WebhookEvent::MessageCreated {
+                chat_id: msg.chat_id,
+                message_id,
+                sender_id: msg.sender_id,
+                message: msg.content,
+                sent_at: Utc::now().timestamp(),
+            }).await;
+        }
+            .instrument(span)
+            .into_actor(self)
+            .map(|_, act, _| act.finish_write())
+            .spawn(ctx);
+    }
+
+    /// Sends the notice to the session, if it is still active.
+    fn notify_session(&self, session_id: usize, notice: Notice) {
+        if let Some(session) = self.sessions.get(&session_id) {
+            session.control.do_send(SessionEvent::Notice(notice));
+        }
+    }
+
+    /// Sends the notice to all the active sessions of the chat.
+    fn notify_chat(&self, chat_id: i64, notice: Notice) {
+        let Some(sessions) = self.chats.get(&chat_id) else {
+            return;
+        };
+
+        sessions.iter()
+            .filter_map(|id| self.sessions.get(id))
+            .for_each(|session| session.control.do_send(SessionEvent::Notice(notice.clone())));
+    }
+
+    /// Sends the reply of a command to the session that issued it, if any.
+    fn reply(&self, session_id: Option<usize>, message: &str) {
+        if let Some(session_id) = session_id {
+            self.notify_session(session_id, Notice::CommandReply { message: message.to_owned() });
+        }
+    }
+
+    /// Broadcasts the stored client message to all the chat sessions that participate on the chat.
+    fn broadcast_message(&self, message_id: i64, message: &ClientMessage) {
         if let Some(sessions) = self.chats.get(&message.chat_id) {
             for id in sessions {
-                if *id == message.session_id {
+                if Some(*id) == message.session_id {
                     continue;
                 }
-                if let Some(addr) = self.sessions.get(id) {
-                    let _ = addr.do_send(FwdMessage {
+                if let Some(session) = self.sessions.get(id) {
+                    session.addr.do_send(FwdMessage {
+                        id: message_id,
                         message: message.content.clone(),
+                        payload_type: message.payload_type,
                         sender_id: message.sender_id,
                         sent_at: Utc::now().timestamp(),
                     });
@@ -99,30 +353,45 @@ impl Handler<Connect> for ChatServer {
     /// - Registers the chat id if not present
     /// - Receives the recent chat messages and send them to the session concurrently
     fn handle(&mut self, msg: Connect, _: &mut Self::Context) -> Self::Result {
-        // Register the session (client) to the server
+        // The server is shutting down, the session gets closed right away without being registered
         let session_id = self.rng.gen::<usize>();
-        self.sessions.insert(session_id, msg.addr.clone());
+        if let Some((reason, _)) = &self.shutdown {
+            msg.control.do_send(SessionEvent::Restart { reason: reason.clone() });
+            return session_id;
+        }
+
+        // Register the session (client) to the server
+        self.sessions.insert(session_id, SessionHandle {
+            addr: msg.addr.clone(),
+            control: msg.control,
+            user_id: msg.user_id,
+            credential: msg.credential,
+        });
 
         self.chats.entry(msg.chat_id).or_default().insert(session_id);
 
         // Retrieve the chat history and send it back to the client concurrently
+        let span = tracing::info_span!("chat_history", request_id = %msg.request_id, chat_id = msg.chat_id);
         let db_pool = self.db_pool.clone();
+        let cipher = self.cipher.clone();
         actix::spawn(async move {
-            let chat_messages = if let Ok(messages) = get_recent_messages(&db_pool, msg.chat_id, 50).await {
+            let chat_messages = if let Ok(messages) = get_recent_messages(&db_pool, &cipher, msg.chat_id, msg.user_id, 50).await {
                 messages
             } else {
-                log::error!("Error retrieving the chat history for {:}", msg.chat_id);
+                tracing::error!("Error retrieving the chat history for {:}", msg.chat_id);
                 Vec::new()
             };
 
             // Send the retrieved messages back to the session
             chat_messages.into_iter()
                 .for_each(|chat_message| msg.addr.do_send(FwdMessage {
+                    id: chat_message.id,
+                    payload_type: chat_message.payload_type(),
                     message: chat_message.message,
                     sender_id: chat_message.sender_id,
                     sent_at: chat_message.created_at.assume_utc().unix_timestamp()
                 }));
-        });
+        }.instrument(span));
 
         session_id
     }
@@ -151,21 +420,254 @@ impl Handler<Disconnect> for ChatServer {
 }
 
 impl Handler<ClientMessage> for ChatServer {
-    type Result = ();
+    type Result = ResponseActFuture<Self, Result<SendOutcome, SendError>>;
 
-    /// The server handles the connection requests from the client as follows:
+    /// The server handles the chat messages from the client as follows:
     ///
-    /// - Broadcasts the message to the active sessions of the chat
-    /// - Saves the message in the database concurrently
-    fn handle(&mut self, msg: ClientMessage, _: &mut Self::Context) -> Self::Result {
-        self.broadcast_message(&msg);
+    /// - Runs the slash commands, their replies are only sent back to the session
+    ///   (except for the scheduled and the ciphertext messages, which are sent as they are)
+    /// - Runs the message through the content filters, the rejections are reported back to the session
+    ///   (the ciphertext messages are only checked for their size)
+    /// - Saves the (possibly rewritten) message in the database concurrently, the scheduled messages
+    ///   are marked as sent along with it so they are never sent twice
+    /// - Broadcasts the stored message, along with its id, to the active sessions of the chat
+    /// - Queues the message for the webhooks of the participants, unless it is ciphertext
+    fn handle(&mut self, mut msg: ClientMessage, _: &mut Self::Context) -> Self::Result {
+        let span = tracing::info_span!(
+            "client_message",
+            request_id = msg.request_id.as_deref(),
+            chat_id = msg.chat_id,
+            sender_id = msg.sender_id,
+        );
+        let _entered = span.enter();
 
-        let db_pool = self.db_pool.clone();
-        actix::spawn(async move {
-            match add_message(&db_pool, msg.chat_id, msg.sender_id, msg.content.as_str()).await {
-                Err(_) => log::error!("Error adding chat message for {}", msg.chat_id),
-                Ok(_) => log::debug!("Message {:} is sent to chat {}", msg.content, msg.chat_id)
+        if self.shutdown.is_some() {
+            return Box::pin(fut::ready(Err(SendError::Unavailable)));
+        }
+        metrics::count_message("received");
+
+        let outcome = match (msg.scheduled_message_id, msg.payload_type) {
+            (Some(_), _) | (_, PayloadType::Ciphertext) => CommandOutcome::Send(msg.content.clone()),
+            (None, PayloadType::Text) => self.commands.dispatch(&CommandContext {
+                chat_id: msg.chat_id,
+                sender_id: msg.sender_id,
+                sender_username: &msg.sender_username,
+                args: "",
+                db_pool: &self.db_pool,
+            }, &msg.content),
+        };
+        // The commands rewrite the message too, e.g. `/me`, and the sender only has what they typed
+        let mut rewritten = false;
+        match outcome {
+            CommandOutcome::Send(content) => {
+                rewritten = content != msg.content;
+                msg.content = content;
             }
-        });
+            CommandOutcome::Reply(reply) => {
+                self.reply(msg.session_id, &reply);
+                return Box::pin(fut::ready(Ok(SendOutcome::Replied(reply))));
+            }
+            CommandOutcome::Task(task) => {
+                // The task writes to the database too, e.g. `/clear-history`, so the shutdown waits for it
+                self.pending_writes += 1;
+                let session_id = msg.session_id;
+                return Box::pin(task.into_actor(self).map(move |result, act, _| {
+                    act.finish_write();
+                    let reply = result.unwrap_or_else(|err| {
+                        tracing::error!("Error running the command in chat {}: {err}", msg.chat_id);
+                        "The command failed, try again later".into()
+                    });
+                    act.reply(session_id, &reply);
+                    Ok(SendOutcome::Replied(reply))
+                }));
+            }
+        }
+
+        let filtered = match msg.payload_type {
+            PayloadType::Text => self.filters.apply(msg.chat_id, msg.sender_id, msg.content.clone()).map(Some),
+            PayloadType::Ciphertext if msg.content.len() > MAX_CIPHERTEXT_LENGTH => Err(Rejection {
+                filter: "ciphertext_length",
+                reason: format!("The encrypted message can't be larger than {MAX_CIPHERTEXT_LENGTH} bytes"),
+            }),
+            PayloadType::Ciphertext => Ok(None),
+        };
+        let rewritten = match filtered {
+            Ok(Some(content)) if content != msg.content => {
+                msg.content = content;
+                true
+            }
+            Ok(_) => rewritten,
+            Err(rejection) => {
+                metrics::count_message("rejected");
+                if rejection.filter == "spam" {
+                    metrics::count_rate_limited("spam");
+                }
+                tracing::debug!("Message to chat {} rejected by the {} filter", msg.chat_id, rejection.filter);
+                if let Some(session_id) = msg.session_id {
+                    self.notify_session(session_id, Notice::MessageRejected { reason: rejection.reason.clone() });
+                }
+                return Box::pin(fut::ready(Err(SendError::Rejected(rejection.reason))));
+            }
+        };
+
+        self.pending_writes += 1;
+        let db_pool = self.db_pool.clone();
+        let cipher = self.cipher.clone();
+        Box::pin(async move {
+            let result = match msg.scheduled_message_id {
+                Some(scheduled_id) => scheduled::send_scheduled_message(&db_pool, &cipher, scheduled_id, msg.content.as_str()).await,
+                None => add_message(&db_pool, &cipher, msg.chat_id, msg.sender_id, msg.content.as_str(), msg.payload_type).await.map(Some),
+            };
+            (msg, result)
+        }
+            .instrument(span.clone())
+            .into_actor(self)
+            .map(|(msg, result), act, ctx| {
+                let outcome = match result {
+                    Err(ApiError::BadRequest(reason)) => {
+                        metrics::count_message("rejected");
+                        if let Some(session_id) = msg.session_id {
+                            act.notify_session(session_id, Notice::MessageRejected { reason: reason.clone() });
+                        }
+                        Err(SendError::Rejected(reason))
+                    }
+                    Err(_) => {
+                        metrics::count_message("failed");
+                        tracing::error!("Error adding chat message for {}", msg.chat_id);
+                        Err(SendError::Failed)
+                    }
+                    Ok(None) => Err(SendError::Skipped),
+                    Ok(Some(message_id)) => {
+                        metrics::count_message("persisted");
+                        tracing::debug!("Message {message_id} is sent to chat {}", msg.chat_id);
+                        act.broadcast_message(message_id, &msg);
+                        // The sender only has what they typed, they get the content the others see
+                        let filtered = rewritten.then(|| msg.content.clone());
+                        if let (Some(session_id), Some(message)) = (msg.session_id, filtered.clone()) {
+                            act.notify_session(session_id, Notice::MessageFiltered { message_id, message });
+                        }
+                        if msg.payload_type == PayloadType::Text {
+                            act.dispatch_webhook(message_id, msg, ctx);
+                        }
+                        Ok(SendOutcome::Sent(message_id, filtered))
+                    }
+                };
+
+                // The webhook event is queued first, so the shutdown waits for it too
+                act.finish_write();
+                outcome
+            }))
+    }
+}
+
+impl Handler<RevokeSessions> for ChatServer {
+    type Result = ();
+
+    /// The server handles the revocation requests as follows:
+    ///
+    /// - Asks the matching sessions of the user to terminate, they disconnect on their own when stopped
+    fn handle(&mut self, msg: RevokeSessions, _: &mut Self::Context) -> Self::Result {
+        self.sessions.values()
+            .filter(|session| session.user_id == msg.user_id)
+            .filter(|session| msg.credentials.as_ref()
+                .map_or(true, |credentials| credentials.contains(&session.credential)))
+            .for_each(|session| session.control.do_send(SessionEvent::Terminate {
+                reason: msg.reason.clone(),
+            }));
+    }
+}
+
+impl Handler<DeleteMessage> for ChatServer {
+    type Result = ();
+
+    /// The server handles the deleted messages as follows:
+    ///
+    /// - Notifies the active sessions of the chat, so they remove the message
+    fn handle(&mut self, msg: DeleteMessage, _: &mut Self::Context) -> Self::Result {
+        self.notify_chat(msg.chat_id, Notice::MessageDeleted { message_id: msg.message_id });
     }
-}
\ No newline at end of file
+}
+
+impl Handler<NotifyChat> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: NotifyChat, _: &mut Self::Context) -> Self::Result {
+        self.notify_chat(msg.chat_id, msg.notice);
+    }
+}
+
+impl Handler<Shutdown> for ChatServer {
+    type Result = ResponseFuture<()>;
+
+    /// The server handles the shutdown requests as follows:
+    ///
+    /// - Stops taking new messages and asks the active sessions to close for a restart
+    /// - Resolves once the messages being stored, and their webhook events, are done
+    fn handle(&mut self, msg: Shutdown, _: &mut Self::Context) -> Self::Result {
+        self.sessions.values()
+            .for_each(|session| session.control.do_send(SessionEvent::Restart { reason: msg.reason.clone() }));
+
+        let (sender, receiver) = oneshot::channel();
+        let (_, waiters) = self.shutdown.get_or_insert_with(|| (msg.reason, Vec::new()));
+        if self.pending_writes == 0 {
+            return Box::pin(async {});
+        }
+        waiters.push(sender);
+
+        Box::pin(async move {
+            let _ = receiver.await;
+        })
+    }
+}
+
+impl Handler<GetServerStats> for ChatServer {
+    type Result = MessageResult<GetServerStats>;
+
+    fn handle(&mut self, _: GetServerStats, _: &mut Self::Context) -> Self::Result {
+        let connected_users: HashSet<i64> = self.sessions.values()
+            .map(|session| session.user_id)
+            .collect();
+
+        MessageResult(ServerStats {
+            active_sessions: self.sessions.len(),
+            active_chats: self.chats.len(),
+            connected_users: connected_users.len(),
+            pending_writes: self.pending_writes,
+            shutting_down: self.shutdown.is_some(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn frame(envelope: impl Into<Envelope>) -> serde_json::Value {
+        serde_json::to_value(envelope.into()).unwrap()
+    }
+
+    #[test]
+    fn tags_the_chat_messages_with_the_message_type() {
+        let message = FwdMessage { id: 7, message: "hi".into(), payload_type: PayloadType::Text, sender_id: 2, sent_at: 1700000000 };
+
+        assert_eq!(frame(message), json!({
+            "type": "message",
+            "id": 7,
+            "message": "hi",
+            "payload_type": "text",
+            "sender_id": 2,
+            "sent_at": 1700000000,
+        }));
+    }
+
+    #[test]
+    fn tags_the_notices_with_their_type() {
+        assert_eq!(frame(Notice::MessageDeleted { message_id: 7 }), json!({ "type": "message_deleted", "message_id": 7 }));
+        assert_eq!(
+            frame(Notice::RetentionChanged { message_ttl_secs: None }),
+            json!({ "type": "retention_changed", "message_ttl_secs": null }),
+        );
+        assert_eq!(frame(Notice::EncryptionEnabled), json!({ "type": "encryption_enabled" }));
+    }
+}
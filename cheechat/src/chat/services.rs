@@ -1,53 +1,91 @@
-use crate::chat::db::{add_chat, get_chat, get_chat_overviews};
+use crate::chat::encryption::MessageCipher;
+use crate::chat::db::{add_chat, get_chat, get_chat_overviews, get_participated_chat, set_message_ttl};
 use crate::chat::models;
-use crate::chat::server::ChatServer;
-use crate::chat::session::ChatSession;
+use crate::chat::models::{CommandReply, MessageSent, PayloadType, SendMessage, SetRetention};
+use crate::chat::server::{ChatServer, ClientMessage, Notice, NotifyChat, SendError, SendOutcome};
+use crate::chat::session::{ChatSession, WebsocketConfig};
+use crate::errors::ApiError;
 use crate::users;
+use crate::users::AuthUser;
+use crate::webhooks;
+use crate::webhooks::WebhookEvent;
 use actix::Addr;
-use actix_session::Session;
 use actix_web::web::Path;
-use actix_web::{get, post, web, Error, HttpRequest, HttpResponse};
+use actix_web::{get, post, put, web, Error, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
 use sqlx::postgres::PgPool;
 use std::cmp::{max, min};
+use tracing_actix_web::RequestId;
 
 /// Service that handles the websocket connections request on the given chat
+#[utoipa::path(
+    tag = "chats",
+    params(("chat_id" = i64, Path, description = "The id of the chat")),
+    responses(
+        (status = 101, description = "Switches to the websocket protocol, the server writes the frames described by the `Envelope` schema"),
+        (status = 404, description = "No such chat, or the user doesn't participate in it"),
+    ),
+)]
 #[get("/ws/chat/{chat_id}")]
-async fn chat_route(
+pub async fn chat_route(
     path: Path<String>,
     req: HttpRequest,
     stream: web::Payload,
     srv: web::Data<Addr<ChatServer>>,
-    session: Session,
+    db_pool: web::Data<PgPool>,
+    ws_config: web::Data<WebsocketConfig>,
+    request_id: RequestId,
+    auth_user: AuthUser,
 ) -> Result<HttpResponse, Error> {
-    // Authenticate the user
-    let user_id = users::authenticate_user(session).await?;
-
     // Parse the chat id from the path parameter
-    let chat_id = path.into_inner().parse::<i64>().expect("failed to parse i64");
+    let chat_id = path.into_inner().parse::<i64>().map_err(|_| ApiError::NotFound)?;
+    let chat = get_participated_chat(db_pool.get_ref(), chat_id, auth_user.id).await?.ok_or(ApiError::NotFound)?;
 
     // Start the session actor with a temporary session id
-    ws::start(
+    ws::WsResponseBuilder::new(
         ChatSession {
             id: 0,
             chat_id,
-            sender_id: user_id,
+            sender_id: auth_user.id,
+            username: auth_user.user.username.clone(),
+            encrypted: chat.encrypted,
+            credential: auth_user.credential,
+            request_id: request_id.to_string(),
             addr_server: srv.get_ref().clone(),
         },
         &req,
         stream,
     )
+        .frame_size(ws_config.max_frame_size)
+        .start()
 }
 
 
 /// Service that handles the init chat request with the provided user.
 /// The server looks if there is already a chat between the users. If there isn't, it creates a new one.
+#[utoipa::path(
+    tag = "chats",
+    request_body = models::ChatRequest,
+    responses(
+        (status = 200, description = "The id of the chat", body = i64),
+        (status = 400, description = "The recipient is the current user", body = String, content_type = "text/plain"),
+        (status = 404, description = "No such recipient"),
+    ),
+)]
 #[post("/chats")]
-async fn init_chat(request: web::Json<models::ChatRequest>, session: Session, db_pool: web::Data<PgPool>) -> Result<HttpResponse, Error> {
-    let user_id = users::authenticate_user(session).await?;
+pub async fn init_chat(
+    request: web::Json<models::ChatRequest>,
+    auth_user: AuthUser,
+    db_pool: web::Data<PgPool>,
+    cipher: web::Data<MessageCipher>,
+) -> Result<HttpResponse, Error> {
+    let user_id = auth_user.id;
 
     let recipient = users::get_user(db_pool.get_ref(), &request.recipient).await?;
     let recipient_id = recipient.id;
+    if recipient_id == user_id {
+        return Err(ApiError::BadRequest("You can't start a chat with yourself".into()).into());
+    }
 
     // Sort the user ids before creating the chat
     let user1_id = min(user_id, recipient_id);
@@ -56,18 +94,112 @@ async fn init_chat(request: web::Json<models::ChatRequest>, session: Session, db
     let chat_id =  if let Some(chat) = get_chat(db_pool.get_ref(), (user1_id, user2_id)).await? {
         chat.id
     } else {
-        add_chat(db_pool.get_ref(), (user1_id, user2_id)).await?
+        let chat_id = add_chat(db_pool.get_ref(), (user1_id, user2_id)).await?;
+        webhooks::dispatch(db_pool.get_ref(), cipher.get_ref(), WebhookEvent::ChatCreated { chat_id, user_ids: [user1_id, user2_id] }).await;
+        chat_id
     };
 
     Ok(HttpResponse::Ok().json(chat_id))
 }
 
 /// Gets all the chats
+#[utoipa::path(
+    tag = "chats",
+    responses((status = 200, body = Vec<models::ChatOverview>)),
+)]
 #[get("/get-chats")]
-pub async fn get_chats(session: Session, db_pool: web::Data<PgPool>) -> Result<HttpResponse, Error> {
-    let user_id = users::authenticate_user(session).await?;
-
-    let chats = get_chat_overviews(db_pool.get_ref(), user_id).await?;
+pub async fn get_chats(
+    auth_user: AuthUser,
+    db_pool: web::Data<PgPool>,
+    cipher: web::Data<MessageCipher>,
+) -> Result<HttpResponse, Error> {
+    let chats = get_chat_overviews(db_pool.get_ref(), cipher.get_ref(), auth_user.id).await?;
 
     Ok(HttpResponse::Ok().json(chats))
 }
+
+/// Sends a message to the chat through the REST API, e.g. for the bots.
+/// The message goes through the same commands and filters and gets broadcast like the websocket messages,
+/// in the end-to-end encrypted chats it must be ciphertext.
+#[utoipa::path(
+    tag = "chats",
+    params(("chat_id" = i64, Path, description = "The id of the chat")),
+    request_body = SendMessage,
+    responses(
+        (status = 201, description = "The message is stored and broadcast", body = MessageSent),
+        (status = 200, description = "The message was a slash command", body = CommandReply),
+        (status = 400, description = "The message is empty or rejected by the content filters", body = String, content_type = "text/plain"),
+        (status = 404, description = "No such chat, or the user doesn't participate in it"),
+        (status = 503, description = "The server is restarting"),
+    ),
+)]
+#[post("/chats/{chat_id}/messages")]
+pub async fn send_message(
+    path: Path<i64>,
+    request: web::Json<SendMessage>,
+    auth_user: AuthUser,
+    db_pool: web::Data<PgPool>,
+    srv: web::Data<Addr<ChatServer>>,
+    request_id: RequestId,
+) -> Result<HttpResponse, Error> {
+    let chat = get_participated_chat(db_pool.get_ref(), path.into_inner(), auth_user.id).await?.ok_or(ApiError::NotFound)?;
+    let content = request.into_inner().message.trim().to_owned();
+    if content.is_empty() {
+        return Err(ApiError::BadRequest("The message can't be empty".into()).into());
+    }
+
+    let result = srv
+        .send(ClientMessage {
+            session_id: None,
+            content,
+            chat_id: chat.id,
+            sender_id: auth_user.id,
+            sender_username: auth_user.user.username.clone(),
+            scheduled_message_id: None,
+            payload_type: if chat.encrypted { PayloadType::Ciphertext } else { PayloadType::Text },
+            request_id: Some(request_id.to_string()),
+        })
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    match result {
+        Ok(SendOutcome::Sent(id, message)) => Ok(HttpResponse::Created().json(MessageSent { id, message })),
+        Ok(SendOutcome::Replied(reply)) => Ok(HttpResponse::Ok().json(CommandReply { reply })),
+        Err(SendError::Rejected(reason)) => Err(ApiError::BadRequest(reason).into()),
+        Err(SendError::Unavailable) => Err(actix_web::error::ErrorServiceUnavailable("The server is restarting")),
+        Err(SendError::Failed | SendError::Skipped) => Err(actix_web::error::ErrorInternalServerError("The message could not be sent")),
+    }
+}
+
+/// Sets the time after which the messages of the chat disappear, either participant may change it.
+/// The server wide retention policy still applies to the chats that keep their messages longer.
+#[utoipa::path(
+    tag = "chats",
+    params(("chat_id" = i64, Path, description = "The id of the chat")),
+    request_body = SetRetention,
+    responses(
+        (status = 204, description = "The retention is set"),
+        (status = 400, description = "The ttl is out of range", body = String, content_type = "text/plain"),
+        (status = 404, description = "No such chat, or the user doesn't participate in it"),
+    ),
+)]
+#[put("/chats/{chat_id}/retention")]
+pub async fn set_retention(
+    path: Path<i64>,
+    request: web::Json<SetRetention>,
+    auth_user: AuthUser,
+    db_pool: web::Data<PgPool>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> Result<HttpResponse, Error> {
+    let request = request.into_inner();
+    request.validate().map_err(ApiError::BadRequest)?;
+    let chat = get_participated_chat(db_pool.get_ref(), path.into_inner(), auth_user.id).await?.ok_or(ApiError::NotFound)?;
+
+    set_message_ttl(db_pool.get_ref(), chat.id, request.message_ttl_secs).await?;
+    srv.do_send(NotifyChat {
+        chat_id: chat.id,
+        notice: Notice::RetentionChanged { message_ttl_secs: request.message_ttl_secs },
+    });
+
+    Ok(HttpResponse::NoContent().finish())
+}
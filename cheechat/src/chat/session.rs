@@ -1,13 +1,29 @@
+use crate::chat::models::PayloadType;
 use crate::chat::server::*;
+use crate::users::Credential;
 use actix::{fut, Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, ContextFutureSpawner, Handler, Running, StreamHandler, WrapFuture};
 use actix_web_actors::ws;
-use actix_web_actors::ws::{Message, ProtocolError};
+use actix_web_actors::ws::{CloseCode, CloseReason, Message, ProtocolError};
+
+/// The limits of the websocket connections of the chat sessions.
+#[derive(Debug, Clone, Copy)]
+pub struct WebsocketConfig {
+    /// The maximum size of a frame in bytes.
+    pub max_frame_size: usize,
+}
 
 /// The chat session actor struct.
 pub struct ChatSession {
     pub id: usize,
     pub chat_id: i64,
     pub sender_id: i64,
+    pub username: String,
+    // Whether the chat is end-to-end encrypted, so the text frames carry ciphertext
+    pub encrypted: bool,
+    // The login session, or the API token, the connection got opened with
+    pub credential: Credential,
+    // The id of the request that opened the connection, it tags the messages of the session in the logs
+    pub request_id: String,
     // The address of the chat server actor, so it can send the chat requests
     pub addr_server: Addr<ChatServer>
 }
@@ -21,8 +37,12 @@ impl Actor for ChatSession {
     fn started(&mut self, ctx: &mut Self::Context) {
         let addr = ctx.address();
         self.addr_server.send(Connect {
-            addr: addr.recipient(),
+            addr: addr.clone().recipient(),
+            control: addr.recipient(),
             chat_id: self.chat_id,
+            user_id: self.sender_id,
+            credential: self.credential,
+            request_id: self.request_id.clone(),
         })
             // Chain the session id received with the replacement of the temporary one
             .into_actor(self)
@@ -48,17 +68,49 @@ impl Handler<FwdMessage> for ChatSession{
     type Result = ();
 
     /// The actor handles forwarded messages from the server as follows:
-    /// - Serialises the message to json and writes it in the websocket.
+    /// - Serialises the message to json, in its envelope, and writes it in the websocket.
     fn handle(&mut self, msg: FwdMessage, ctx: &mut Self::Context) -> Self::Result {
-        let msg = serde_json::to_string(&msg).unwrap();
+        let msg = serde_json::to_string(&Envelope::from(msg)).unwrap();
 
         ctx.text(msg);
     }
 }
 
+impl Handler<SessionEvent> for ChatSession {
+    type Result = ();
+
+    /// The actor handles control events from the server as follows:
+    /// - Closes the websocket connection with the provided reason on termination or restart
+    /// - Serialises the notices to json and writes them in the websocket
+    fn handle(&mut self, msg: SessionEvent, ctx: &mut Self::Context) -> Self::Result {
+        match msg {
+            SessionEvent::Terminate { reason } => {
+                ctx.close(Some(CloseReason {
+                    code: CloseCode::Policy,
+                    description: Some(reason),
+                }));
+                ctx.stop();
+            }
+            SessionEvent::Restart { reason } => {
+                ctx.close(Some(CloseReason {
+                    code: CloseCode::Restart,
+                    description: Some(reason),
+                }));
+                ctx.stop();
+            }
+            SessionEvent::Notice(notice) => {
+                if let Notice::EncryptionEnabled = notice {
+                    self.encrypted = true;
+                }
+                ctx.text(serde_json::to_string(&Envelope::from(notice)).unwrap());
+            }
+        }
+    }
+}
+
 impl StreamHandler<Result<Message, ProtocolError>> for ChatSession {
     /// The actor handles web socket messages as follows:
-    /// - Sends the text type messages to the chat server
+    /// - Sends the text type messages to the chat server, as ciphertext in the end-to-end encrypted chats
     /// - Close the web socket connection for the unexpected message types
     fn handle(&mut self, msg: Result<Message, ProtocolError>, ctx: &mut Self::Context) {
         let msg = match msg {
@@ -75,10 +127,14 @@ impl StreamHandler<Result<Message, ProtocolError>> for ChatSession {
             }
             Message::Text(msg) => {
                 self.addr_server.do_send(ClientMessage {
-                    session_id: self.id,
+                    session_id: Some(self.id),
                     content: msg.trim().to_owned(),
                     chat_id: self.chat_id,
                     sender_id: self.sender_id,
+                    sender_username: self.username.clone(),
+                    scheduled_message_id: None,
+                    payload_type: if self.encrypted { PayloadType::Ciphertext } else { PayloadType::Text },
+                    request_id: Some(self.request_id.clone()),
                 })
             }
             Message::Close(reason) => {
@@ -89,7 +145,7 @@ impl StreamHandler<Result<Message, ProtocolError>> for ChatSession {
             Message::Pong(_) => (),
             Message::Continuation(_) => ctx.stop(),
             Message::Binary(_) => {
-                log::error!("Unexpected message received, dropping the connection");
+                tracing::error!("Unexpected message received, dropping the connection");
                 ctx.stop();
             }
         }
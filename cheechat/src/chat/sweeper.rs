@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use std::future::Future;
+
+use actix::{Actor, ActorFutureExt, Addr, AsyncContext, Context, ContextFutureSpawner, WrapFuture};
+use sqlx::postgres::PgPool;
+
+use crate::app::config::RetentionConfig;
+use crate::chat::db::delete_expired_messages;
+use crate::chat::models::Expiry;
+use crate::chat::server::{ChatServer, Notice, NotifyChat};
+use crate::errors::ApiError;
+use crate::{reports, scheduled, webhooks};
+
+/// The number of messages deleted at once, so a large backlog doesn't lock the table for long.
+const BATCH_SIZE: i64 = 1000;
+
+/// The actor that hard deletes the messages that outlived the TTL of their chat or the server wide
+/// retention policy, and notifies the active sessions of the chats so they remove them too.
+///
+/// The copies of the messages expire the same way: the finished webhook deliveries and scheduled messages
+/// get deleted, and the snapshots of the resolved reports get cleared.
+pub struct MessageSweeper {
+    db_pool: PgPool,
+    chat_server: Addr<ChatServer>,
+    interval: Duration,
+    /// The maximum age of the messages in every chat, if any.
+    max_age_secs: Option<i64>,
+    /// Whether a sweep is running, so the sweeps don't overlap.
+    busy: bool,
+}
+
+impl MessageSweeper {
+    pub fn new(db_pool: PgPool, chat_server: Addr<ChatServer>, config: &RetentionConfig) -> Self {
+        Self {
+            db_pool,
+            chat_server,
+            interval: Duration::from_secs(config.sweep_interval_secs.max(1)),
+            max_age_secs: (config.max_message_age_days > 0).then(|| config.max_message_age_days as i64 * 24 * 3600),
+            busy: false,
+        }
+    }
+
+    fn sweep(&mut self, ctx: &mut Context<Self>) {
+        if self.busy {
+            return;
+        }
+        self.busy = true;
+
+        let db_pool = self.db_pool.clone();
+        let chat_server = self.chat_server.clone();
+        let rules: Vec<Expiry> = [Some(Expiry::ChatTtl), self.max_age_secs.map(Expiry::MaxAge)]
+            .into_iter()
+            .flatten()
+            .collect();
+        async move {
+            for expiry in rules {
+                match delete_expired(&db_pool, &chat_server, expiry).await {
+                    Ok(0) => (),
+                    Ok(deleted) => tracing::info!("Deleted {deleted} expired messages"),
+                    Err(err) => tracing::error!("Error deleting the expired messages: {err}"),
+                }
+                purge(expiry, "webhook deliveries", |limit| webhooks::delete_expired_deliveries(&db_pool, expiry, limit)).await;
+                purge(expiry, "scheduled messages", |limit| scheduled::delete_expired_scheduled_messages(&db_pool, expiry, limit)).await;
+                purge(expiry, "report snapshots", |limit| reports::clear_expired_snapshots(&db_pool, expiry, limit)).await;
+            }
+        }
+            .into_actor(self)
+            .map(|_, act, _| act.busy = false)
+            .spawn(ctx);
+    }
+}
+
+impl Actor for MessageSweeper {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.interval, |act, ctx| act.sweep(ctx));
+    }
+}
+
+/// Deletes the expired messages batch by batch and returns how many got deleted.
+async fn delete_expired(db_pool: &PgPool, chat_server: &Addr<ChatServer>, expiry: Expiry) -> Result<usize, ApiError> {
+    let mut deleted = 0;
+
+    loop {
+        let messages = delete_expired_messages(db_pool, expiry, BATCH_SIZE).await?;
+        deleted += messages.len();
+        let done = (messages.len() as i64) < BATCH_SIZE;
+
+        let mut chats: HashMap<i64, Vec<i64>> = HashMap::new();
+        for message in messages {
+            chats.entry(message.chat_id).or_default().push(message.id);
+        }
+        for (chat_id, message_ids) in chats {
+            chat_server.do_send(NotifyChat { chat_id, notice: Notice::MessagesExpired { message_ids } });
+        }
+
+        if done {
+            return Ok(deleted);
+        }
+    }
+}
+
+/// Purges the expired copies of the messages batch by batch, with the given purge of one batch.
+async fn purge<F, Fut>(expiry: Expiry, name: &str, purge_batch: F)
+where
+    F: Fn(i64) -> Fut,
+    Fut: Future<Output = Result<u64, ApiError>>,
+{
+    let mut purged = 0;
+    loop {
+        match purge_batch(BATCH_SIZE).await {
+            Ok(count) => {
+                purged += count;
+                if (count as i64) < BATCH_SIZE {
+                    break;
+                }
+            }
+            Err(err) => {
+                tracing::error!("Error purging the expired {name} ({expiry:?}): {err}");
+                break;
+            }
+        }
+    }
+
+    if purged > 0 {
+        tracing::info!("Purged {purged} expired {name}");
+    }
+}
@@ -0,0 +1,540 @@
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+
+use bcrypt::hash;
+use clap::{Args, Parser, Subcommand};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::Serialize;
+use sqlx::postgres::PgPool;
+
+use crate::app;
+use crate::app::config::AppConfig;
+use crate::app::migrations;
+use crate::errors::ApiError;
+use crate::{chat, docs, reports, scheduled, users, webhooks};
+
+/// The command line interface of the cheechat binary.
+#[derive(Parser, Debug)]
+#[command(name = "cheechat", version, about = "The Cheechat chat server")]
+pub struct Cli {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// The settings given on the command line, they override the configuration file and the environment variables.
+#[derive(Args, Debug)]
+pub struct ConfigArgs {
+    /// The TOML configuration file, `cheechat.toml` is read if it exists.
+    #[arg(long = "config", value_name = "FILE", global = true)]
+    pub file: Option<PathBuf>,
+    #[arg(long, global = true)]
+    pub server_addr: Option<String>,
+    #[arg(long, global = true)]
+    pub database_url: Option<String>,
+    #[arg(long, global = true)]
+    pub redis_addr: Option<String>,
+    /// Overrides any setting, e.g. `--set server.workers=4`. The values are parsed as json if possible.
+    #[arg(long = "set", value_name = "KEY=VALUE", global = true)]
+    pub settings: Vec<String>,
+}
+
+impl ConfigArgs {
+    /// Builds the overrides as a json object shaped like the configuration.
+    pub fn overrides(&self) -> Result<serde_json::Value, String> {
+        let mut overrides = serde_json::Map::new();
+        let flags = [
+            ("server_addr", &self.server_addr),
+            ("database_url", &self.database_url),
+            ("redis_addr", &self.redis_addr),
+        ];
+        for (key, value) in flags {
+            if let Some(value) = value {
+                overrides.insert(key.into(), value.clone().into());
+            }
+        }
+
+        for setting in &self.settings {
+            let (path, value) = setting.split_once('=')
+                .ok_or_else(|| format!("The setting {setting} must be formatted as KEY=VALUE"))?;
+            let value = serde_json::from_str(value).unwrap_or_else(|_| serde_json::Value::from(value));
+
+            // Walk down the dotted path, creating the missing sections
+            let mut keys: Vec<&str> = path.split('.').map(str::trim).collect();
+            let last = keys.pop().filter(|key| !key.is_empty())
+                .ok_or_else(|| format!("The setting {setting} has no key"))?;
+            let mut section = &mut overrides;
+            for key in keys {
+                section = section.entry(key)
+                    .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()))
+                    .as_object_mut()
+                    .ok_or_else(|| format!("The setting {path} overlaps another one"))?;
+            }
+            section.insert(last.into(), value);
+        }
+
+        Ok(serde_json::Value::Object(overrides))
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Runs the chat server (default).
+    Serve,
+    /// Applies the pending database migrations.
+    Migrate {
+        #[command(subcommand)]
+        action: Option<MigrateAction>,
+    },
+    /// Creates a new user.
+    CreateUser {
+        #[arg(long)]
+        username: String,
+        #[arg(long)]
+        email: String,
+        #[arg(long)]
+        first_name: String,
+        #[arg(long)]
+        last_name: String,
+        /// The password of the user, a random one is generated and printed if not provided.
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// Resets the password of a user and revokes the login sessions.
+    ResetPassword {
+        #[arg(long)]
+        username: String,
+        /// The new password, a random one is generated and printed if not provided.
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// Suspends a user and revokes the login sessions.
+    ///
+    /// The websocket connections that are already open on a running server stay open until they get closed.
+    BanUser {
+        #[arg(long)]
+        username: String,
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Lifts the suspension of a user.
+    UnbanUser {
+        #[arg(long)]
+        username: String,
+    },
+    /// Sets the role of a user, e.g. to bootstrap the first admin.
+    SetRole {
+        #[arg(long)]
+        username: String,
+        /// One of user, moderator or admin.
+        #[arg(long)]
+        role: users::Role,
+    },
+    /// Lists the chats of a user.
+    ListChats {
+        /// The username of the user.
+        #[arg(long)]
+        user: String,
+    },
+    /// Exports the chat with all the messages as json.
+    ExportChat {
+        chat_id: i64,
+        /// The file to write the export to, defaults to the standard output.
+        #[arg(long, short)]
+        output: Option<String>,
+    },
+    /// Shows the user, chat and message counters.
+    Stats,
+    /// Inspects the configuration.
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Prints the OpenAPI document of the REST API, e.g. to generate a client.
+    Openapi,
+    /// Generates a new key for the session cookies.
+    GenerateSessionKey,
+    /// Generates a new master key for the encryption of the messages at rest.
+    GenerateEncryptionKey,
+    /// Re-encrypts the stored messages with the active master key, e.g. after a key rotation.
+    ///
+    /// Covers the chat messages, the scheduled messages, the reported message snapshots and the webhook payloads.
+    /// Only the data keys of the encrypted messages get re-wrapped, the plain text messages get encrypted.
+    /// The previous keys must stay configured until the job completed.
+    ReencryptMessages {
+        /// The number of messages re-encrypted per batch.
+        #[arg(long, default_value_t = 500)]
+        batch_size: i64,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigAction {
+    /// Loads and validates the configuration, then prints it with the secrets redacted.
+    Check,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MigrateAction {
+    /// Shows the applied and pending migrations without applying them.
+    Status,
+}
+
+#[derive(Serialize, Debug)]
+struct ChatExport {
+    id: i64,
+    created_at: i64,
+    participants: Vec<users::UserInfo>,
+    messages: Vec<MessageExport>,
+}
+
+#[derive(Serialize, Debug)]
+struct MessageExport {
+    id: i64,
+    sender_id: i64,
+    message: String,
+    sent_at: i64,
+}
+
+/// Runs the requested command.
+pub async fn run(cli: Cli, config: AppConfig) -> io::Result<()> {
+    let command = match cli.command {
+        None | Some(Command::Serve) => return app::run(config).await,
+        Some(Command::Config { action: ConfigAction::Check }) => return check_config(&config),
+        Some(Command::Openapi) => {
+            println!("{}", docs::openapi().to_pretty_json().map_err(io::Error::other)?);
+            return Ok(());
+        }
+        Some(Command::GenerateSessionKey) => {
+            println!("{}", app::session::SessionKeys::generate_key());
+            return Ok(());
+        }
+        Some(Command::GenerateEncryptionKey) => {
+            println!("{}", chat::MessageCipher::generate_key());
+            return Ok(());
+        }
+        Some(command) => command,
+    };
+
+    let cipher = chat::MessageCipher::from_config(&config.encryption)?;
+
+    let db_pool = app::create_db_pool(&config).await.map_err(io::Error::other)?;
+
+    // The migrate command is the only one allowed to run against an incompatible schema
+    if !matches!(command, Command::Migrate { .. }) {
+        migrations::ensure_compatible(&db_pool, false).await.map_err(io::Error::other)?;
+    }
+
+    let result = match command {
+        Command::Migrate { action } => return migrate(&db_pool, action).await,
+        Command::CreateUser { username, email, first_name, last_name, password } => {
+            create_user(&db_pool, &cipher, username, email, first_name, last_name, password).await
+        }
+        Command::ResetPassword { username, password } => reset_password(&db_pool, &username, password).await,
+        Command::BanUser { username, reason } => ban_user(&db_pool, &username, reason.as_deref()).await,
+        Command::UnbanUser { username } => unban_user(&db_pool, &username).await,
+        Command::SetRole { username, role } => set_role(&db_pool, &username, role).await,
+        Command::ListChats { user } => list_chats(&db_pool, &cipher, &user).await,
+        Command::ExportChat { chat_id, output } => return export_chat(&db_pool, &cipher, chat_id, output.as_deref()).await,
+        Command::Stats => stats(&db_pool).await,
+        Command::ReencryptMessages { batch_size } => reencrypt_messages(&db_pool, &cipher, batch_size.max(1)).await,
+        Command::Serve | Command::Config { .. } | Command::Openapi | Command::GenerateSessionKey | Command::GenerateEncryptionKey => unreachable!("The command is handled above"),
+    };
+
+    result.map_err(io::Error::other)
+}
+
+/// Also loads the keys, filters and certificates the server would load, so their errors show up too.
+fn check_config(config: &AppConfig) -> io::Result<()> {
+    app::session::SessionKeys::from_config(&config.session)?;
+    chat::MessageCipher::from_config(&config.encryption)?;
+    chat::FilterPipeline::from_config(&config.filters)?;
+    app::tls::load_server_config(&config.server.tls)?;
+
+    println!("{}", serde_json::to_string_pretty(config).map_err(io::Error::other)?);
+    Ok(())
+}
+
+async fn migrate(db_pool: &PgPool, action: Option<MigrateAction>) -> io::Result<()> {
+    if action.is_none() {
+        migrations::run(db_pool).await.map_err(io::Error::other)?;
+    }
+
+    let status = migrations::status(db_pool).await.map_err(io::Error::other)?;
+    for migration in &status.migrations {
+        let state = if migration.applied { "applied" } else { "pending" };
+        println!("{:>4} {:<40} {}", migration.version, migration.description, state);
+    }
+    for version in &status.unknown_versions {
+        println!("{:>4} {:<40} unknown to this binary", version, "");
+    }
+
+    if status.is_up_to_date() {
+        println!("The database schema is up to date");
+    }
+
+    Ok(())
+}
+
+async fn create_user(
+    db_pool: &PgPool,
+    cipher: &chat::MessageCipher,
+    username: String,
+    email: String,
+    first_name: String,
+    last_name: String,
+    password: Option<String>,
+) -> Result<(), ApiError> {
+    let password = password_or_random(password);
+    let user = users::RegisterUser {
+        email,
+        first_name,
+        last_name,
+        password: hash(&password, 10).unwrap(),
+        username,
+    };
+
+    let username = user.username.clone();
+    let id = users::add_user(db_pool, user).await?;
+    webhooks::dispatch(db_pool, cipher, webhooks::WebhookEvent::UserRegistered { user_id: id, username }).await;
+    println!("Created user {id} with password: {password}");
+
+    Ok(())
+}
+
+async fn reset_password(db_pool: &PgPool, username: &str, password: Option<String>) -> Result<(), ApiError> {
+    let user = users::get_user(db_pool, username).await?;
+    let password = password_or_random(password);
+
+    users::update_password(db_pool, user.id, &hash(&password, 10).unwrap()).await?;
+    let revoked = users::revoke_user_sessions(db_pool, user.id, None).await?;
+    println!("Reset the password of {username} to: {password} ({} sessions revoked)", revoked.len());
+
+    Ok(())
+}
+
+async fn ban_user(db_pool: &PgPool, username: &str, reason: Option<&str>) -> Result<(), ApiError> {
+    let user = users::get_user(db_pool, username).await?;
+
+    let revoked = users::suspend_user(db_pool, user.id, reason).await?;
+    println!("Suspended {username} ({} sessions revoked)", revoked.len());
+
+    Ok(())
+}
+
+async fn unban_user(db_pool: &PgPool, username: &str) -> Result<(), ApiError> {
+    let user = users::get_user(db_pool, username).await?;
+
+    users::unsuspend_user(db_pool, user.id).await?;
+    println!("Lifted the suspension of {username}");
+
+    Ok(())
+}
+
+async fn set_role(db_pool: &PgPool, username: &str, role: users::Role) -> Result<(), ApiError> {
+    let user = users::get_user(db_pool, username).await?;
+
+    users::set_user_role(db_pool, user.id, role).await?;
+    println!("Set the role of {username} to {role}");
+
+    Ok(())
+}
+
+async fn list_chats(db_pool: &PgPool, cipher: &chat::MessageCipher, username: &str) -> Result<(), ApiError> {
+    let user = users::get_user(db_pool, username).await?;
+
+    let chats = chat::get_chat_overviews(db_pool, cipher, user.id).await?;
+    println!("{:>8}  {:<50}  {}", "CHAT", "WITH", "LAST MESSAGE AT");
+    for chat in chats {
+        let last_message_at = chat.last_message_at
+            .map(|timestamp| timestamp.to_string())
+            .unwrap_or_else(|| "-".into());
+        println!("{:>8}  {:<50}  {}", chat.chat_id, chat.other_user.username, last_message_at);
+    }
+
+    Ok(())
+}
+
+/// Exports the chat as JSON, the errors of the output file are reported as they are.
+async fn export_chat(db_pool: &PgPool, cipher: &chat::MessageCipher, chat_id: i64, output: Option<&str>) -> io::Result<()> {
+    let chat = chat::get_chat_by_id(db_pool, chat_id)
+        .await
+        .map_err(io::Error::other)?
+        .ok_or_else(|| io::Error::other(ApiError::NotFound))?;
+
+    let mut participants = Vec::new();
+    for user_id in [chat.user1_id, chat.user2_id] {
+        let user = users::get_user_by_id(db_pool, user_id)
+            .await
+            .map_err(io::Error::other)?
+            .ok_or_else(|| io::Error::other(ApiError::NotFound))?;
+        participants.push(users::UserInfo::from_user(user));
+    }
+
+    let messages = chat::get_messages(db_pool, cipher, chat_id).await.map_err(io::Error::other)?
+        .into_iter()
+        .map(|message| MessageExport {
+            id: message.id,
+            sender_id: message.sender_id,
+            message: message.message,
+            sent_at: message.created_at.assume_utc().unix_timestamp(),
+        })
+        .collect();
+
+    let export = ChatExport {
+        id: chat.id,
+        created_at: chat.created_at.assume_utc().unix_timestamp(),
+        participants,
+        messages,
+    };
+    let json = serde_json::to_string_pretty(&export)?;
+
+    match output {
+        Some(path) => {
+            std::fs::write(path, json)?;
+            println!("Exported chat {chat_id} to {path}");
+        }
+        None => println!("{json}"),
+    }
+
+    Ok(())
+}
+
+async fn stats(db_pool: &PgPool) -> Result<(), ApiError> {
+    let users = users::get_user_stats(db_pool).await?;
+    let chats = chat::get_chat_stats(db_pool).await?;
+
+    println!("{:<22}{}", "Users:", users.total);
+    println!("{:<22}{}", "Suspended users:", users.suspended);
+    println!("{:<22}{}", "Deleted users:", users.deleted);
+    println!("{:<22}{}", "Chats:", chats.chats);
+    println!("{:<22}{}", "Messages:", chats.messages);
+    println!("{:<22}{}", "Messages (last 24h):", chats.messages_last_day);
+
+    Ok(())
+}
+
+async fn reencrypt_messages(db_pool: &PgPool, cipher: &chat::MessageCipher, batch_size: i64) -> Result<(), ApiError> {
+    let Some(key_id) = cipher.active_key_id() else {
+        return Err(ApiError::BadRequest("There is no active encryption key, set ENCRYPTION__ACTIVE_KEY_ID".into()));
+    };
+
+    reseal_rows(
+        cipher,
+        "messages",
+        |after_id| chat::get_messages_to_reseal(db_pool, key_id, after_id, batch_size),
+        |id, sealed| async move { chat::update_sealed_message(db_pool, id, &sealed).await },
+    ).await?;
+    reseal_rows(
+        cipher,
+        "scheduled messages",
+        |after_id| scheduled::get_scheduled_messages_to_reseal(db_pool, key_id, after_id, batch_size),
+        |id, sealed| async move { scheduled::update_sealed_scheduled_message(db_pool, id, &sealed).await },
+    ).await?;
+    reseal_rows(
+        cipher,
+        "report snapshots",
+        |after_id| reports::get_reports_to_reseal(db_pool, key_id, after_id, batch_size),
+        |id, sealed| async move { reports::update_sealed_report(db_pool, id, &sealed).await },
+    ).await?;
+    reseal_rows(
+        cipher,
+        "webhook payloads",
+        |after_id| webhooks::get_deliveries_to_reseal(db_pool, key_id, after_id, batch_size),
+        |id, sealed| async move { webhooks::update_sealed_delivery(db_pool, id, &sealed).await },
+    ).await
+}
+
+/// Re-encrypts the rows of one table in batches, the rows are fetched after the id of the last batch.
+async fn reseal_rows<Fetch, FetchFut, Update, UpdateFut>(
+    cipher: &chat::MessageCipher,
+    name: &str,
+    fetch: Fetch,
+    update: Update,
+) -> Result<(), ApiError>
+where
+    Fetch: Fn(i64) -> FetchFut,
+    FetchFut: Future<Output = Result<Vec<chat::SealedRow>, ApiError>>,
+    Update: Fn(i64, chat::SealedMessage) -> UpdateFut,
+    UpdateFut: Future<Output = Result<(), ApiError>>,
+{
+    let (mut resealed, mut failed, mut after_id) = (0, 0, 0);
+    loop {
+        let rows = fetch(after_id).await?;
+        let Some(last) = rows.last() else {
+            break;
+        };
+        after_id = last.id;
+
+        for row in rows {
+            match cipher.reseal(row.sealed, &row.context) {
+                Ok(sealed) => {
+                    update(row.id, sealed).await?;
+                    resealed += 1;
+                }
+                Err(_) => {
+                    eprintln!("Row {} of the {name} could not be re-encrypted, is its key still configured?", row.id);
+                    failed += 1;
+                }
+            }
+        }
+    }
+    println!("Re-encrypted {resealed} {name} with the key {} ({failed} failed)", cipher.active_key_id().unwrap_or_default());
+
+    Ok(())
+}
+
+/// Returns the provided password or generates a random one.
+fn password_or_random(password: Option<String>) -> String {
+    password.unwrap_or_else(|| {
+        rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(16)
+            .map(char::from)
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn overrides(args: &[&str]) -> Result<serde_json::Value, String> {
+        Cli::try_parse_from(["cheechat"].iter().chain(args)).unwrap().config.overrides()
+    }
+
+    #[test]
+    fn nests_the_dotted_settings_under_their_sections() {
+        let overrides = overrides(&[
+            "--database-url", "postgres://db/chat",
+            "--set", "server.workers=4",
+            "--set", "server.tls.cert_path=cert.pem",
+            "--set", "cors.allowed_origins=[\"https://chat.example.com\"]",
+        ]);
+
+        assert_eq!(overrides, Ok(json!({
+            "database_url": "postgres://db/chat",
+            "server": { "workers": 4, "tls": { "cert_path": "cert.pem" } },
+            "cors": { "allowed_origins": ["https://chat.example.com"] },
+        })));
+    }
+
+    #[test]
+    fn the_settings_override_the_flags() {
+        assert_eq!(
+            overrides(&["--server-addr", "0.0.0.0:80", "--set", "server_addr=127.0.0.1:8080"]),
+            Ok(json!({ "server_addr": "127.0.0.1:8080" })),
+        );
+    }
+
+    #[test]
+    fn rejects_the_malformed_settings() {
+        assert!(overrides(&["--set", "server.workers"]).is_err());
+        assert!(overrides(&["--set", "server.=4"]).is_err());
+        assert!(overrides(&["--set", "app_name=chat", "--set", "app_name.suffix=x"]).is_err());
+    }
+}
@@ -0,0 +1,11 @@
+use actix_web::web;
+use utoipa_swagger_ui::SwaggerUi;
+
+mod openapi;
+
+pub use openapi::*;
+
+/// Registers the OpenAPI document and the docs UI, they are reachable without a session.
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(SwaggerUi::new("/docs/{_:.*}").url("/openapi.json", openapi()));
+}
@@ -0,0 +1,50 @@
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::{audit, bots, chat, e2ee, health, reports, scheduled, users, webhooks};
+
+/// The root of the OpenAPI document, the routes are described by their modules.
+///
+/// Every route requires the session cookie or the API token of a bot, unless it says otherwise.
+#[derive(OpenApi)]
+#[openapi(
+    info(description = "The REST API of the chat server. The chat messages are exchanged over the websocket \
+        of `/ws/chat/{chat_id}`, its frames are described by the `Envelope` schema."),
+    modifiers(&SecuritySchemes),
+    security(("session_cookie" = []), ("bot_token" = [])),
+)]
+struct ApiDoc;
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        // The name of the cookie is configurable, `id` is the default one
+        components.add_security_scheme(
+            "session_cookie",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::with_description("id", "Set by the login"))),
+        );
+        components.add_security_scheme("bot_token", SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)));
+    }
+}
+
+/// Builds the OpenAPI document of the client facing routes, along with the schema of the websocket frames.
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    let mut openapi = ApiDoc::openapi();
+    for module in [
+        users::ApiDoc::openapi(),
+        audit::ApiDoc::openapi(),
+        chat::ApiDoc::openapi(),
+        e2ee::ApiDoc::openapi(),
+        scheduled::ApiDoc::openapi(),
+        reports::ApiDoc::openapi(),
+        webhooks::ApiDoc::openapi(),
+        bots::ApiDoc::openapi(),
+        health::ApiDoc::openapi(),
+    ] {
+        openapi.merge(module);
+    }
+
+    openapi
+}
@@ -0,0 +1,94 @@
+use crate::e2ee::models::{OneTimePrekey, UploadKeys, UserKeys};
+use crate::errors::ApiError;
+use sqlx::postgres::PgPool;
+use tracing::instrument;
+
+/// Replaces the public keys of the user and adds the one-time prekeys, the ones with known ids are ignored
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn set_user_keys(pool: &PgPool, user_id: i64, keys: &UploadKeys) -> Result<(), ApiError> {
+    let key_ids: Vec<i64> = keys.one_time_prekeys.iter().map(|prekey| prekey.key_id).collect();
+    let public_keys: Vec<String> = keys.one_time_prekeys.iter().map(|prekey| prekey.public_key.clone()).collect();
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+            r#"
+            INSERT INTO cheechat.user_keys (user_id, identity_key, signed_prekey_id, signed_prekey, signed_prekey_signature)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (user_id) DO UPDATE SET
+                identity_key = EXCLUDED.identity_key,
+                signed_prekey_id = EXCLUDED.signed_prekey_id,
+                signed_prekey = EXCLUDED.signed_prekey,
+                signed_prekey_signature = EXCLUDED.signed_prekey_signature,
+                updated_at = CURRENT_TIMESTAMP
+            "#,
+            user_id,
+            keys.identity_key,
+            keys.signed_prekey.key_id,
+            keys.signed_prekey.public_key,
+            keys.signed_prekey.signature
+        )
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+            r#"
+            INSERT INTO cheechat.one_time_prekeys (user_id, key_id, public_key)
+            SELECT $1, UNNEST($2::BIGINT[]), UNNEST($3::TEXT[])
+            ON CONFLICT (user_id, key_id) DO NOTHING
+            "#,
+            user_id,
+            &key_ids,
+            &public_keys
+        )
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Retrieves the public keys of the user
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn get_user_keys(pool: &PgPool, user_id: i64) -> Result<Option<UserKeys>, ApiError> {
+    let keys = sqlx::query_as!(UserKeys, "SELECT * FROM cheechat.user_keys WHERE user_id = $1", user_id)
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(keys)
+}
+
+/// Counts the one-time prekeys of the user that are left to hand out
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn count_one_time_prekeys(pool: &PgPool, user_id: i64) -> Result<i64, ApiError> {
+    let row = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM cheechat.one_time_prekeys WHERE user_id = $1"#,
+        user_id
+    )
+        .fetch_one(pool)
+        .await?;
+
+    Ok(row.count)
+}
+
+/// Takes the oldest one-time prekey of the user, so it is never handed out twice
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn take_one_time_prekey(pool: &PgPool, user_id: i64) -> Result<Option<OneTimePrekey>, ApiError> {
+    let prekey = sqlx::query_as!(
+        OneTimePrekey,
+        r#"
+        DELETE FROM cheechat.one_time_prekeys
+        WHERE id = (
+            SELECT id FROM cheechat.one_time_prekeys
+            WHERE user_id = $1
+            ORDER BY id
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING *
+        "#,
+        user_id
+    )
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(prekey)
+}
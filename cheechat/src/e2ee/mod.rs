@@ -0,0 +1,29 @@
+use actix_web::web;
+use utoipa::OpenApi;
+
+mod services;
+mod db;
+mod models;
+
+pub use db::*;
+pub use models::*;
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(services::upload_keys);
+    cfg.service(services::get_keys_status);
+    cfg.service(services::get_key_bundle);
+    cfg.service(services::enable_encryption);
+}
+
+/// The OpenAPI description of the routes.
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        services::upload_keys,
+        services::get_keys_status,
+        services::get_key_bundle,
+        services::enable_encryption,
+    ),
+    tags((name = "encryption", description = "The public keys of the users and the end-to-end encrypted chats")),
+)]
+pub struct ApiDoc;
@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use sqlx;
+use sqlx::types::time::PrimitiveDateTime;
+
+/// The long lived public keys of a user. The server never sees the private keys.
+#[derive(Debug, sqlx::FromRow)]
+pub struct UserKeys {
+    pub user_id: i64,
+    pub identity_key: String,
+    pub signed_prekey_id: i64,
+    pub signed_prekey: String,
+    /// The signature of the signed prekey by the identity key.
+    pub signed_prekey_signature: String,
+    pub updated_at: PrimitiveDateTime,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct OneTimePrekey {
+    pub id: i64,
+    pub user_id: i64,
+    pub key_id: i64,
+    pub public_key: String,
+    pub created_at: PrimitiveDateTime,
+}
+
+/// A public prekey, encoded as the client sees fit (e.g. base64).
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct Prekey {
+    pub key_id: i64,
+    pub public_key: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct SignedPrekey {
+    pub key_id: i64,
+    pub public_key: String,
+    pub signature: String,
+}
+
+/// The public keys of the current user, the one-time prekeys get added to the ones left.
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct UploadKeys {
+    pub identity_key: String,
+    pub signed_prekey: SignedPrekey,
+    #[serde(default)]
+    pub one_time_prekeys: Vec<Prekey>,
+}
+
+impl UploadKeys {
+    const MAX_KEY_LENGTH: usize = 1024;
+    const MAX_ONE_TIME_PREKEYS: usize = 100;
+
+    pub fn validate(&self) -> Result<(), String> {
+        let keys = [
+            &self.identity_key,
+            &self.signed_prekey.public_key,
+            &self.signed_prekey.signature,
+        ];
+        let prekeys = self.one_time_prekeys.iter().map(|prekey| &prekey.public_key);
+
+        if keys.into_iter().chain(prekeys).any(|key| key.is_empty() || key.len() > Self::MAX_KEY_LENGTH) {
+            return Err(format!("The keys must be between 1 and {} characters long", Self::MAX_KEY_LENGTH));
+        }
+        if self.one_time_prekeys.len() > Self::MAX_ONE_TIME_PREKEYS {
+            return Err(format!("At most {} one-time prekeys can be uploaded at once", Self::MAX_ONE_TIME_PREKEYS));
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether the current user uploaded the keys, and how many one-time prekeys are left to hand out.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct KeysStatus {
+    pub uploaded: bool,
+    pub one_time_prekeys: i64,
+}
+
+/// The public keys of a user that another user needs to set up an encrypted session with them.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct KeyBundle {
+    pub user_id: i64,
+    pub identity_key: String,
+    pub signed_prekey: SignedPrekey,
+    /// Handed out once, none when the user ran out of them.
+    pub one_time_prekey: Option<Prekey>,
+}
+
+impl KeyBundle {
+    pub fn from_keys(keys: UserKeys, one_time_prekey: Option<OneTimePrekey>) -> Self {
+        Self {
+            user_id: keys.user_id,
+            identity_key: keys.identity_key,
+            signed_prekey: SignedPrekey {
+                key_id: keys.signed_prekey_id,
+                public_key: keys.signed_prekey,
+                signature: keys.signed_prekey_signature,
+            },
+            one_time_prekey: one_time_prekey.map(|prekey| Prekey {
+                key_id: prekey.key_id,
+                public_key: prekey.public_key,
+            }),
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use actix::Addr;
+use actix_web::web::Path;
+use actix_web::{get, post, put, web, Error, HttpResponse};
+use redis::aio::ConnectionManager;
+use sqlx::postgres::PgPool;
+
+use crate::chat;
+use crate::chat::{get_participated_chat, ChatServer, Notice, NotifyChat};
+use crate::e2ee::db;
+use crate::e2ee::models::{KeyBundle, KeysStatus, UploadKeys};
+use crate::errors::ApiError;
+use crate::metrics;
+use crate::users::AuthUser;
+
+/// The key bundles a user may request per window, a client needs one per new session with another user.
+const KEY_BUNDLE_LIMIT: i64 = 30;
+const KEY_BUNDLE_WINDOW_SECS: i64 = 3600;
+
+/// Uploads the public keys of the current user along with a batch of one-time prekeys
+#[utoipa::path(
+    tag = "encryption",
+    request_body = UploadKeys,
+    responses(
+        (status = 200, body = KeysStatus),
+        (status = 400, description = "Invalid keys", body = String, content_type = "text/plain"),
+    ),
+)]
+#[put("/keys")]
+pub async fn upload_keys(
+    auth_user: AuthUser,
+    request: web::Json<UploadKeys>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, Error> {
+    let request = request.into_inner();
+    request.validate().map_err(ApiError::BadRequest)?;
+
+    db::set_user_keys(db_pool.get_ref(), auth_user.id, &request).await?;
+    let one_time_prekeys = db::count_one_time_prekeys(db_pool.get_ref(), auth_user.id).await?;
+
+    Ok(HttpResponse::Ok().json(KeysStatus { uploaded: true, one_time_prekeys }))
+}
+
+/// Gets whether the current user uploaded the keys, so the clients know when to replenish the one-time prekeys
+#[utoipa::path(
+    tag = "encryption",
+    responses((status = 200, body = KeysStatus)),
+)]
+#[get("/keys")]
+pub async fn get_keys_status(auth_user: AuthUser, db_pool: web::Data<PgPool>) -> Result<HttpResponse, Error> {
+    let uploaded = db::get_user_keys(db_pool.get_ref(), auth_user.id).await?.is_some();
+    let one_time_prekeys = db::count_one_time_prekeys(db_pool.get_ref(), auth_user.id).await?;
+
+    Ok(HttpResponse::Ok().json(KeysStatus { uploaded, one_time_prekeys }))
+}
+
+/// Gets the key bundle of the other participant of the chat, handing out one of their one-time prekeys
+#[utoipa::path(
+    tag = "encryption",
+    params(("chat_id" = i64, Path, description = "The id of the chat")),
+    responses(
+        (status = 200, body = KeyBundle),
+        (status = 404, description = "No such chat, or the other participant has no keys"),
+        (status = 429, description = "Too many key bundles were requested, retry after the `Retry-After` seconds"),
+    ),
+)]
+#[get("/chats/{chat_id}/keys")]
+pub async fn get_key_bundle(
+    auth_user: AuthUser,
+    path: Path<i64>,
+    db_pool: web::Data<PgPool>,
+    redis: web::Data<ConnectionManager>,
+) -> Result<HttpResponse, Error> {
+    let chat = get_participated_chat(db_pool.get_ref(), path.into_inner(), auth_user.id).await?.ok_or(ApiError::NotFound)?;
+    let other_user_id = if chat.user1_id == auth_user.id { chat.user2_id } else { chat.user1_id };
+    check_key_bundle_rate(redis.get_ref(), auth_user.id).await?;
+
+    let keys = db::get_user_keys(db_pool.get_ref(), other_user_id)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    let one_time_prekey = db::take_one_time_prekey(db_pool.get_ref(), other_user_id).await?;
+
+    Ok(HttpResponse::Ok().json(KeyBundle::from_keys(keys, one_time_prekey)))
+}
+
+/// Switches the chat to end-to-end encryption, once both participants uploaded their keys.
+/// There is no way back, so the messages can't be downgraded to plain text without the participants noticing.
+#[utoipa::path(
+    tag = "encryption",
+    params(("chat_id" = i64, Path, description = "The id of the chat")),
+    responses(
+        (status = 204, description = "The chat is end-to-end encrypted"),
+        (status = 400, description = "A participant didn't upload their keys", body = String, content_type = "text/plain"),
+        (status = 404, description = "No such chat, or the user doesn't participate in it"),
+    ),
+)]
+#[post("/chats/{chat_id}/encryption")]
+pub async fn enable_encryption(
+    auth_user: AuthUser,
+    path: Path<i64>,
+    db_pool: web::Data<PgPool>,
+    srv: web::Data<Addr<ChatServer>>,
+) -> Result<HttpResponse, Error> {
+    let chat = get_participated_chat(db_pool.get_ref(), path.into_inner(), auth_user.id).await?.ok_or(ApiError::NotFound)?;
+    if chat.encrypted {
+        return Ok(HttpResponse::NoContent().finish());
+    }
+
+    for user_id in [chat.user1_id, chat.user2_id] {
+        if db::get_user_keys(db_pool.get_ref(), user_id).await?.is_none() {
+            return Err(ApiError::BadRequest("Both participants must upload their keys first".into()).into());
+        }
+    }
+
+    chat::enable_encryption(db_pool.get_ref(), chat.id).await?;
+    srv.do_send(NotifyChat { chat_id: chat.id, notice: Notice::EncryptionEnabled });
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Counts the key bundle requested by the user and refuses it past the limit of the window, every bundle
+/// uses up a one-time prekey of the other participant. The counters live in Redis, so they are shared by the instances.
+async fn check_key_bundle_rate(redis: &ConnectionManager, user_id: i64) -> Result<(), ApiError> {
+    let key = format!("cheechat:key_bundles:{user_id}");
+    let mut redis = redis.clone();
+    let counted: Result<(i64, i64), redis::RedisError> = redis::pipe()
+        .atomic()
+        .cmd("SET").arg(&key).arg(0).arg("EX").arg(KEY_BUNDLE_WINDOW_SECS).arg("NX").ignore()
+        .incr(&key, 1)
+        .ttl(&key)
+        .query_async(&mut redis)
+        .await;
+
+    match counted {
+        Ok((count, _)) if count <= KEY_BUNDLE_LIMIT => Ok(()),
+        Ok((_, retry_after)) => {
+            metrics::count_rate_limited("key_bundle");
+            Err(ApiError::TooManyAttempts(retry_after.max(1)))
+        }
+        // The key exchange keeps working without Redis, the prekeys are still handed out one at a time
+        Err(err) => {
+            tracing::warn!("Error counting the key bundle requests: {err}");
+            Ok(())
+        }
+    }
+}
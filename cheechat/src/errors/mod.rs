@@ -1,3 +1,5 @@
+use actix_web::error::BlockingError;
+use actix_web::http::header;
 use actix_web::{HttpResponse, ResponseError};
 use sqlx::Error as DbError;
 use derive_more::{Display, Error, From};
@@ -7,6 +9,16 @@ pub enum ApiError {
     NotFound,
     DbError(DbError),
     AuthError,
+    Forbidden,
+    /// A stored message could not be encrypted or decrypted, e.g. its key is not configured.
+    EncryptionError,
+    /// The blocking task, e.g. a bcrypt hash, was cancelled.
+    BlockingError(BlockingError),
+    #[from(ignore)]
+    BadRequest(#[error(not(source))] String),
+    /// Too many attempts, the client may retry after the given seconds.
+    #[from(ignore)]
+    TooManyAttempts(#[error(not(source))] i64),
 }
 
 impl ResponseError for ApiError {
@@ -17,6 +29,13 @@ impl ResponseError for ApiError {
                 HttpResponse::InternalServerError().body(err.to_string())
             },
             ApiError::AuthError => HttpResponse::Unauthorized().finish(),
+            ApiError::Forbidden => HttpResponse::Forbidden().finish(),
+            ApiError::EncryptionError => HttpResponse::InternalServerError().finish(),
+            ApiError::BlockingError(_) => HttpResponse::InternalServerError().finish(),
+            ApiError::BadRequest(ref reason) => HttpResponse::BadRequest().body(reason.clone()),
+            ApiError::TooManyAttempts(retry_after) => HttpResponse::TooManyRequests()
+                .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+                .finish(),
         }
     }
 }
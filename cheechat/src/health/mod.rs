@@ -0,0 +1,27 @@
+use actix_web::web;
+use utoipa::OpenApi;
+
+mod services;
+mod models;
+
+pub use models::*;
+
+/// Registers the probes, they are reachable without a session.
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(services::healthz);
+    cfg.service(services::readyz);
+}
+
+/// Registers the live counters of the server, they are reachable without a session,
+/// only when the monitoring is enabled.
+pub fn init_status_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(services::status);
+}
+
+/// The OpenAPI description of the probes.
+#[derive(OpenApi)]
+#[openapi(
+    paths(services::healthz, services::readyz, services::status),
+    tags((name = "health", description = "The probes of the orchestrator and the live counters")),
+)]
+pub struct ApiDoc;
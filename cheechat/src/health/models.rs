@@ -0,0 +1,46 @@
+use std::time::Instant;
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::chat::ServerStats;
+
+/// When the server started, to report the uptime.
+#[derive(Debug, Clone, Copy)]
+pub struct Uptime {
+    started_at: Instant,
+}
+
+impl Uptime {
+    pub fn start() -> Self {
+        Self { started_at: Instant::now() }
+    }
+
+    pub fn secs(&self) -> u64 {
+        self.started_at.elapsed().as_secs()
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Liveness {
+    pub status: &'static str,
+}
+
+/// The outcome of the readiness checks, the details of the failures only get logged.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Readiness {
+    pub ready: bool,
+    pub database: bool,
+    pub redis: bool,
+    /// Whether the schema matches the migrations of the binary.
+    pub migrations: bool,
+    /// Whether the chat server still takes connections, it doesn't once the shutdown started.
+    pub chat_server: bool,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Status {
+    pub version: &'static str,
+    pub uptime_secs: u64,
+    pub chat: ServerStats,
+}
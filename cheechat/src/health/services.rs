@@ -0,0 +1,111 @@
+use std::future::Future;
+use std::time::Duration;
+
+use actix::Addr;
+use actix_web::{get, web, Error, HttpResponse};
+use redis::aio::ConnectionManager;
+use sqlx::postgres::PgPool;
+use sqlx::Connection;
+
+use crate::app::migrations;
+use crate::chat::{ChatServer, GetServerStats};
+use crate::health::models::{Liveness, Readiness, Status, Uptime};
+
+/// How long a dependency may take to answer before it is reported as unavailable.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Reports that the process is alive
+#[utoipa::path(
+    tag = "health",
+    responses((status = 200, body = Liveness)),
+    security(()),
+)]
+#[get("/healthz")]
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(Liveness { status: "ok" })
+}
+
+/// Reports whether the server can take traffic: Postgres and Redis are reachable, the migrations are applied
+/// and the server is not shutting down. Responds with 503 otherwise.
+#[utoipa::path(
+    tag = "health",
+    responses(
+        (status = 200, body = Readiness),
+        (status = 503, description = "A check failed, or the server is shutting down", body = Readiness),
+    ),
+    security(()),
+)]
+#[get("/readyz")]
+pub async fn readyz(
+    db_pool: web::Data<PgPool>,
+    redis: web::Data<ConnectionManager>,
+    chat_server: web::Data<Addr<ChatServer>>,
+) -> HttpResponse {
+    // The checks run concurrently, so the probe takes as long as the slowest one
+    let (database, migrations, redis, chat_server) = tokio::join!(
+        check("database", async {
+            db_pool.acquire().await?.ping().await
+        }),
+        check("migrations", async {
+            migrations::status(db_pool.get_ref()).await.map(|status| status.is_up_to_date())
+        }),
+        check("redis", async {
+            let mut redis = redis.get_ref().clone();
+            let pong: String = redis::cmd("PING").query_async(&mut redis).await?;
+            Ok::<_, redis::RedisError>(pong)
+        }),
+        check("chat server", chat_server.send(GetServerStats)),
+    );
+    let migrations = migrations.unwrap_or(false);
+    let chat_server = chat_server.is_some_and(|stats| !stats.shutting_down);
+
+    let readiness = Readiness {
+        ready: database.is_some() && redis.is_some() && migrations && chat_server,
+        database: database.is_some(),
+        redis: redis.is_some(),
+        migrations,
+        chat_server,
+    };
+    if readiness.ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+/// Gets the version, the uptime and the live counters of the chat server
+#[utoipa::path(
+    tag = "health",
+    responses(
+        (status = 200, body = Status),
+        (status = 404, description = "The monitoring is disabled"),
+    ),
+    security(()),
+)]
+#[get("/status")]
+pub async fn status(uptime: web::Data<Uptime>, chat_server: web::Data<Addr<ChatServer>>) -> Result<HttpResponse, Error> {
+    let chat = chat_server.send(GetServerStats)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(Status {
+        version: env!("CARGO_PKG_VERSION"),
+        uptime_secs: uptime.secs(),
+        chat,
+    }))
+}
+
+/// Runs the check within the timeout, the failures are logged.
+async fn check<T, E: std::fmt::Display>(name: &str, check: impl Future<Output = Result<T, E>>) -> Option<T> {
+    match actix_web::rt::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(Ok(value)) => Some(value),
+        Ok(Err(err)) => {
+            tracing::warn!("Readiness check of the {name} failed: {err}");
+            None
+        }
+        Err(_) => {
+            tracing::warn!("Readiness check of the {name} timed out");
+            None
+        }
+    }
+}
@@ -3,3 +3,14 @@ pub mod users;
 pub mod errors;
 pub mod chat;
 pub mod app;
+pub mod audit;
+pub mod admin;
+pub mod reports;
+pub mod webhooks;
+pub mod bots;
+pub mod scheduled;
+pub mod e2ee;
+pub mod health;
+pub mod metrics;
+pub mod docs;
+pub mod cli;
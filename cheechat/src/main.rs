@@ -1,17 +1,43 @@
-use cheechat::{app};
-use confik::{Configuration, EnvSource};
-use dotenvy::dotenv;
+use std::process::ExitCode;
+
+use cheechat::{app, cli};
+use clap::Parser;
 
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    dotenv().unwrap();
+async fn main() -> ExitCode {
+    let cli = cli::Cli::parse();
+
+    // The .env file is optional, the settings may come from the environment or the configuration file
+    if let Err(err) = dotenvy::dotenv() {
+        if !err.not_found() {
+            eprintln!("Invalid .env file: {err}");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let config = cli.config.overrides()
+        .and_then(|overrides| app::config::AppConfig::load(cli.config.file.as_deref(), overrides));
+    let config = match config {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Invalid configuration: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
 
-    let config = app::config::AppConfig::builder()
-        .override_with(EnvSource::new())
-        .try_build()
-        .unwrap();
+    // Flushes the exported spans when dropped, on the way out
+    let _telemetry = match app::telemetry::init(&config.logging) {
+        Ok(telemetry) => telemetry,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
 
-    app::run(config).await?;
+    if let Err(err) = cli::run(cli, config).await {
+        eprintln!("{err}");
+        return ExitCode::FAILURE;
+    }
 
-    Ok(())
-}
\ No newline at end of file
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,65 @@
+use std::sync::LazyLock;
+
+use prometheus::core::Collector;
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry};
+
+/// The registry of the cheechat metrics, exposed at `/metrics`.
+pub static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+pub static HTTP_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| register(IntCounterVec::new(
+    Opts::new("cheechat_http_requests_total", "The http requests by route and status"),
+    &["method", "route", "status"],
+)));
+
+pub static HTTP_REQUEST_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| register(HistogramVec::new(
+    HistogramOpts::new("cheechat_http_request_duration_seconds", "The latency of the http requests by route"),
+    &["method", "route"],
+)));
+
+pub static WS_SESSIONS: LazyLock<IntGauge> = LazyLock::new(|| register(IntGauge::new(
+    "cheechat_ws_sessions", "The active websocket sessions",
+)));
+
+pub static ACTIVE_CHATS: LazyLock<IntGauge> = LazyLock::new(|| register(IntGauge::new(
+    "cheechat_active_chats", "The chats with at least one active websocket session",
+)));
+
+/// The chat messages by outcome: received by the chat server, then persisted, rejected or failed.
+pub static CHAT_MESSAGES: LazyLock<IntCounterVec> = LazyLock::new(|| register(IntCounterVec::new(
+    Opts::new("cheechat_chat_messages_total", "The chat messages by outcome"),
+    &["outcome"],
+)));
+
+pub static DB_POOL_CONNECTIONS: LazyLock<IntGaugeVec> = LazyLock::new(|| register(IntGaugeVec::new(
+    Opts::new("cheechat_db_pool_connections", "The connections of the database pool by state"),
+    &["state"],
+)));
+
+pub static DB_POOL_MAX_CONNECTIONS: LazyLock<IntGauge> = LazyLock::new(|| register(IntGauge::new(
+    "cheechat_db_pool_max_connections", "The maximum size of the database pool",
+)));
+
+pub static DB_QUERY_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| register(HistogramVec::new(
+    HistogramOpts::new("cheechat_db_query_duration_seconds", "The latency of the database queries by name"),
+    &["query"],
+)));
+
+/// The requests rejected by a limiter: the login lockouts and the spam filter of the chat messages.
+pub static RATE_LIMIT_REJECTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| register(IntCounterVec::new(
+    Opts::new("cheechat_rate_limit_rejections_total", "The requests rejected by a rate limiter"),
+    &["limiter"],
+)));
+
+pub fn count_message(outcome: &str) {
+    CHAT_MESSAGES.with_label_values(&[outcome]).inc();
+}
+
+pub fn count_rate_limited(limiter: &str) {
+    RATE_LIMIT_REJECTIONS.with_label_values(&[limiter]).inc();
+}
+
+fn register<C: Collector + Clone + 'static, E: std::fmt::Debug>(collector: Result<C, E>) -> C {
+    let collector = collector.expect("The metric options are valid");
+    REGISTRY.register(Box::new(collector.clone())).expect("The metric is registered once");
+    collector
+}
@@ -0,0 +1,35 @@
+use std::time::Instant;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::Error;
+
+use crate::metrics::collectors::{HTTP_REQUESTS, HTTP_REQUEST_DURATION};
+
+/// The route label of the requests that didn't match a route, or failed in a middleware before the response.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Middleware that counts the http requests and records their latency, by route pattern rather than
+/// by path so the ids in the paths don't multiply the series.
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let method = req.method().to_string();
+    let started_at = Instant::now();
+
+    let result = next.call(req).await;
+
+    let (route, status) = match &result {
+        Ok(res) => (
+            res.request().match_pattern().unwrap_or_else(|| UNMATCHED_ROUTE.into()),
+            res.status(),
+        ),
+        Err(err) => (UNMATCHED_ROUTE.into(), err.as_response_error().status_code()),
+    };
+    HTTP_REQUESTS.with_label_values(&[&method, &route, status.as_str()]).inc();
+    HTTP_REQUEST_DURATION.with_label_values(&[&method, &route]).observe(started_at.elapsed().as_secs_f64());
+
+    result
+}
@@ -0,0 +1,15 @@
+use actix_web::web;
+
+mod collectors;
+mod middleware;
+mod queries;
+mod services;
+
+pub use collectors::*;
+pub use middleware::track_requests;
+pub use queries::{QueryLatencyLayer, QUERY_TARGET};
+
+/// Registers the prometheus endpoint, it is reachable without a session, only when the monitoring is enabled.
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(services::get_metrics);
+}
@@ -0,0 +1,44 @@
+use std::time::Instant;
+
+use tracing::span::{Attributes, Id};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::metrics::collectors::DB_QUERY_DURATION;
+
+/// The target of the spans of the database queries, set with `#[instrument(target = "cheechat::db")]`.
+pub const QUERY_TARGET: &str = "cheechat::db";
+
+/// Records the latency of the database queries from their spans, by the name of the function running the query.
+///
+/// The spans of the queries must reach it whatever the log filter is, see [`crate::app::telemetry::init`].
+pub struct QueryLatencyLayer;
+
+/// When the span of the query got created.
+struct QueryStart(Instant);
+
+impl<S> Layer<S> for QueryLatencyLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if attrs.metadata().target() != QUERY_TARGET {
+            return;
+        }
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(QueryStart(Instant::now()));
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        if let Some(QueryStart(started_at)) = span.extensions().get::<QueryStart>() {
+            DB_QUERY_DURATION
+                .with_label_values(&[span.name()])
+                .observe(started_at.elapsed().as_secs_f64());
+        }
+    }
+}
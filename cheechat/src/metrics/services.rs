@@ -0,0 +1,32 @@
+use actix::Addr;
+use actix_web::{get, web, Error, HttpResponse};
+use prometheus::{Encoder, TextEncoder};
+use sqlx::postgres::PgPool;
+
+use crate::chat::{ChatServer, GetServerStats};
+use crate::metrics::collectors::{ACTIVE_CHATS, DB_POOL_CONNECTIONS, DB_POOL_MAX_CONNECTIONS, REGISTRY, WS_SESSIONS};
+
+/// Gets the metrics in the prometheus text format, the gauges of the chat server and the pool get refreshed first
+#[get("/metrics")]
+pub async fn get_metrics(db_pool: web::Data<PgPool>, chat_server: web::Data<Addr<ChatServer>>) -> Result<HttpResponse, Error> {
+    let stats = chat_server.send(GetServerStats)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    WS_SESSIONS.set(stats.active_sessions as i64);
+    ACTIVE_CHATS.set(stats.active_chats as i64);
+
+    let size = i64::from(db_pool.size());
+    let idle = db_pool.num_idle() as i64;
+    DB_POOL_CONNECTIONS.with_label_values(&["idle"]).set(idle);
+    DB_POOL_CONNECTIONS.with_label_values(&["in_use"]).set(size - idle);
+    DB_POOL_MAX_CONNECTIONS.set(i64::from(db_pool.options().get_max_connections()));
+
+    let encoder = TextEncoder::new();
+    let mut body = Vec::new();
+    encoder.encode(&REGISTRY.gather(), &mut body)
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(body))
+}
@@ -0,0 +1,275 @@
+use crate::chat::{ChatMessage, Expiry, MessageCipher, PayloadType, SealContext, SealedMessage, SealedRow};
+use crate::errors::ApiError;
+use crate::reports::models::{Report, ReportStatus};
+use sqlx::postgres::PgPool;
+use tracing::instrument;
+
+/// Adds the report of the user, along with a snapshot of the reported message if any.
+///
+/// The snapshot of an end-to-end encrypted message is the plaintext submitted by the reporter.
+#[instrument(target = "cheechat::db", skip_all, fields(reporter_id = reporter_id, reported_user_id = reported_user_id, chat_id = chat_id))]
+pub async fn add_report(
+    pool: &PgPool,
+    cipher: &MessageCipher,
+    reporter_id: i64,
+    reported_user_id: i64,
+    chat_id: Option<i64>,
+    message: Option<&ChatMessage>,
+    plaintext: Option<&str>,
+    reason: &str,
+) -> Result<i64, ApiError> {
+    // The snapshot is encrypted at rest like the message itself
+    let context = SealContext::Report { reporter_id, reported_user_id };
+    let e2ee = message.is_some_and(|message| message.payload_type() == PayloadType::Ciphertext);
+    let snapshot = message
+        .map(|message| if e2ee { plaintext.unwrap_or_default() } else { message.message.as_str() })
+        .map(|snapshot| cipher.encrypt(snapshot, &context))
+        .transpose()?;
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO cheechat.reports (reporter_id, reported_user_id, chat_id, message_id, message_snapshot, message_sent_at, reason, key_id, data_key, e2ee)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        RETURNING id
+        "#,
+        reporter_id,
+        reported_user_id,
+        chat_id,
+        message.map(|message| message.id),
+        snapshot.as_ref().map(|snapshot| snapshot.message.as_str()),
+        message.map(|message| message.created_at),
+        reason,
+        snapshot.as_ref().and_then(|snapshot| snapshot.key_id.as_deref()),
+        snapshot.as_ref().and_then(|snapshot| snapshot.data_key.as_deref()),
+        e2ee
+    )
+        .fetch_one(pool)
+        .await?;
+
+    Ok(row.id)
+}
+
+/// Retrieves the report based on the id
+#[instrument(target = "cheechat::db", skip_all, fields(report_id = report_id))]
+pub async fn get_report(pool: &PgPool, cipher: &MessageCipher, report_id: i64) -> Result<Option<Report>, ApiError> {
+    let mut report = sqlx::query_as!(Report, "SELECT * FROM cheechat.reports WHERE id = $1", report_id)
+        .fetch_optional(pool)
+        .await?;
+
+    if let Some(report) = report.as_mut() {
+        decrypt_snapshot(cipher, report)?;
+    }
+    Ok(report)
+}
+
+/// Whether the chat, or one of its messages, was reported
+#[instrument(target = "cheechat::db", skip_all, fields(chat_id = chat_id))]
+pub async fn is_chat_reported(pool: &PgPool, chat_id: i64) -> Result<bool, ApiError> {
+    let row = sqlx::query!(
+        r#"SELECT EXISTS (SELECT 1 FROM cheechat.reports WHERE chat_id = $1) AS "reported!""#,
+        chat_id
+    )
+        .fetch_one(pool)
+        .await?;
+
+    Ok(row.reported)
+}
+
+/// Retrieves the reports with the given status, oldest first so the queue is handled in order
+#[instrument(target = "cheechat::db", skip_all)]
+pub async fn get_reports(
+    pool: &PgPool,
+    cipher: &MessageCipher,
+    status: ReportStatus,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<Report>, ApiError> {
+    let mut reports = sqlx::query_as!(
+        Report,
+        "SELECT * FROM cheechat.reports WHERE status = $1 ORDER BY created_at ASC LIMIT $2 OFFSET $3",
+        status.as_str(),
+        limit,
+        offset
+    )
+        .fetch_all(pool)
+        .await?;
+
+    for report in reports.iter_mut() {
+        decrypt_snapshot(cipher, report)?;
+    }
+    Ok(reports)
+}
+
+/// Decrypts the message snapshot of the stored report in place.
+fn decrypt_snapshot(cipher: &MessageCipher, report: &mut Report) -> Result<(), ApiError> {
+    let context = SealContext::Report { reporter_id: report.reporter_id, reported_user_id: report.reported_user_id };
+    if let Some(snapshot) = report.message_snapshot.take() {
+        report.message_snapshot = Some(cipher.decrypt(snapshot, report.key_id.as_deref(), report.data_key.as_deref(), &context)?);
+    }
+    report.key_id = None;
+    report.data_key = None;
+    Ok(())
+}
+
+/// Retrieves the message snapshots that are not encrypted with the given master key, after the given id
+#[instrument(target = "cheechat::db", skip_all, fields(after_id = after_id))]
+pub async fn get_reports_to_reseal(pool: &PgPool, key_id: &str, after_id: i64, limit: i64) -> Result<Vec<SealedRow>, ApiError> {
+    let reports = sqlx::query!(
+        r#"
+        SELECT id, reporter_id, reported_user_id, message_snapshot AS "message_snapshot!", key_id, data_key
+        FROM cheechat.reports
+        WHERE id > $2 AND message_snapshot IS NOT NULL AND key_id IS DISTINCT FROM $1
+        ORDER BY id
+        LIMIT $3
+        "#,
+        key_id,
+        after_id,
+        limit
+    )
+        .map(|row| SealedRow {
+            id: row.id,
+            context: SealContext::Report { reporter_id: row.reporter_id, reported_user_id: row.reported_user_id },
+            sealed: SealedMessage { message: row.message_snapshot, key_id: row.key_id, data_key: row.data_key },
+        })
+        .fetch_all(pool)
+        .await?;
+
+    Ok(reports)
+}
+
+/// Replaces the stored message snapshot of the report, e.g. once re-encrypted with another master key
+#[instrument(target = "cheechat::db", skip_all, fields(report_id = report_id))]
+pub async fn update_sealed_report(pool: &PgPool, report_id: i64, sealed: &SealedMessage) -> Result<(), ApiError> {
+    sqlx::query!(
+        "UPDATE cheechat.reports SET message_snapshot = $2, key_id = $3, data_key = $4 WHERE id = $1",
+        report_id,
+        sealed.message,
+        sealed.key_id,
+        sealed.data_key
+    )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Resolves the open report and returns whether it was still open
+#[instrument(target = "cheechat::db", skip_all, fields(report_id = report_id, moderator_id = moderator_id))]
+pub async fn resolve_report(
+    pool: &PgPool,
+    report_id: i64,
+    status: ReportStatus,
+    resolution: &str,
+    moderator_id: i64,
+) -> Result<bool, ApiError> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE cheechat.reports
+        SET status = $2, resolution = $3, resolved_by = $4, resolved_at = CURRENT_TIMESTAMP
+        WHERE id = $1 AND status = 'open'
+        RETURNING id
+        "#,
+        report_id,
+        status.as_str(),
+        resolution,
+        moderator_id
+    )
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.is_some())
+}
+
+/// Deletes the message and resolves all its open reports as actioned, in a single transaction.
+///
+/// Returns whether the message existed, the reports are left open otherwise.
+#[instrument(target = "cheechat::db", skip_all, fields(message_id = message_id, moderator_id = moderator_id))]
+pub async fn delete_reported_message(pool: &PgPool, message_id: i64, resolution: &str, moderator_id: i64) -> Result<bool, ApiError> {
+    let mut tx = pool.begin().await?;
+
+    // The reports keep the snapshot of the message, the reference gets cleared on deletion
+    sqlx::query!(
+            r#"
+            UPDATE cheechat.reports
+            SET status = 'actioned', resolution = $2, resolved_by = $3, resolved_at = CURRENT_TIMESTAMP
+            WHERE message_id = $1 AND status = 'open'
+            "#,
+            message_id,
+            resolution,
+            moderator_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    let deleted = sqlx::query!("DELETE FROM cheechat.chat_messages WHERE id = $1 RETURNING id", message_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    if deleted.is_none() {
+        return Ok(false);
+    }
+
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Resolves all the open reports against the user as actioned
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id, moderator_id = moderator_id))]
+pub async fn resolve_user_reports(pool: &PgPool, user_id: i64, resolution: &str, moderator_id: i64) -> Result<(), ApiError> {
+    sqlx::query!(
+        r#"
+        UPDATE cheechat.reports
+        SET status = 'actioned', resolution = $2, resolved_by = $3, resolved_at = CURRENT_TIMESTAMP
+        WHERE reported_user_id = $1 AND status = 'open'
+        "#,
+        user_id,
+        resolution,
+        moderator_id
+    )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Clears up to the given number of message snapshots expired by the rule, like the messages themselves.
+///
+/// The snapshots of the open reports are kept until they are resolved, so the moderators can still review them.
+#[instrument(target = "cheechat::db", skip_all)]
+pub async fn clear_expired_snapshots(pool: &PgPool, expiry: Expiry, limit: i64) -> Result<u64, ApiError> {
+    let result = match expiry {
+        Expiry::ChatTtl => sqlx::query!(
+            r#"
+            UPDATE cheechat.reports SET message_snapshot = NULL, key_id = NULL, data_key = NULL
+            WHERE id IN (
+                SELECT reports.id
+                FROM cheechat.chats AS chats
+                JOIN cheechat.reports AS reports ON reports.chat_id = chats.id
+                    AND reports.message_snapshot IS NOT NULL
+                    AND reports.status <> 'open'
+                    AND reports.message_sent_at <= CURRENT_TIMESTAMP - chats.message_ttl_secs::FLOAT8 * INTERVAL '1 second'
+                WHERE chats.message_ttl_secs IS NOT NULL
+                LIMIT $1
+            )
+            "#,
+            limit
+        )
+            .execute(pool)
+            .await?,
+        Expiry::MaxAge(max_age_secs) => sqlx::query!(
+            r#"
+            UPDATE cheechat.reports SET message_snapshot = NULL, key_id = NULL, data_key = NULL
+            WHERE id IN (
+                SELECT id FROM cheechat.reports
+                WHERE message_snapshot IS NOT NULL
+                    AND status <> 'open'
+                    AND message_sent_at < CURRENT_TIMESTAMP - $1::FLOAT8 * INTERVAL '1 second'
+                LIMIT $2
+            )
+            "#,
+            max_age_secs as f64,
+            limit
+        )
+            .execute(pool)
+            .await?,
+    };
+
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,32 @@
+use actix_web::web;
+use utoipa::OpenApi;
+
+mod services;
+mod db;
+mod models;
+
+pub use db::*;
+pub use models::*;
+
+/// Registers the routes for the users to report messages and users.
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(services::create_report);
+}
+
+/// Registers the moderation queue routes, they have to be registered in a scope that requires a moderator.
+pub fn init_moderation_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(services::get_reports);
+    cfg.service(services::get_report);
+    cfg.service(services::dismiss_report);
+    cfg.service(services::delete_reported_message);
+    cfg.service(services::suspend_reported_user);
+    cfg.service(services::delete_message);
+}
+
+/// The OpenAPI description of the routes of the users, the moderation queue is left out of the client facing document.
+#[derive(OpenApi)]
+#[openapi(
+    paths(services::create_report),
+    tags((name = "reports", description = "The reports of the messages and users to the moderators")),
+)]
+pub struct ApiDoc;
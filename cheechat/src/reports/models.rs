@@ -0,0 +1,155 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use std::str::FromStr;
+use sqlx;
+use sqlx::types::time::PrimitiveDateTime;
+
+/// The review status of a report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportStatus {
+    /// Waiting in the moderation queue.
+    Open,
+    /// A moderator acted upon the report, e.g. deleted the message or suspended the user.
+    Actioned,
+    /// A moderator reviewed the report and took no action.
+    Dismissed,
+}
+
+impl ReportStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReportStatus::Open => "open",
+            ReportStatus::Actioned => "actioned",
+            ReportStatus::Dismissed => "dismissed",
+        }
+    }
+}
+
+impl FromStr for ReportStatus {
+    type Err = String;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match status {
+            "open" => Ok(ReportStatus::Open),
+            "actioned" => Ok(ReportStatus::Actioned),
+            "dismissed" => Ok(ReportStatus::Dismissed),
+            status => Err(format!("Unknown report status: {status}")),
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Report {
+    pub id: i64,
+    pub reporter_id: i64,
+    pub reported_user_id: i64,
+    pub chat_id: Option<i64>,
+    pub message_id: Option<i64>,
+    /// The content of the reported message at the time of the report.
+    pub message_snapshot: Option<String>,
+    pub message_sent_at: Option<PrimitiveDateTime>,
+    pub reason: String,
+    pub status: String,
+    pub resolution: Option<String>,
+    pub resolved_by: Option<i64>,
+    pub resolved_at: Option<PrimitiveDateTime>,
+    pub created_at: PrimitiveDateTime,
+    /// The master key that wraps the data key of the snapshot, none for the plain text snapshots.
+    pub key_id: Option<String>,
+    pub data_key: Option<String>,
+    /// Whether the message is end-to-end encrypted, the snapshot is then the plaintext submitted by the reporter.
+    pub e2ee: bool,
+}
+
+/// The report of a message, or of a user if no message is provided.
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct CreateReport {
+    pub message_id: Option<i64>,
+    pub user_id: Option<i64>,
+    pub reason: String,
+    /// The content of an end-to-end encrypted message as the reporter reads it, required to report one.
+    pub plaintext: Option<String>,
+}
+
+impl CreateReport {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.message_id.is_none() && self.user_id.is_none() {
+            return Err("Either the message_id or the user_id must be provided".into());
+        }
+        if self.reason.trim().is_empty() {
+            return Err("The reason can't be empty".into());
+        }
+        if self.reason.chars().count() > 1000 {
+            return Err("The reason can't be longer than 1000 characters".into());
+        }
+        if self.plaintext.as_ref().is_some_and(|plaintext| plaintext.chars().count() > 5000) {
+            return Err("The plaintext can't be longer than 5000 characters".into());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ReportCreated {
+    pub id: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ReportInfo {
+    pub id: i64,
+    pub reporter_id: i64,
+    pub reported_user_id: i64,
+    pub chat_id: Option<i64>,
+    pub message_id: Option<i64>,
+    pub message_snapshot: Option<String>,
+    /// Whether the snapshot was submitted by the reporter, for the end-to-end encrypted messages.
+    pub e2ee: bool,
+    pub message_sent_at: Option<i64>,
+    pub reason: String,
+    pub status: ReportStatus,
+    pub resolution: Option<String>,
+    pub resolved_by: Option<i64>,
+    pub resolved_at: Option<i64>,
+    pub created_at: i64,
+}
+
+impl ReportInfo {
+    pub fn from_report(report: Report) -> Self {
+        Self {
+            id: report.id,
+            reporter_id: report.reporter_id,
+            reported_user_id: report.reported_user_id,
+            chat_id: report.chat_id,
+            message_id: report.message_id,
+            message_snapshot: report.message_snapshot,
+            e2ee: report.e2ee,
+            message_sent_at: report.message_sent_at.map(|dt| dt.assume_utc().unix_timestamp()),
+            reason: report.reason,
+            status: report.status.parse().unwrap_or(ReportStatus::Open),
+            resolution: report.resolution,
+            resolved_by: report.resolved_by,
+            resolved_at: report.resolved_at.map(|dt| dt.assume_utc().unix_timestamp()),
+            created_at: report.created_at.assume_utc().unix_timestamp(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReportsQuery {
+    /// The status of the reports, defaults to the open ones.
+    pub status: Option<ReportStatus>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DismissReport {
+    pub note: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SuspendReportedUser {
+    pub reason: Option<String>,
+}
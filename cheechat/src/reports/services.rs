@@ -0,0 +1,233 @@
+use actix::Addr;
+use actix_web::web::Path;
+use actix_web::{delete, get, post, web, Error, HttpRequest, HttpResponse};
+use sqlx::postgres::PgPool;
+use std::cmp::{max, min};
+
+use crate::admin;
+use crate::chat::{self, ChatServer, DeleteMessage, MessageCipher, PayloadType};
+use crate::errors::ApiError;
+use crate::reports::db;
+use crate::reports::models::{CreateReport, DismissReport, Report, ReportCreated, ReportInfo, ReportStatus, ReportsQuery, SuspendReportedUser};
+use crate::users::{self, AuthUser, ClientInfo, Role};
+
+const DEFAULT_REPORTS_LIMIT: i64 = 50;
+const MAX_REPORTS_LIMIT: i64 = 200;
+
+/// Reports a message of a chat the current user participates in, or a user
+#[utoipa::path(
+    tag = "reports",
+    request_body = CreateReport,
+    responses(
+        (status = 201, body = ReportCreated),
+        (status = 400, description = "Invalid report, e.g. of their own message", body = String, content_type = "text/plain"),
+        (status = 404, description = "No such message or user"),
+    ),
+)]
+#[post("/reports")]
+pub async fn create_report(
+    auth_user: AuthUser,
+    request: web::Json<CreateReport>,
+    db_pool: web::Data<PgPool>,
+    cipher: web::Data<MessageCipher>,
+) -> Result<HttpResponse, Error> {
+    let request = request.into_inner();
+    request.validate().map_err(ApiError::BadRequest)?;
+    let reason = request.reason.trim();
+
+    let id = match request.message_id {
+        Some(message_id) => {
+            let message = chat::get_message(db_pool.get_ref(), cipher.get_ref(), message_id)
+                .await?
+                .ok_or(ApiError::NotFound)?;
+            let chat = chat::get_chat_by_id(db_pool.get_ref(), message.chat_id)
+                .await?
+                .ok_or(ApiError::NotFound)?;
+
+            // Only the participants of the chat can see, and so report, its messages
+            if chat.user1_id != auth_user.id && chat.user2_id != auth_user.id {
+                return Err(ApiError::NotFound.into());
+            }
+            if message.sender_id == auth_user.id {
+                return Err(ApiError::BadRequest("You can't report your own message".into()).into());
+            }
+            // The server can't read the end-to-end encrypted messages, the reporter provides what they read
+            let plaintext = request.plaintext.as_deref().map(str::trim).filter(|plaintext| !plaintext.is_empty());
+            if message.payload_type() == PayloadType::Ciphertext && plaintext.is_none() {
+                return Err(ApiError::BadRequest("The plaintext of the end-to-end encrypted message must be provided".into()).into());
+            }
+
+            db::add_report(
+                db_pool.get_ref(),
+                cipher.get_ref(),
+                auth_user.id,
+                message.sender_id,
+                Some(chat.id),
+                Some(&message),
+                plaintext,
+                reason,
+            ).await?
+        }
+        None => {
+            let user_id = request.user_id.unwrap_or_default();
+            let user = users::get_user_by_id(db_pool.get_ref(), user_id)
+                .await?
+                .filter(|user| user.deleted_at.is_none())
+                .ok_or(ApiError::NotFound)?;
+            if user.id == auth_user.id {
+                return Err(ApiError::BadRequest("You can't report yourself".into()).into());
+            }
+
+            let chat = chat::get_chat(db_pool.get_ref(), (min(auth_user.id, user.id), max(auth_user.id, user.id))).await?;
+            db::add_report(db_pool.get_ref(), cipher.get_ref(), auth_user.id, user.id, chat.map(|chat| chat.id), None, None, reason).await?
+        }
+    };
+
+    Ok(HttpResponse::Created().json(ReportCreated { id }))
+}
+
+/// Gets the moderation queue, the open reports by default
+#[get("/reports")]
+pub async fn get_reports(
+    query_params: web::Query<ReportsQuery>,
+    db_pool: web::Data<PgPool>,
+    cipher: web::Data<MessageCipher>,
+) -> Result<HttpResponse, Error> {
+    let status = query_params.status.unwrap_or(ReportStatus::Open);
+    let limit = query_params.limit
+        .unwrap_or(DEFAULT_REPORTS_LIMIT)
+        .clamp(1, MAX_REPORTS_LIMIT);
+    let offset = query_params.offset.unwrap_or(0).max(0);
+
+    let reports = db::get_reports(db_pool.get_ref(), cipher.get_ref(), status, limit, offset).await?;
+
+    let reports: Vec<_> = reports.into_iter().map(ReportInfo::from_report).collect();
+    Ok(HttpResponse::Ok().json(reports))
+}
+
+/// Gets the report
+#[get("/reports/{report_id}")]
+pub async fn get_report(path: Path<i64>, db_pool: web::Data<PgPool>, cipher: web::Data<MessageCipher>) -> Result<HttpResponse, Error> {
+    let report = db::get_report(db_pool.get_ref(), cipher.get_ref(), path.into_inner())
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    Ok(HttpResponse::Ok().json(ReportInfo::from_report(report)))
+}
+
+/// Dismisses the report without taking any action
+#[post("/reports/{report_id}/dismiss")]
+pub async fn dismiss_report(
+    auth_user: AuthUser,
+    path: Path<i64>,
+    request: web::Json<DismissReport>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, Error> {
+    let note = request.note.as_deref()
+        .map(str::trim)
+        .filter(|note| !note.is_empty())
+        .unwrap_or("Dismissed");
+
+    if !db::resolve_report(db_pool.get_ref(), path.into_inner(), ReportStatus::Dismissed, note, auth_user.id).await? {
+        return Err(ApiError::NotFound.into());
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Deletes the reported message and resolves all its open reports
+#[post("/reports/{report_id}/delete-message")]
+pub async fn delete_reported_message(
+    auth_user: AuthUser,
+    path: Path<i64>,
+    db_pool: web::Data<PgPool>,
+    cipher: web::Data<MessageCipher>,
+    chat_server: web::Data<Addr<ChatServer>>,
+) -> Result<HttpResponse, Error> {
+    let report = get_open_report(db_pool.get_ref(), cipher.get_ref(), path.into_inner()).await?;
+    let message_id = report.message_id
+        .ok_or_else(|| ApiError::BadRequest("The report has no message or it is already deleted".into()))?;
+
+    remove_message(db_pool.get_ref(), cipher.get_ref(), chat_server.get_ref(), &auth_user, message_id).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Suspends the reported user and resolves all the open reports against the user
+#[post("/reports/{report_id}/suspend-user")]
+pub async fn suspend_reported_user(
+    req: HttpRequest,
+    auth_user: AuthUser,
+    path: Path<i64>,
+    request: web::Json<SuspendReportedUser>,
+    db_pool: web::Data<PgPool>,
+    cipher: web::Data<MessageCipher>,
+    chat_server: web::Data<Addr<ChatServer>>,
+) -> Result<HttpResponse, Error> {
+    let report = get_open_report(db_pool.get_ref(), cipher.get_ref(), path.into_inner()).await?;
+    let user = admin::get_moderated_user(db_pool.get_ref(), &auth_user, report.reported_user_id).await?;
+    let reason = request.reason.as_deref()
+        .map(str::trim)
+        .filter(|reason| !reason.is_empty())
+        .unwrap_or(&report.reason);
+
+    let client = ClientInfo::from_request(&req);
+    admin::suspend_account(db_pool.get_ref(), chat_server.get_ref(), &auth_user, &client, user.id, Some(reason)).await?;
+    db::resolve_user_reports(db_pool.get_ref(), user.id, "User suspended", auth_user.id).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Deletes the message, e.g. found while inspecting a chat, and resolves its open reports
+#[delete("/messages/{message_id}")]
+pub async fn delete_message(
+    auth_user: AuthUser,
+    path: Path<i64>,
+    db_pool: web::Data<PgPool>,
+    cipher: web::Data<MessageCipher>,
+    chat_server: web::Data<Addr<ChatServer>>,
+) -> Result<HttpResponse, Error> {
+    remove_message(db_pool.get_ref(), cipher.get_ref(), chat_server.get_ref(), &auth_user, path.into_inner()).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Retrieves the report if it is still open.
+async fn get_open_report(db_pool: &PgPool, cipher: &MessageCipher, report_id: i64) -> Result<Report, ApiError> {
+    db::get_report(db_pool, cipher, report_id)
+        .await?
+        .filter(|report| report.status == ReportStatus::Open.as_str())
+        .ok_or(ApiError::NotFound)
+}
+
+/// Deletes the message if the sender can be moderated by the current user,
+/// and removes it live from the active sessions of the chat.
+async fn remove_message(
+    db_pool: &PgPool,
+    cipher: &MessageCipher,
+    chat_server: &Addr<ChatServer>,
+    auth_user: &AuthUser,
+    message_id: i64,
+) -> Result<(), ApiError> {
+    let message = chat::get_message(db_pool, cipher, message_id)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    let sender_role = users::get_user_by_id(db_pool, message.sender_id)
+        .await?
+        .map(|sender| sender.role())
+        .unwrap_or(Role::User);
+    if !auth_user.can_moderate(message.sender_id, sender_role) {
+        return Err(ApiError::Forbidden);
+    }
+
+    if !db::delete_reported_message(db_pool, message.id, "Message deleted", auth_user.id).await? {
+        return Err(ApiError::NotFound);
+    }
+
+    chat_server.do_send(DeleteMessage {
+        chat_id: message.chat_id,
+        message_id: message.id,
+    });
+
+    Ok(())
+}
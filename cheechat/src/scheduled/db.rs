@@ -0,0 +1,352 @@
+use crate::chat::{Expiry, MessageCipher, SealContext, SealedMessage, SealedRow};
+use crate::errors::ApiError;
+use crate::scheduled::models::{DueScheduledMessage, ScheduledMessage, ScheduledStatus};
+use sqlx::postgres::PgPool;
+use tracing::instrument;
+
+/// Adds the message to send to the chat at the given unix timestamp
+#[instrument(target = "cheechat::db", skip_all, fields(chat_id = chat_id, sender_id = sender_id))]
+pub async fn add_scheduled_message(
+    pool: &PgPool,
+    cipher: &MessageCipher,
+    chat_id: i64,
+    sender_id: i64,
+    message: &str,
+    send_at: i64,
+) -> Result<ScheduledMessage, ApiError> {
+    let sealed = cipher.encrypt(message, &SealContext::ScheduledMessage { chat_id, sender_id })?;
+    let mut scheduled = sqlx::query_as!(
+        ScheduledMessage,
+        r#"
+        INSERT INTO cheechat.scheduled_messages (chat_id, sender_id, message, send_at, key_id, data_key)
+        VALUES ($1, $2, $3, TO_TIMESTAMP($4::FLOAT8) AT TIME ZONE 'UTC', $5, $6)
+        RETURNING *
+        "#,
+        chat_id,
+        sender_id,
+        sealed.message,
+        send_at as f64,
+        sealed.key_id,
+        sealed.data_key
+    )
+        .fetch_one(pool)
+        .await?;
+
+    scheduled.message = message.to_owned();
+    scheduled.key_id = None;
+    scheduled.data_key = None;
+    Ok(scheduled)
+}
+
+/// Counts the pending scheduled messages of the user
+#[instrument(target = "cheechat::db", skip_all, fields(sender_id = sender_id))]
+pub async fn count_pending_scheduled_messages(pool: &PgPool, sender_id: i64) -> Result<i64, ApiError> {
+    let row = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM cheechat.scheduled_messages WHERE sender_id = $1 AND status = 'pending'"#,
+        sender_id
+    )
+        .fetch_one(pool)
+        .await?;
+
+    Ok(row.count)
+}
+
+/// Retrieves the scheduled messages of the user with the given status, optionally limited to a chat
+#[instrument(target = "cheechat::db", skip_all, fields(sender_id = sender_id, chat_id = chat_id))]
+pub async fn get_scheduled_messages(
+    pool: &PgPool,
+    cipher: &MessageCipher,
+    sender_id: i64,
+    chat_id: Option<i64>,
+    status: ScheduledStatus,
+    limit: i64,
+) -> Result<Vec<ScheduledMessage>, ApiError> {
+    let mut scheduled = sqlx::query_as!(
+        ScheduledMessage,
+        r#"
+        SELECT * FROM cheechat.scheduled_messages
+        WHERE sender_id = $1 AND ($2::BIGINT IS NULL OR chat_id = $2) AND status = $3
+        ORDER BY send_at
+        LIMIT $4
+        "#,
+        sender_id,
+        chat_id,
+        status.as_str(),
+        limit
+    )
+        .fetch_all(pool)
+        .await?;
+
+    for scheduled in scheduled.iter_mut() {
+        decrypt_scheduled_message(cipher, scheduled)?;
+    }
+    Ok(scheduled)
+}
+
+/// Retrieves the scheduled message of the user based on the id
+#[instrument(target = "cheechat::db", skip_all, fields(sender_id = sender_id, scheduled_id = scheduled_id))]
+pub async fn get_scheduled_message(
+    pool: &PgPool,
+    cipher: &MessageCipher,
+    sender_id: i64,
+    scheduled_id: i64,
+) -> Result<Option<ScheduledMessage>, ApiError> {
+    let mut scheduled = sqlx::query_as!(
+        ScheduledMessage,
+        "SELECT * FROM cheechat.scheduled_messages WHERE id = $1 AND sender_id = $2",
+        scheduled_id,
+        sender_id
+    )
+        .fetch_optional(pool)
+        .await?;
+
+    if let Some(scheduled) = scheduled.as_mut() {
+        decrypt_scheduled_message(cipher, scheduled)?;
+    }
+    Ok(scheduled)
+}
+
+/// Decrypts the stored scheduled message in place.
+fn decrypt_scheduled_message(cipher: &MessageCipher, scheduled: &mut ScheduledMessage) -> Result<(), ApiError> {
+    let context = SealContext::ScheduledMessage { chat_id: scheduled.chat_id, sender_id: scheduled.sender_id };
+    let message = std::mem::take(&mut scheduled.message);
+    scheduled.message = cipher.decrypt(message, scheduled.key_id.as_deref(), scheduled.data_key.as_deref(), &context)?;
+    scheduled.key_id = None;
+    scheduled.data_key = None;
+    Ok(())
+}
+
+/// Cancels the pending scheduled message of the user and returns whether it was still pending
+#[instrument(target = "cheechat::db", skip_all, fields(sender_id = sender_id, scheduled_id = scheduled_id))]
+pub async fn cancel_scheduled_message(pool: &PgPool, sender_id: i64, scheduled_id: i64) -> Result<bool, ApiError> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE cheechat.scheduled_messages SET status = 'cancelled', claimed_until = NULL
+        WHERE id = $1 AND sender_id = $2 AND status = 'pending'
+        RETURNING id
+        "#,
+        scheduled_id,
+        sender_id
+    )
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.is_some())
+}
+
+/// Claims the due scheduled messages, along with their senders and chats, for the given lease.
+///
+/// The claimed messages are not due again until the lease expires, so a crashed worker
+/// doesn't lose them and concurrent workers don't pick them up at the same time.
+#[instrument(target = "cheechat::db", skip_all)]
+pub async fn claim_due_scheduled_messages(pool: &PgPool, limit: i64, lease_secs: i64) -> Result<Vec<DueScheduledMessage>, ApiError> {
+    let scheduled = sqlx::query_as!(
+        DueScheduledMessage,
+        r#"
+        UPDATE cheechat.scheduled_messages AS scheduled
+        SET claimed_until = CURRENT_TIMESTAMP + $2::FLOAT8 * INTERVAL '1 second'
+        FROM cheechat.users AS users, cheechat.chats AS chats
+        WHERE scheduled.sender_id = users.id AND scheduled.chat_id = chats.id AND scheduled.id IN (
+            SELECT id FROM cheechat.scheduled_messages
+            WHERE status = 'pending'
+                AND send_at <= CURRENT_TIMESTAMP
+                AND (claimed_until IS NULL OR claimed_until <= CURRENT_TIMESTAMP)
+            ORDER BY send_at
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING
+            scheduled.id AS "id!",
+            scheduled.chat_id AS "chat_id!",
+            scheduled.sender_id AS "sender_id!",
+            users.username AS "sender_username!",
+            (users.deleted_at IS NULL AND users.suspended_at IS NULL) AS "sender_active!",
+            chats.encrypted AS "chat_encrypted!",
+            scheduled.message AS "message!",
+            scheduled.key_id,
+            scheduled.data_key,
+            scheduled.attempts AS "attempts!"
+        "#,
+        limit,
+        lease_secs as f64
+    )
+        .fetch_all(pool)
+        .await?;
+
+    Ok(scheduled)
+}
+
+/// Stores the scheduled message as a chat message and marks it as sent, in a single transaction.
+///
+/// Returns the id of the chat message, or nothing if the scheduled message is no longer pending,
+/// e.g. it was cancelled or sent in the meantime, so it can't be sent twice. The message is marked as failed
+/// if the chat switched to end-to-end encryption since it got claimed, the plain text can't be stored there.
+#[instrument(target = "cheechat::db", skip_all, fields(scheduled_id = scheduled_id))]
+pub async fn send_scheduled_message(
+    pool: &PgPool,
+    cipher: &MessageCipher,
+    scheduled_id: i64,
+    message: &str,
+) -> Result<Option<i64>, ApiError> {
+    let mut tx = pool.begin().await?;
+
+    let Some(scheduled) = sqlx::query!(
+            r#"
+            UPDATE cheechat.scheduled_messages
+            SET status = 'sent', attempts = attempts + 1, claimed_until = NULL, last_error = NULL, sent_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND status = 'pending'
+            RETURNING chat_id, sender_id
+            "#,
+            scheduled_id
+        )
+        .fetch_optional(&mut *tx)
+        .await? else {
+        return Ok(None);
+    };
+
+    let sealed = cipher.encrypt(message, &SealContext::ChatMessage { chat_id: scheduled.chat_id, sender_id: scheduled.sender_id })?;
+    let row = sqlx::query!(
+            r#"
+            INSERT INTO cheechat.chat_messages (chat_id, sender_id, message, key_id, data_key)
+            SELECT $1, $2, $3, $4, $5 FROM cheechat.chats
+            WHERE id = $1 AND NOT encrypted
+            RETURNING id
+            "#,
+            scheduled.chat_id,
+            scheduled.sender_id,
+            sealed.message,
+            sealed.key_id,
+            sealed.data_key
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+    let Some(row) = row else {
+        tx.rollback().await?;
+        let error = "The chat is end-to-end encrypted";
+        mark_scheduled_attempt_failed(pool, scheduled_id, error, None).await?;
+        return Err(ApiError::BadRequest(error.into()));
+    };
+    sqlx::query!(
+            "UPDATE cheechat.scheduled_messages SET message_id = $2 WHERE id = $1",
+            scheduled_id,
+            row.id
+        )
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("UPDATE cheechat.chats SET last_message_at = CURRENT_TIMESTAMP WHERE id = $1", scheduled.chat_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(Some(row.id))
+}
+
+/// Records the failed attempt to send the scheduled message, which is retried after the given delay
+/// or marked as failed for good if there is no retry.
+#[instrument(target = "cheechat::db", skip_all, fields(scheduled_id = scheduled_id))]
+pub async fn mark_scheduled_attempt_failed(
+    pool: &PgPool,
+    scheduled_id: i64,
+    error: &str,
+    retry_in_secs: Option<i64>,
+) -> Result<(), ApiError> {
+    sqlx::query!(
+        r#"
+        UPDATE cheechat.scheduled_messages SET
+            status = CASE WHEN $3::FLOAT8 IS NULL THEN 'failed' ELSE 'pending' END,
+            attempts = attempts + 1,
+            last_error = $2,
+            claimed_until = CURRENT_TIMESTAMP + COALESCE($3, 0) * INTERVAL '1 second'
+        WHERE id = $1 AND status = 'pending'
+        "#,
+        scheduled_id,
+        error,
+        retry_in_secs.map(|secs| secs as f64)
+    )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Retrieves the scheduled messages that are not encrypted with the given master key, after the given id
+#[instrument(target = "cheechat::db", skip_all, fields(after_id = after_id))]
+pub async fn get_scheduled_messages_to_reseal(pool: &PgPool, key_id: &str, after_id: i64, limit: i64) -> Result<Vec<SealedRow>, ApiError> {
+    let scheduled = sqlx::query!(
+        r#"
+        SELECT id, chat_id, sender_id, message, key_id, data_key FROM cheechat.scheduled_messages
+        WHERE id > $2 AND key_id IS DISTINCT FROM $1
+        ORDER BY id
+        LIMIT $3
+        "#,
+        key_id,
+        after_id,
+        limit
+    )
+        .map(|row| SealedRow {
+            id: row.id,
+            context: SealContext::ScheduledMessage { chat_id: row.chat_id, sender_id: row.sender_id },
+            sealed: SealedMessage { message: row.message, key_id: row.key_id, data_key: row.data_key },
+        })
+        .fetch_all(pool)
+        .await?;
+
+    Ok(scheduled)
+}
+
+/// Replaces the stored body of the scheduled message, e.g. once re-encrypted with another master key
+#[instrument(target = "cheechat::db", skip_all, fields(scheduled_id = scheduled_id))]
+pub async fn update_sealed_scheduled_message(pool: &PgPool, scheduled_id: i64, sealed: &SealedMessage) -> Result<(), ApiError> {
+    sqlx::query!(
+        "UPDATE cheechat.scheduled_messages SET message = $2, key_id = $3, data_key = $4 WHERE id = $1",
+        scheduled_id,
+        sealed.message,
+        sealed.key_id,
+        sealed.data_key
+    )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Deletes up to the given number of sent, cancelled or failed scheduled messages expired by the rule,
+/// the pending ones are kept until they are sent.
+#[instrument(target = "cheechat::db", skip_all)]
+pub async fn delete_expired_scheduled_messages(pool: &PgPool, expiry: Expiry, limit: i64) -> Result<u64, ApiError> {
+    let result = match expiry {
+        Expiry::ChatTtl => sqlx::query!(
+            r#"
+            DELETE FROM cheechat.scheduled_messages
+            WHERE id IN (
+                SELECT scheduled.id
+                FROM cheechat.chats AS chats
+                JOIN cheechat.scheduled_messages AS scheduled ON scheduled.chat_id = chats.id
+                    AND scheduled.status <> 'pending'
+                    AND scheduled.created_at <= CURRENT_TIMESTAMP - chats.message_ttl_secs::FLOAT8 * INTERVAL '1 second'
+                WHERE chats.message_ttl_secs IS NOT NULL
+                LIMIT $1
+            )
+            "#,
+            limit
+        )
+            .execute(pool)
+            .await?,
+        Expiry::MaxAge(max_age_secs) => sqlx::query!(
+            r#"
+            DELETE FROM cheechat.scheduled_messages
+            WHERE id IN (
+                SELECT id FROM cheechat.scheduled_messages
+                WHERE status <> 'pending' AND created_at < CURRENT_TIMESTAMP - $1::FLOAT8 * INTERVAL '1 second'
+                LIMIT $2
+            )
+            "#,
+            max_age_secs as f64,
+            limit
+        )
+            .execute(pool)
+            .await?,
+    };
+
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,29 @@
+use actix_web::web;
+use utoipa::OpenApi;
+
+mod services;
+mod worker;
+mod db;
+mod models;
+
+pub use db::*;
+pub use models::*;
+pub use worker::ScheduledMessageWorker;
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(services::schedule_message);
+    cfg.service(services::get_scheduled_messages);
+    cfg.service(services::cancel_scheduled_message);
+}
+
+/// The OpenAPI description of the routes.
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        services::schedule_message,
+        services::get_scheduled_messages,
+        services::cancel_scheduled_message,
+    ),
+    tags((name = "scheduled", description = "The messages scheduled to be sent later")),
+)]
+pub struct ApiDoc;
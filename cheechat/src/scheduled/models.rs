@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use std::str::FromStr;
+use sqlx;
+use sqlx::types::time::PrimitiveDateTime;
+
+/// The delivery status of a scheduled message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduledStatus {
+    /// Waiting for its time to be sent.
+    Pending,
+    /// Sent to the chat, exactly once.
+    Sent,
+    /// Cancelled by the sender before it was sent.
+    Cancelled,
+    /// Rejected by the content filters, or given up on after too many failed attempts.
+    Failed,
+}
+
+impl ScheduledStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ScheduledStatus::Pending => "pending",
+            ScheduledStatus::Sent => "sent",
+            ScheduledStatus::Cancelled => "cancelled",
+            ScheduledStatus::Failed => "failed",
+        }
+    }
+}
+
+impl FromStr for ScheduledStatus {
+    type Err = String;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match status {
+            "pending" => Ok(ScheduledStatus::Pending),
+            "sent" => Ok(ScheduledStatus::Sent),
+            "cancelled" => Ok(ScheduledStatus::Cancelled),
+            "failed" => Ok(ScheduledStatus::Failed),
+            status => Err(format!("Unknown scheduled message status: {status}")),
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct ScheduledMessage {
+    pub id: i64,
+    pub chat_id: i64,
+    pub sender_id: i64,
+    pub message: String,
+    pub send_at: PrimitiveDateTime,
+    pub status: String,
+    pub attempts: i32,
+    /// Until when the message is reserved for the worker that is sending it.
+    pub claimed_until: Option<PrimitiveDateTime>,
+    /// The chat message it became once sent.
+    pub message_id: Option<i64>,
+    pub last_error: Option<String>,
+    pub created_at: PrimitiveDateTime,
+    pub sent_at: Option<PrimitiveDateTime>,
+    /// The master key that wraps the data key of the message, none for the plain text messages.
+    pub key_id: Option<String>,
+    pub data_key: Option<String>,
+}
+
+impl ScheduledMessage {
+    pub fn status(&self) -> ScheduledStatus {
+        self.status.parse().unwrap_or(ScheduledStatus::Failed)
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ScheduledMessageInfo {
+    pub id: i64,
+    pub chat_id: i64,
+    pub message: String,
+    pub send_at: i64,
+    pub status: String,
+    pub message_id: Option<i64>,
+    pub last_error: Option<String>,
+    pub created_at: i64,
+    pub sent_at: Option<i64>,
+}
+
+impl ScheduledMessageInfo {
+    pub fn from_scheduled_message(scheduled: ScheduledMessage) -> Self {
+        Self {
+            id: scheduled.id,
+            chat_id: scheduled.chat_id,
+            message: scheduled.message,
+            send_at: scheduled.send_at.assume_utc().unix_timestamp(),
+            status: scheduled.status,
+            message_id: scheduled.message_id,
+            last_error: scheduled.last_error,
+            created_at: scheduled.created_at.assume_utc().unix_timestamp(),
+            sent_at: scheduled.sent_at.map(|dt| dt.assume_utc().unix_timestamp()),
+        }
+    }
+}
+
+/// The message to send to the chat at the given unix timestamp.
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct ScheduleMessage {
+    pub message: String,
+    pub send_at: i64,
+}
+
+impl ScheduleMessage {
+    /// The furthest in the future a message can be scheduled.
+    const MAX_DELAY_SECS: i64 = 365 * 24 * 3600;
+
+    pub fn validate(&self, now: i64) -> Result<(), String> {
+        if self.message.trim().is_empty() {
+            return Err("The message can't be empty".into());
+        }
+        if self.send_at <= now {
+            return Err("The message must be scheduled in the future".into());
+        }
+        if self.send_at - now > Self::MAX_DELAY_SECS {
+            return Err("The message can't be scheduled more than a year ahead".into());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ScheduledMessagesQuery {
+    pub chat_id: Option<i64>,
+    /// Defaults to the pending messages.
+    pub status: Option<ScheduledStatus>,
+    pub limit: Option<i64>,
+}
+
+/// A due scheduled message along with its sender, claimed by the worker.
+#[derive(Debug)]
+pub struct DueScheduledMessage {
+    pub id: i64,
+    pub chat_id: i64,
+    pub sender_id: i64,
+    pub sender_username: String,
+    /// Whether the sender is neither suspended nor deleted.
+    pub sender_active: bool,
+    /// Whether the chat switched to end-to-end encryption since the message got scheduled.
+    pub chat_encrypted: bool,
+    /// The message as it is stored, encrypted at rest if there is a key.
+    pub message: String,
+    pub key_id: Option<String>,
+    pub data_key: Option<String>,
+    pub attempts: i32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn schedule(message: &str, send_at: i64) -> ScheduleMessage {
+        ScheduleMessage { message: message.into(), send_at }
+    }
+
+    #[test]
+    fn accepts_the_messages_up_to_a_year_ahead() {
+        assert_eq!(schedule("hi", NOW + 1).validate(NOW), Ok(()));
+        assert_eq!(schedule("hi", NOW + ScheduleMessage::MAX_DELAY_SECS).validate(NOW), Ok(()));
+        assert!(schedule("hi", NOW + ScheduleMessage::MAX_DELAY_SECS + 1).validate(NOW).is_err());
+    }
+
+    #[test]
+    fn rejects_the_past_and_the_empty_messages() {
+        assert!(schedule("hi", NOW).validate(NOW).is_err());
+        assert!(schedule("hi", NOW - 60).validate(NOW).is_err());
+        assert!(schedule(" \n", NOW + 60).validate(NOW).is_err());
+    }
+
+    #[test]
+    fn parses_the_stored_statuses() {
+        assert_eq!("sent".parse(), Ok(ScheduledStatus::Sent));
+        assert!("lost".parse::<ScheduledStatus>().is_err());
+    }
+}
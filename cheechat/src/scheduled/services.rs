@@ -0,0 +1,115 @@
+use actix_web::web::Path;
+use actix_web::{delete, get, post, web, Error, HttpResponse};
+use chrono::Utc;
+use sqlx::postgres::PgPool;
+
+use crate::chat;
+use crate::errors::ApiError;
+use crate::scheduled::db;
+use crate::scheduled::models::{ScheduleMessage, ScheduledMessageInfo, ScheduledMessagesQuery, ScheduledStatus};
+use crate::users::AuthUser;
+
+const DEFAULT_SCHEDULED_LIMIT: i64 = 50;
+const MAX_SCHEDULED_LIMIT: i64 = 200;
+/// The number of pending scheduled messages a user may have at once.
+const MAX_PENDING_PER_USER: i64 = 100;
+
+/// Schedules a message to be sent to the chat at a later time.
+/// It goes through the content filters when it is sent, the slash commands are not run.
+#[utoipa::path(
+    tag = "scheduled",
+    params(("chat_id" = i64, Path, description = "The id of the chat")),
+    request_body = ScheduleMessage,
+    responses(
+        (status = 201, body = ScheduledMessageInfo),
+        (status = 400, description = "Invalid message or time, or too many pending messages", body = String, content_type = "text/plain"),
+        (status = 404, description = "No such chat, or the user doesn't participate in it"),
+    ),
+)]
+#[post("/chats/{chat_id}/scheduled-messages")]
+pub async fn schedule_message(
+    auth_user: AuthUser,
+    path: Path<i64>,
+    request: web::Json<ScheduleMessage>,
+    db_pool: web::Data<PgPool>,
+    cipher: web::Data<chat::MessageCipher>,
+) -> Result<HttpResponse, Error> {
+    let request = request.into_inner();
+    request.validate(Utc::now().timestamp()).map_err(ApiError::BadRequest)?;
+
+    let chat = chat::get_participated_chat(db_pool.get_ref(), path.into_inner(), auth_user.id)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    if chat.encrypted {
+        return Err(ApiError::BadRequest("Messages can't be scheduled in end-to-end encrypted chats".into()).into());
+    }
+    if db::count_pending_scheduled_messages(db_pool.get_ref(), auth_user.id).await? >= MAX_PENDING_PER_USER {
+        return Err(ApiError::BadRequest(format!("You can't have more than {MAX_PENDING_PER_USER} pending scheduled messages")).into());
+    }
+
+    let scheduled = db::add_scheduled_message(
+        db_pool.get_ref(),
+        cipher.get_ref(),
+        chat.id,
+        auth_user.id,
+        request.message.trim(),
+        request.send_at,
+    ).await?;
+
+    Ok(HttpResponse::Created().json(ScheduledMessageInfo::from_scheduled_message(scheduled)))
+}
+
+/// Gets the scheduled messages of the current user, the pending ones by default, soonest first
+#[utoipa::path(
+    tag = "scheduled",
+    params(ScheduledMessagesQuery),
+    responses((status = 200, body = Vec<ScheduledMessageInfo>)),
+)]
+#[get("/scheduled-messages")]
+pub async fn get_scheduled_messages(
+    auth_user: AuthUser,
+    query_params: web::Query<ScheduledMessagesQuery>,
+    db_pool: web::Data<PgPool>,
+    cipher: web::Data<chat::MessageCipher>,
+) -> Result<HttpResponse, Error> {
+    let query_params = query_params.into_inner();
+    let limit = query_params.limit
+        .unwrap_or(DEFAULT_SCHEDULED_LIMIT)
+        .clamp(1, MAX_SCHEDULED_LIMIT);
+    let status = query_params.status.unwrap_or(ScheduledStatus::Pending);
+
+    let scheduled = db::get_scheduled_messages(db_pool.get_ref(), cipher.get_ref(), auth_user.id, query_params.chat_id, status, limit).await?;
+
+    let scheduled: Vec<_> = scheduled.into_iter().map(ScheduledMessageInfo::from_scheduled_message).collect();
+    Ok(HttpResponse::Ok().json(scheduled))
+}
+
+/// Cancels the pending scheduled message of the current user
+#[utoipa::path(
+    tag = "scheduled",
+    params(("scheduled_id" = i64, Path, description = "The id of the scheduled message")),
+    responses(
+        (status = 204, description = "The message is cancelled"),
+        (status = 400, description = "The message is not pending anymore", body = String, content_type = "text/plain"),
+        (status = 404, description = "No such scheduled message"),
+    ),
+)]
+#[delete("/scheduled-messages/{scheduled_id}")]
+pub async fn cancel_scheduled_message(
+    auth_user: AuthUser,
+    path: Path<i64>,
+    db_pool: web::Data<PgPool>,
+    cipher: web::Data<chat::MessageCipher>,
+) -> Result<HttpResponse, Error> {
+    let scheduled = db::get_scheduled_message(db_pool.get_ref(), cipher.get_ref(), auth_user.id, path.into_inner())
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    // The message may get sent in the meantime, the cancellation only applies to the pending ones
+    if scheduled.status() != ScheduledStatus::Pending
+        || !db::cancel_scheduled_message(db_pool.get_ref(), auth_user.id, scheduled.id).await? {
+        return Err(ApiError::BadRequest("Only the pending scheduled messages can be cancelled".into()).into());
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
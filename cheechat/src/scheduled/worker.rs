@@ -0,0 +1,130 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix::{Actor, ActorFutureExt, Addr, AsyncContext, Context, ContextFutureSpawner, WrapFuture};
+use sqlx::postgres::PgPool;
+
+use crate::chat::{ChatServer, ClientMessage, MessageCipher, PayloadType, SealContext, SendError, SendOutcome};
+use crate::errors::ApiError;
+use crate::scheduled::db;
+use crate::scheduled::models::DueScheduledMessage;
+
+/// How often the due scheduled messages are checked for.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// The number of scheduled messages claimed at once.
+const BATCH_SIZE: i64 = 50;
+/// How long the claimed messages are reserved for the worker, enough to send a whole batch.
+const LEASE_SECS: i64 = 60;
+/// The number of attempts after which a scheduled message is marked as failed.
+const MAX_ATTEMPTS: i32 = 5;
+const RETRY_SECS: i64 = 30;
+
+/// The actor that sends the due scheduled messages through the chat server.
+///
+/// The schedule lives in the database and the messages are marked as sent in the same transaction
+/// that stores them, so they are sent exactly once and the pending ones survive the restarts.
+pub struct ScheduledMessageWorker {
+    db_pool: PgPool,
+    chat_server: Addr<ChatServer>,
+    cipher: Arc<MessageCipher>,
+    /// Whether a batch is being sent, so the polls don't overlap.
+    busy: bool,
+}
+
+impl ScheduledMessageWorker {
+    pub fn new(db_pool: PgPool, chat_server: Addr<ChatServer>, cipher: Arc<MessageCipher>) -> Self {
+        Self { db_pool, chat_server, cipher, busy: false }
+    }
+
+    fn poll(&mut self, ctx: &mut Context<Self>) {
+        if self.busy {
+            return;
+        }
+        self.busy = true;
+
+        let db_pool = self.db_pool.clone();
+        let chat_server = self.chat_server.clone();
+        let cipher = self.cipher.clone();
+        async move { send_due(&db_pool, &chat_server, &cipher).await }
+            .into_actor(self)
+            .map(|result, act, _| {
+                act.busy = false;
+                if let Err(err) = result {
+                    tracing::error!("Error sending the scheduled messages: {err}");
+                }
+            })
+            .spawn(ctx);
+    }
+}
+
+impl Actor for ScheduledMessageWorker {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(POLL_INTERVAL, |act, ctx| act.poll(ctx));
+    }
+}
+
+/// Sends the due scheduled messages and records the failures.
+async fn send_due(db_pool: &PgPool, chat_server: &Addr<ChatServer>, cipher: &MessageCipher) -> Result<(), ApiError> {
+    let due = db::claim_due_scheduled_messages(db_pool, BATCH_SIZE, LEASE_SECS).await?;
+
+    for scheduled in due {
+        if !scheduled.sender_active {
+            db::mark_scheduled_attempt_failed(db_pool, scheduled.id, "The sender is no longer active", None).await?;
+            continue;
+        }
+        if scheduled.chat_encrypted {
+            db::mark_scheduled_attempt_failed(db_pool, scheduled.id, "The chat is end-to-end encrypted", None).await?;
+            continue;
+        }
+        let context = SealContext::ScheduledMessage { chat_id: scheduled.chat_id, sender_id: scheduled.sender_id };
+        let content = match cipher.decrypt(scheduled.message.clone(), scheduled.key_id.as_deref(), scheduled.data_key.as_deref(), &context) {
+            Ok(content) => content,
+            // The key may be missing from the configuration for now
+            Err(_) => {
+                retry_later(db_pool, &scheduled, "The message could not be decrypted").await?;
+                continue;
+            }
+        };
+
+        let result = chat_server
+            .send(ClientMessage {
+                session_id: None,
+                content,
+                chat_id: scheduled.chat_id,
+                sender_id: scheduled.sender_id,
+                sender_username: scheduled.sender_username.clone(),
+                scheduled_message_id: Some(scheduled.id),
+                payload_type: PayloadType::Text,
+                request_id: None,
+            })
+            .await;
+
+        match result {
+            Ok(Ok(SendOutcome::Sent(message_id, _))) => {
+                tracing::debug!("Scheduled message {} is sent as message {message_id}", scheduled.id);
+            }
+            // Scheduled messages don't run the commands, so there is never a reply
+            Ok(Ok(SendOutcome::Replied(_))) | Ok(Err(SendError::Skipped)) => (),
+            Ok(Err(SendError::Rejected(reason))) => {
+                db::mark_scheduled_attempt_failed(db_pool, scheduled.id, &reason, None).await?;
+            }
+            // The server is shutting down, the lease expires and the message is sent after the restart
+            Ok(Err(SendError::Unavailable)) => (),
+            Ok(Err(SendError::Failed)) => retry_later(db_pool, &scheduled, "The message could not be stored").await?,
+            Err(err) => retry_later(db_pool, &scheduled, &err.to_string()).await?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Records the failed attempt, the message is retried until it runs out of attempts.
+async fn retry_later(db_pool: &PgPool, scheduled: &DueScheduledMessage, error: &str) -> Result<(), ApiError> {
+    let attempt = scheduled.attempts + 1;
+    let retry_in_secs = (attempt < MAX_ATTEMPTS).then_some(RETRY_SECS);
+
+    tracing::warn!("Scheduled message {} failed (attempt {attempt}): {error}", scheduled.id);
+    db::mark_scheduled_attempt_failed(db_pool, scheduled.id, error, retry_in_secs).await
+}
@@ -0,0 +1,217 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use actix_session::{Session, SessionExt};
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::http::header;
+use actix_web::{web, Error, FromRequest, HttpMessage, HttpRequest};
+use bcrypt::hash;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use sqlx::postgres::PgPool;
+
+use crate::errors::ApiError;
+use crate::users::db;
+use crate::users::models::{Role, UserInfo};
+
+/// The credential the request got authenticated with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Credential {
+    /// The login session of the session cookie, with its id.
+    Session(i64),
+    /// The API token of a bot, with its id.
+    ApiToken(i64),
+}
+
+/// The authenticated user of the request, along with the loaded profile.
+///
+/// The user is loaded once per request and cached in the request extensions,
+/// so extracting it again (e.g. in the middleware and the handler) does not hit the database.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub id: i64,
+    pub credential: Credential,
+    pub role: Role,
+    pub user: UserInfo,
+}
+
+impl AuthUser {
+    /// The id of the login session the request belongs to, none for the bots.
+    pub fn login_session_id(&self) -> Option<i64> {
+        match self.credential {
+            Credential::Session(session_id) => Some(session_id),
+            Credential::ApiToken(_) => None,
+        }
+    }
+
+    /// Fails with forbidden if the user doesn't have at least the given role.
+    pub fn require_role(&self, role: Role) -> Result<(), ApiError> {
+        if self.role < role {
+            return Err(ApiError::Forbidden);
+        }
+        Ok(())
+    }
+
+    /// Whether the user is allowed to moderate the target user:
+    /// moderators only act on regular users, admins on anyone but themselves.
+    pub fn can_moderate(&self, target_id: i64, target_role: Role) -> bool {
+        match self.role {
+            Role::Admin => target_id != self.id,
+            Role::Moderator => target_role == Role::User,
+            Role::User => false,
+        }
+    }
+}
+
+impl FromRequest for AuthUser {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+
+        Box::pin(async move {
+            if let Some(auth_user) = req.extensions().get::<AuthUser>().cloned() {
+                return Ok(auth_user);
+            }
+
+            let db_pool = req
+                .app_data::<web::Data<PgPool>>()
+                .expect("The database pool is not registered as app data");
+
+            // The bots authenticate with their API token, the users with the session cookie
+            let mut session = None;
+            let (id, credential) = match bearer_token(&req) {
+                Some(token) => {
+                    let token = db::use_api_token(db_pool.get_ref(), &hash_api_token(&token))
+                        .await?
+                        .ok_or(ApiError::AuthError)?;
+                    (token.user_id, Credential::ApiToken(token.id))
+                }
+                None => {
+                    let login_session = req.get_session();
+                    let (id, session_id) = authenticate_user(&login_session)?;
+
+                    // The login session might have been revoked from another device
+                    if !db::touch_user_session(db_pool.get_ref(), id, session_id).await? {
+                        login_session.purge();
+                        return Err(ApiError::AuthError.into());
+                    }
+                    session = Some(login_session);
+                    (id, Credential::Session(session_id))
+                }
+            };
+
+            let user = db::get_user_by_id(db_pool.get_ref(), id)
+                .await?
+                .filter(|user| user.deleted_at.is_none())
+                .ok_or(ApiError::AuthError)?;
+            if user.suspended_at.is_some() {
+                if let Some(session) = &session {
+                    session.purge();
+                }
+                return Err(ApiError::Forbidden.into());
+            }
+            // The bots act on behalf of their owner, they are suspended and deleted along with it
+            if let Some(owner_id) = user.bot_owner_id {
+                let owner_active = db::get_user_by_id(db_pool.get_ref(), owner_id)
+                    .await?
+                    .is_some_and(|owner| owner.deleted_at.is_none() && owner.suspended_at.is_none());
+                if !owner_active {
+                    return Err(ApiError::Forbidden.into());
+                }
+            }
+
+            let role = user.role();
+            let auth_user = AuthUser { id, credential, role, user: UserInfo::from_user(user) };
+            req.extensions_mut().insert(auth_user.clone());
+
+            Ok(auth_user)
+        })
+    }
+}
+
+/// Middleware that rejects the requests without an authenticated user.
+///
+/// Wrap a scope with it, so the routes registered in it can't forget to authenticate.
+pub async fn require_auth(
+    _: AuthUser,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    next.call(req).await
+}
+
+/// Middleware that rejects the requests of users that are not at least moderators.
+pub async fn require_moderator(
+    auth_user: AuthUser,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    auth_user.require_role(Role::Moderator)?;
+    next.call(req).await
+}
+
+/// Middleware that rejects the requests of users that are not admins.
+pub async fn require_admin(
+    auth_user: AuthUser,
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    auth_user.require_role(Role::Admin)?;
+    next.call(req).await
+}
+
+/// Retrieves the ids of the user and the login session stored in the session.
+pub fn authenticate_user(session: &Session) -> Result<(i64, i64), Error> {
+    let user_id = session
+        .get::<String>("user_id")?
+        .and_then(|user_id| user_id.parse::<i64>().ok())
+        .ok_or(ApiError::AuthError)?;
+    let session_id = session
+        .get::<i64>("session_id")?
+        .ok_or(ApiError::AuthError)?;
+
+    Ok((user_id, session_id))
+}
+
+/// Retrieves the token of the `Authorization: Bearer` header, if any.
+fn bearer_token(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim().to_owned())
+}
+
+/// Generates a new API token, only its hash gets stored.
+pub fn generate_api_token() -> String {
+    let token: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(40)
+        .map(char::from)
+        .collect();
+
+    format!("cht_{token}")
+}
+
+/// Hashes the API token for the storage and the lookup. The tokens are random enough
+/// for a fast unsalted hash, unlike the passwords.
+pub fn hash_api_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Hashes a random password that nobody knows, for the accounts that can't be logged in anymore.
+pub fn unusable_password_hash() -> String {
+    let random_password: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+
+    hash(random_password, 10).unwrap()
+}
@@ -1,36 +1,474 @@
 use crate::errors::ApiError;
-use crate::users::models::User;
+use crate::users::models::{ApiToken, ClientInfo, RecoveryCode, Role, UpdateProfile, User, UserSession, UserStats};
 use crate::users::RegisterUser;
 use sqlx::postgres::PgPool;
+use tracing::instrument;
 
 /// Retrieves all users
+#[instrument(target = "cheechat::db", skip_all)]
 pub async fn get_users(pool: &PgPool) -> Result<Vec<User>, ApiError> {
-    let users = sqlx::query_as!(User, "SELECT * FROM cheechat.users")
+    let users = sqlx::query_as!(User, "SELECT * FROM cheechat.users WHERE deleted_at IS NULL")
         .fetch_all(pool)
         .await?;
     Ok(users)
 }
 
+/// Searches the users by username, email or name, most recent first.
+/// The suspended and deleted users are included, so they can be administrated.
+#[instrument(target = "cheechat::db", skip_all)]
+pub async fn search_users(pool: &PgPool, query: Option<&str>, limit: i64, offset: i64) -> Result<Vec<User>, ApiError> {
+    let pattern = query.map(|query| format!("%{}%", query.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")));
+
+    let users = sqlx::query_as!(
+            User,
+            r#"
+            SELECT * FROM cheechat.users
+            WHERE $1::VARCHAR IS NULL
+                OR username ILIKE $1
+                OR email ILIKE $1
+                OR first_name || ' ' || last_name ILIKE $1
+            ORDER BY id DESC
+            LIMIT $2 OFFSET $3
+            "#,
+            pattern,
+            limit,
+            offset
+        )
+        .fetch_all(pool)
+        .await?;
+
+    Ok(users)
+}
+
 /// Retrieves the user based on the username.
+#[instrument(target = "cheechat::db", skip_all)]
 pub async fn get_user(pool: &PgPool, username: &str) -> Result<User, ApiError> {
-    let user = sqlx::query_as!(User, "SELECT * FROM cheechat.users where username=$1", username)
+    let user = sqlx::query_as!(User, "SELECT * FROM cheechat.users where username=$1 AND deleted_at IS NULL", username)
         .fetch_one(pool)
         .await?;
     Ok(user)
 }
 
+/// Looks up the user based on the username.
+#[instrument(target = "cheechat::db", skip_all)]
+pub async fn find_user(pool: &PgPool, username: &str) -> Result<Option<User>, ApiError> {
+    let user = sqlx::query_as!(User, "SELECT * FROM cheechat.users where username=$1 AND deleted_at IS NULL", username)
+        .fetch_optional(pool)
+        .await?;
+    Ok(user)
+}
+
+/// Retrieves the user based on the id.
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = id))]
+pub async fn get_user_by_id(pool: &PgPool, id: i64) -> Result<Option<User>, ApiError> {
+    let user = sqlx::query_as!(User, "SELECT * FROM cheechat.users where id=$1", id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(user)
+}
+
 /// Adds a new user.
+#[instrument(target = "cheechat::db", skip_all)]
 pub async fn add_user(pool: &PgPool, user: RegisterUser) -> Result<i64, ApiError> {
     let row = sqlx::query!(
             "INSERT INTO cheechat.users (username, password, first_name, last_name, email) VALUES ($1, $2, $3, $4, $5) RETURNING id",
             user.username,
             user.password,
-            user.email,
             user.first_name,
-            user.last_name
+            user.last_name,
+            user.email
+        )
+        .fetch_one(pool)
+        .await?;
+
+    Ok(row.id)
+}
+
+/// Adds a new login session for the user.
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn add_user_session(pool: &PgPool, user_id: i64, client: &ClientInfo) -> Result<i64, ApiError> {
+    let row = sqlx::query!(
+            "INSERT INTO cheechat.user_sessions (user_id, user_agent, ip_address) VALUES ($1, $2, $3) RETURNING id",
+            user_id,
+            client.user_agent,
+            client.ip_address
         )
         .fetch_one(pool)
         .await?;
 
     Ok(row.id)
 }
+
+/// Marks the login session as active and returns whether it is still valid (not revoked).
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id, session_id = session_id))]
+pub async fn touch_user_session(pool: &PgPool, user_id: i64, session_id: i64) -> Result<bool, ApiError> {
+    let row = sqlx::query!(
+            "UPDATE cheechat.user_sessions SET last_active_at = CURRENT_TIMESTAMP WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL RETURNING id",
+            session_id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.is_some())
+}
+
+/// Retrieves the active login sessions of the user.
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn get_user_sessions(pool: &PgPool, user_id: i64) -> Result<Vec<UserSession>, ApiError> {
+    let sessions = sqlx::query_as!(
+            UserSession,
+            "SELECT * FROM cheechat.user_sessions WHERE user_id = $1 AND revoked_at IS NULL ORDER BY last_active_at DESC",
+            user_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+    Ok(sessions)
+}
+
+/// Revokes the login session of the user and returns whether it was active.
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id, session_id = session_id))]
+pub async fn revoke_user_session(pool: &PgPool, user_id: i64, session_id: i64) -> Result<bool, ApiError> {
+    let row = sqlx::query!(
+            "UPDATE cheechat.user_sessions SET revoked_at = CURRENT_TIMESTAMP WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL RETURNING id",
+            session_id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.is_some())
+}
+
+/// Revokes all the active login sessions of the user, optionally keeping one of them.
+/// Returns the ids of the revoked sessions.
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id, keep_session_id = keep_session_id))]
+pub async fn revoke_user_sessions(pool: &PgPool, user_id: i64, keep_session_id: Option<i64>) -> Result<Vec<i64>, ApiError> {
+    let rows = sqlx::query!(
+            "UPDATE cheechat.user_sessions SET revoked_at = CURRENT_TIMESTAMP WHERE user_id = $1 AND revoked_at IS NULL AND id IS DISTINCT FROM $2 RETURNING id",
+            user_id,
+            keep_session_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.into_iter().map(|row| row.id).collect())
+}
+
+/// Sets the TOTP secret of the user, the two-factor authentication stays disabled until confirmed.
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn set_totp_secret(pool: &PgPool, user_id: i64, secret: Option<&str>) -> Result<(), ApiError> {
+    sqlx::query!(
+            "UPDATE cheechat.users SET totp_secret = $1, totp_enabled = FALSE, totp_last_step = NULL WHERE id = $2",
+            secret,
+            user_id
+        )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Enables the two-factor authentication of the user.
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn enable_totp(pool: &PgPool, user_id: i64) -> Result<(), ApiError> {
+    sqlx::query!("UPDATE cheechat.users SET totp_enabled = TRUE WHERE id = $1", user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Records the time step of the accepted code of the authenticator app,
+/// returns false if a code of that step or of a later one was already accepted.
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn use_totp_step(pool: &PgPool, user_id: i64, step: i64) -> Result<bool, ApiError> {
+    let row = sqlx::query!(
+            r#"
+            UPDATE cheechat.users SET totp_last_step = $2
+            WHERE id = $1 AND (totp_last_step IS NULL OR totp_last_step < $2)
+            RETURNING id
+            "#,
+            user_id,
+            step
+        )
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.is_some())
+}
+
+/// Replaces the recovery codes of the user with the provided hashed codes.
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn set_recovery_codes(pool: &PgPool, user_id: i64, code_hashes: &[String]) -> Result<(), ApiError> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!("DELETE FROM cheechat.recovery_codes WHERE user_id = $1", user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+            "INSERT INTO cheechat.recovery_codes (user_id, code_hash) SELECT $1, UNNEST($2::VARCHAR[])",
+            user_id,
+            code_hashes
+        )
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Retrieves the unused recovery codes of the user.
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn get_recovery_codes(pool: &PgPool, user_id: i64) -> Result<Vec<RecoveryCode>, ApiError> {
+    let codes = sqlx::query_as!(
+            RecoveryCode,
+            "SELECT * FROM cheechat.recovery_codes WHERE user_id = $1 AND used_at IS NULL",
+            user_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+    Ok(codes)
+}
+
+/// Marks the recovery code as used and returns whether it was still unused.
+#[instrument(target = "cheechat::db", skip_all, fields(code_id = code_id))]
+pub async fn use_recovery_code(pool: &PgPool, code_id: i64) -> Result<bool, ApiError> {
+    let row = sqlx::query!(
+            "UPDATE cheechat.recovery_codes SET used_at = CURRENT_TIMESTAMP WHERE id = $1 AND used_at IS NULL RETURNING id",
+            code_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.is_some())
+}
+
+/// Updates the provided profile fields of the user and returns the updated user.
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn update_profile(pool: &PgPool, user_id: i64, profile: &UpdateProfile) -> Result<User, ApiError> {
+    let user = sqlx::query_as!(
+            User,
+            r#"
+            UPDATE cheechat.users SET
+                email = COALESCE($2, email),
+                first_name = COALESCE($3, first_name),
+                last_name = COALESCE($4, last_name),
+                username = COALESCE($5, username),
+                avatar_url = CASE WHEN $6::VARCHAR IS NULL THEN avatar_url ELSE NULLIF($6, '') END,
+                status_text = CASE WHEN $7::VARCHAR IS NULL THEN status_text ELSE NULLIF($7, '') END
+            WHERE id = $1
+            RETURNING *
+            "#,
+            user_id,
+            profile.email,
+            profile.first_name,
+            profile.last_name,
+            profile.username,
+            profile.avatar_url,
+            profile.status_text
+        )
+        .fetch_one(pool)
+        .await?;
+
+    Ok(user)
+}
+
+/// Updates the (hashed) password of the user.
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn update_password(pool: &PgPool, user_id: i64, password: &str) -> Result<(), ApiError> {
+    sqlx::query!("UPDATE cheechat.users SET password = $1 WHERE id = $2", password, user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Deletes the account of the user by anonymizing it, so the chats and the messages
+/// of the other participants are kept but can't be traced back to the user.
+///
+/// The password gets replaced with the provided unusable hash, all the login sessions and API tokens get revoked
+/// and the end-to-end encryption keys get deleted. The messages sent by the user are kept as empty tombstones,
+/// and the pending scheduled messages get cancelled and emptied.
+///
+/// The bots of the user get deleted along with it. Returns the ids of the anonymized users, the user and its bots.
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn anonymize_user(pool: &PgPool, user_id: i64, unusable_password: &str) -> Result<Vec<i64>, ApiError> {
+    let mut tx = pool.begin().await?;
+
+    let user_ids: Vec<i64> = sqlx::query!(
+            "SELECT id FROM cheechat.users WHERE id = $1 OR (bot_owner_id = $1 AND deleted_at IS NULL) FOR UPDATE",
+            user_id
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|row| row.id)
+        .collect();
+
+    sqlx::query!(
+            r#"
+            UPDATE cheechat.users SET
+                email = '',
+                first_name = 'Deleted',
+                last_name = 'User',
+                username = 'deleted-' || id,
+                password = $2,
+                totp_secret = NULL,
+                totp_enabled = FALSE,
+                totp_last_step = NULL,
+                avatar_url = NULL,
+                status_text = NULL,
+                deleted_at = CURRENT_TIMESTAMP
+            WHERE id = ANY($1)
+            "#,
+            &user_ids,
+            unusable_password
+        )
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM cheechat.recovery_codes WHERE user_id = ANY($1)", &user_ids)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+            "UPDATE cheechat.user_sessions SET revoked_at = CURRENT_TIMESTAMP WHERE user_id = ANY($1) AND revoked_at IS NULL",
+            &user_ids
+        )
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+            "UPDATE cheechat.api_tokens SET revoked_at = CURRENT_TIMESTAMP WHERE user_id = ANY($1) AND revoked_at IS NULL",
+            &user_ids
+        )
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM cheechat.user_keys WHERE user_id = ANY($1)", &user_ids)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM cheechat.one_time_prekeys WHERE user_id = ANY($1)", &user_ids)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+            "UPDATE cheechat.chat_messages SET message = '', key_id = NULL, data_key = NULL WHERE sender_id = ANY($1)",
+            &user_ids
+        )
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+            r#"
+            UPDATE cheechat.scheduled_messages SET
+                message = '',
+                key_id = NULL,
+                data_key = NULL,
+                status = CASE WHEN status = 'pending' THEN 'cancelled' ELSE status END,
+                claimed_until = NULL
+            WHERE sender_id = ANY($1)
+            "#,
+            &user_ids
+        )
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(user_ids)
+}
+
+/// Suspends the user with the given reason and revokes all the login sessions.
+/// Returns the ids of the revoked sessions.
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn suspend_user(pool: &PgPool, user_id: i64, reason: Option<&str>) -> Result<Vec<i64>, ApiError> {
+    sqlx::query!(
+            "UPDATE cheechat.users SET suspended_at = CURRENT_TIMESTAMP, suspension_reason = $2 WHERE id = $1",
+            user_id,
+            reason
+        )
+        .execute(pool)
+        .await?;
+
+    revoke_user_sessions(pool, user_id, None).await
+}
+
+/// Lifts the suspension of the user.
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn unsuspend_user(pool: &PgPool, user_id: i64) -> Result<(), ApiError> {
+    sqlx::query!(
+            "UPDATE cheechat.users SET suspended_at = NULL, suspension_reason = NULL WHERE id = $1",
+            user_id
+        )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Retrieves the user counters.
+#[instrument(target = "cheechat::db", skip_all)]
+pub async fn get_user_stats(pool: &PgPool) -> Result<UserStats, ApiError> {
+    let stats = sqlx::query_as!(
+            UserStats,
+            r#"
+            SELECT
+                COUNT(*) FILTER (WHERE deleted_at IS NULL) AS "total!",
+                COUNT(*) FILTER (WHERE deleted_at IS NULL AND suspended_at IS NOT NULL) AS "suspended!",
+                COUNT(*) FILTER (WHERE deleted_at IS NOT NULL) AS "deleted!"
+            FROM cheechat.users
+            "#
+        )
+        .fetch_one(pool)
+        .await?;
+
+    Ok(stats)
+}
+
+/// Sets the role of the user.
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn set_user_role(pool: &PgPool, user_id: i64, role: Role) -> Result<(), ApiError> {
+    sqlx::query!("UPDATE cheechat.users SET role = $1 WHERE id = $2", role.as_str(), user_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Adds an API token (its hash) for the user.
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn add_api_token(pool: &PgPool, user_id: i64, token_hash: &str) -> Result<i64, ApiError> {
+    let row = sqlx::query!(
+            "INSERT INTO cheechat.api_tokens (user_id, token_hash) VALUES ($1, $2) RETURNING id",
+            user_id,
+            token_hash
+        )
+        .fetch_one(pool)
+        .await?;
+
+    Ok(row.id)
+}
+
+/// Looks up the active API token by its hash and marks it as used.
+#[instrument(target = "cheechat::db", skip_all)]
+pub async fn use_api_token(pool: &PgPool, token_hash: &str) -> Result<Option<ApiToken>, ApiError> {
+    let token = sqlx::query_as!(
+            ApiToken,
+            "UPDATE cheechat.api_tokens SET last_used_at = CURRENT_TIMESTAMP WHERE token_hash = $1 AND revoked_at IS NULL RETURNING *",
+            token_hash
+        )
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(token)
+}
+
+/// Revokes all the active API tokens of the user and returns their ids.
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn revoke_api_tokens(pool: &PgPool, user_id: i64) -> Result<Vec<i64>, ApiError> {
+    let rows = sqlx::query!(
+            "UPDATE cheechat.api_tokens SET revoked_at = CURRENT_TIMESTAMP WHERE user_id = $1 AND revoked_at IS NULL RETURNING id",
+            user_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.into_iter().map(|row| row.id).collect())
+}
@@ -0,0 +1,138 @@
+use sqlx::postgres::PgPool;
+use tracing::instrument;
+
+use crate::errors::ApiError;
+use crate::metrics;
+use crate::users::models::ClientInfo;
+
+/// The progressive lockout policy applied to the failed login attempts of a key.
+///
+/// After the free attempts, every failure locks the key for twice as long as the previous one.
+pub struct LockoutPolicy {
+    pub free_attempts: i32,
+    pub base_lock_secs: i64,
+    pub max_lock_secs: i64,
+}
+
+impl LockoutPolicy {
+    /// The lock duration after the given number of consecutive failures, if any.
+    pub fn lock_secs(&self, failures: i32) -> Option<i64> {
+        if failures <= self.free_attempts {
+            return None;
+        }
+        let exponent = (failures - self.free_attempts - 1).min(20) as u32;
+        Some((self.base_lock_secs << exponent).min(self.max_lock_secs))
+    }
+}
+
+/// The policy for the failed attempts on a single account.
+pub const ACCOUNT_POLICY: LockoutPolicy = LockoutPolicy {
+    free_attempts: 5,
+    base_lock_secs: 30,
+    max_lock_secs: 3600,
+};
+
+/// The policy for the failed attempts from a single IP address, across all accounts.
+pub const IP_POLICY: LockoutPolicy = LockoutPolicy {
+    free_attempts: 20,
+    base_lock_secs: 60,
+    max_lock_secs: 3600,
+};
+
+pub fn account_key(user_id: i64) -> String {
+    format!("user:{user_id}")
+}
+
+pub fn ip_key(client: &ClientInfo) -> Option<String> {
+    client.ip_address.as_ref().map(|ip| format!("ip:{ip}"))
+}
+
+/// Fails with the remaining lock time if the key is locked.
+#[instrument(target = "cheechat::db", skip_all)]
+pub async fn ensure_unlocked(pool: &PgPool, key: &str) -> Result<(), ApiError> {
+    let row = sqlx::query!(
+        r#"SELECT EXTRACT(EPOCH FROM (locked_until - CURRENT_TIMESTAMP))::BIGINT AS "retry_after!"
+        FROM cheechat.login_failures WHERE key = $1 AND locked_until > CURRENT_TIMESTAMP"#,
+        key
+    )
+        .fetch_optional(pool)
+        .await?;
+
+    match row {
+        Some(row) => {
+            metrics::count_rate_limited("login");
+            Err(ApiError::TooManyAttempts(row.retry_after.max(1)))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Counts a failed attempt for the key and locks it according to the policy.
+/// The failures are forgotten after a day without failed attempts.
+///
+/// Returns whether the key got locked.
+#[instrument(target = "cheechat::db", skip_all)]
+pub async fn record_failure(pool: &PgPool, key: &str, policy: &LockoutPolicy) -> Result<bool, ApiError> {
+    let row = sqlx::query!(
+        r#"INSERT INTO cheechat.login_failures (key, failures) VALUES ($1, 1)
+        ON CONFLICT (key) DO UPDATE SET
+            failures = CASE
+                WHEN login_failures.last_failure_at < CURRENT_TIMESTAMP - INTERVAL '1 day' THEN 1
+                ELSE login_failures.failures + 1
+            END,
+            last_failure_at = CURRENT_TIMESTAMP
+        RETURNING failures"#,
+        key
+    )
+        .fetch_one(pool)
+        .await?;
+
+    let Some(lock_secs) = policy.lock_secs(row.failures) else {
+        return Ok(false);
+    };
+
+    sqlx::query!(
+        "UPDATE cheechat.login_failures SET locked_until = CURRENT_TIMESTAMP + $2::FLOAT8 * INTERVAL '1 second' WHERE key = $1",
+        key,
+        lock_secs as f64
+    )
+        .execute(pool)
+        .await?;
+
+    Ok(true)
+}
+
+/// Forgets the failed attempts of the key after a successful login.
+#[instrument(target = "cheechat::db", skip_all)]
+pub async fn reset(pool: &PgPool, key: &str) -> Result<(), ApiError> {
+    sqlx::query!("DELETE FROM cheechat.login_failures WHERE key = $1", key)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lets_the_free_attempts_through() {
+        assert_eq!(ACCOUNT_POLICY.lock_secs(0), None);
+        assert_eq!(ACCOUNT_POLICY.lock_secs(5), None);
+    }
+
+    #[test]
+    fn doubles_the_lock_after_every_failure() {
+        assert_eq!(ACCOUNT_POLICY.lock_secs(6), Some(30));
+        assert_eq!(ACCOUNT_POLICY.lock_secs(7), Some(60));
+        assert_eq!(ACCOUNT_POLICY.lock_secs(8), Some(120));
+        assert_eq!(IP_POLICY.lock_secs(21), Some(60));
+    }
+
+    #[test]
+    fn caps_the_lock() {
+        assert_eq!(ACCOUNT_POLICY.lock_secs(13), Some(3600));
+        assert_eq!(ACCOUNT_POLICY.lock_secs(i32::MAX), Some(3600));
+    }
+}
@@ -1,30 +1,62 @@
-use actix_session::Session;
-use actix_web::{web, Error};
+use actix_web::web;
+use utoipa::OpenApi;
 
+mod auth;
+mod lockout;
 mod services;
+mod two_factor;
 mod db;
 mod models;
 
+pub use auth::*;
 pub use db::*;
 pub use models::*;
-use crate::errors::ApiError;
 
+/// Registers the routes that are reachable without a session.
 pub fn init_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(services::register);
     cfg.service(services::login);
+    cfg.service(services::login_second_factor);
     cfg.service(services::logout);
+}
+
+/// Registers the routes that require an authenticated user.
+pub fn init_protected_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(services::get_user);
     cfg.service(services::get_current_user_id);
+    cfg.service(services::get_profile);
+    cfg.service(services::update_profile);
+    cfg.service(services::delete_account);
+    cfg.service(services::change_password);
+    cfg.service(services::get_sessions);
+    cfg.service(services::revoke_sessions);
+    cfg.service(services::revoke_session);
+    cfg.service(services::setup_two_factor);
+    cfg.service(services::confirm_two_factor);
+    cfg.service(services::disable_two_factor);
 }
 
-
-/// Authenticates the user.
-pub async fn authenticate_user(session: Session) -> Result<i64, Error>{
-    let user_id: Option<String> = session.get::<String>("user_id")?;
-    if let None = user_id {
-        return Err(ApiError::AuthError.into())
-    }
-    let user_id = user_id.unwrap().parse::<i64>().expect("Error parsing i64 user id");
-
-    Ok(user_id)
-}
\ No newline at end of file
+/// The OpenAPI description of the routes.
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        services::register,
+        services::login,
+        services::login_second_factor,
+        services::logout,
+        services::get_user,
+        services::get_current_user_id,
+        services::get_profile,
+        services::update_profile,
+        services::delete_account,
+        services::change_password,
+        services::get_sessions,
+        services::revoke_sessions,
+        services::revoke_session,
+        services::setup_two_factor,
+        services::confirm_two_factor,
+        services::disable_two_factor,
+    ),
+    tags((name = "users", description = "The registration, the login and the account of the current user")),
+)]
+pub struct ApiDoc;
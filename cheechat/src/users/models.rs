@@ -1,5 +1,12 @@
+use actix_web::http::header;
+use actix_web::{web, HttpRequest};
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
 use sqlx;
+use sqlx::types::time::PrimitiveDateTime;
 
 #[derive(Debug, sqlx::FromRow)]
 pub struct User {
@@ -9,28 +16,97 @@ pub struct User {
     pub last_name: String,
     pub password: String,
     pub username: String,
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
+    pub avatar_url: Option<String>,
+    pub status_text: Option<String>,
+    pub deleted_at: Option<PrimitiveDateTime>,
+    pub suspended_at: Option<PrimitiveDateTime>,
+    pub suspension_reason: Option<String>,
+    pub role: String,
+    pub is_bot: bool,
+    /// The user that manages the bot, for the bot users.
+    pub bot_owner_id: Option<i64>,
+    /// The time step of the last accepted code of the authenticator app.
+    pub totp_last_step: Option<i64>,
 }
 
-#[derive(Deserialize)]
+impl User {
+    /// The role of the user, unknown roles fall back to the least privileged one.
+    pub fn role(&self) -> Role {
+        self.role.parse().unwrap_or(Role::User)
+    }
+}
+
+/// The roles of the users, ordered from the least to the most privileged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Moderator,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(role: &str) -> Result<Self, Self::Err> {
+        match role {
+            "user" => Ok(Role::User),
+            "moderator" => Ok(Role::Moderator),
+            "admin" => Ok(Role::Admin),
+            role => Err(format!("Unknown role: {role}")),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
 pub struct Credentials {
     pub username: String,
+    #[schema(format = Password)]
     pub password: String,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, ToSchema)]
+pub struct LoginResponse {
+    /// Whether the login has to be completed with a second factor code.
+    pub two_factor_required: bool,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
 pub struct UserInfo {
     pub id: i64,
     pub email: String,
     pub first_name: String,
     pub last_name: String,
     pub username: String,
+    pub avatar_url: Option<String>,
+    pub status_text: Option<String>,
+    pub is_bot: bool,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, ToSchema)]
 pub struct RegisterUser {
     pub email: String,
     pub first_name: String,
     pub last_name: String,
+    #[schema(format = Password)]
     pub password: String,
     pub username: String,
 }
@@ -43,12 +119,299 @@ impl UserInfo {
             first_name: user.first_name,
             last_name: user.last_name,
             username: user.username,
+            avatar_url: user.avatar_url,
+            status_text: user.status_text,
+            is_bot: user.is_bot,
+        }
+    }
+}
+
+/// The profile fields to update, the missing ones are left unchanged.
+/// An empty avatar or status text clears it.
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct UpdateProfile {
+    pub email: Option<String>,
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub username: Option<String>,
+    pub avatar_url: Option<String>,
+    pub status_text: Option<String>,
+}
+
+impl UpdateProfile {
+    /// Validates the provided fields against the limits of the users table.
+    pub fn validate(&self) -> Result<(), String> {
+        let required = [
+            ("email", &self.email, 200),
+            ("first_name", &self.first_name, 200),
+            ("last_name", &self.last_name, 200),
+            ("username", &self.username, 50),
+        ];
+        for (field, value, max_length) in required {
+            if let Some(value) = value {
+                if value.trim().is_empty() {
+                    return Err(format!("The {field} can't be empty"));
+                }
+                if value.chars().count() > max_length {
+                    return Err(format!("The {field} can't be longer than {max_length} characters"));
+                }
+            }
+        }
+
+        let optional = [
+            ("avatar_url", &self.avatar_url, 500),
+            ("status_text", &self.status_text, 200),
+        ];
+        for (field, value, max_length) in optional {
+            if value.as_ref().is_some_and(|value| value.chars().count() > max_length) {
+                return Err(format!("The {field} can't be longer than {max_length} characters"));
+            }
+        }
+
+        if self.email.as_ref().is_some_and(|email| !email.contains('@')) {
+            return Err("The email is not valid".into());
         }
+
+        Ok(())
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct ChangePassword {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct DeleteAccount {
+    pub password: String,
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct GetUser {
     pub username: String
 }
 
+/// A login session of the user, tracked alongside the redis session.
+#[derive(Debug, sqlx::FromRow)]
+pub struct UserSession {
+    pub id: i64,
+    pub user_id: i64,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: PrimitiveDateTime,
+    pub last_active_at: PrimitiveDateTime,
+    pub revoked_at: Option<PrimitiveDateTime>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct SessionInfo {
+    pub id: i64,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: i64,
+    pub last_active_at: i64,
+    pub current: bool,
+}
+
+impl SessionInfo {
+    pub fn from_session(session: UserSession, current_session_id: Option<i64>) -> Self {
+        Self {
+            id: session.id,
+            user_agent: session.user_agent,
+            ip_address: session.ip_address,
+            created_at: session.created_at.assume_utc().unix_timestamp(),
+            last_active_at: session.last_active_at.assume_utc().unix_timestamp(),
+            current: Some(session.id) == current_session_id,
+        }
+    }
+}
+
+/// The device and network details of the client that sent the request.
+#[derive(Debug, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+impl ClientInfo {
+    /// The IP address is the one of the connection, unless it comes from a trusted proxy,
+    /// see [`TrustedProxies::client_ip`].
+    pub fn from_request(req: &HttpRequest) -> Self {
+        let peer_ip = req.peer_addr().map(|addr| addr.ip());
+        let ip_address = match req.app_data::<web::Data<TrustedProxies>>() {
+            Some(proxies) => peer_ip.map(|peer_ip| proxies.client_ip(peer_ip, req)),
+            None => peer_ip,
+        };
+
+        Self {
+            user_agent: req.headers()
+                .get(header::USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned),
+            ip_address: ip_address.map(|ip| ip.to_string()),
+        }
+    }
+}
+
+/// The reverse proxies whose `X-Forwarded-For` header is trusted.
+///
+/// The header is set by the clients as they see fit, so only the addresses appended by the trusted proxies
+/// are taken into account, otherwise anyone could dodge the IP lockout or forge the IPs of the audit log.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Vec<IpAddr>);
+
+impl TrustedProxies {
+    pub fn from_config(proxies: &[String]) -> Result<Self, String> {
+        proxies.iter()
+            .map(|proxy| proxy.parse().map_err(|_| format!("{proxy} is not an IP address")))
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    /// Resolves the address of the client: the connection comes from a trusted proxy, the last address
+    /// of the `X-Forwarded-For` header that isn't one of the trusted proxies is the client.
+    ///
+    /// The header is read from the right, the side appended by the trusted proxies: the entries left of
+    /// an invalid one were written by the client, so the last trusted hop is the client then.
+    pub fn client_ip(&self, peer_ip: IpAddr, req: &HttpRequest) -> IpAddr {
+        if !self.0.contains(&peer_ip) {
+            return peer_ip;
+        }
+
+        let forwarded: Vec<&str> = req.headers()
+            .get_all("x-forwarded-for")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+        let mut last_hop = peer_ip;
+        for entry in forwarded.into_iter().rev() {
+            match entry.trim().parse() {
+                Ok(ip) if self.0.contains(&ip) => last_hop = ip,
+                Ok(ip) => return ip,
+                Err(_) => break,
+            }
+        }
+        last_hop
+    }
+}
+
+/// A hashed one-time recovery code for the two-factor authentication.
+#[derive(Debug, sqlx::FromRow)]
+pub struct RecoveryCode {
+    pub id: i64,
+    pub user_id: i64,
+    pub code_hash: String,
+    pub used_at: Option<PrimitiveDateTime>,
+}
+
+/// The second factor code, either from the authenticator app or a recovery code.
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct TwoFactorCode {
+    pub code: String,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct TwoFactorSetup {
+    pub secret: String,
+    /// The `otpauth://` URI to be rendered as a QR code by the client.
+    pub provisioning_uri: String,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct DisableTwoFactor {
+    pub password: String,
+    pub code: String,
+}
+
+/// The user counters of the deployment.
+#[derive(Serialize, Debug)]
+pub struct UserStats {
+    pub total: i64,
+    pub suspended: i64,
+    pub deleted: i64,
+}
+
+/// An API token of a bot, only the hash of the token is stored.
+#[derive(Debug, sqlx::FromRow)]
+pub struct ApiToken {
+    pub id: i64,
+    pub user_id: i64,
+    pub token_hash: String,
+    pub created_at: PrimitiveDateTime,
+    pub last_used_at: Option<PrimitiveDateTime>,
+    pub revoked_at: Option<PrimitiveDateTime>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn proxies() -> TrustedProxies {
+        TrustedProxies::from_config(&["10.0.0.2".into()]).unwrap()
+    }
+
+    #[test]
+    fn client_ip_ignores_the_forwarded_header_of_untrusted_peers() {
+        let req = TestRequest::default()
+            .peer_addr("203.0.113.7:4000".parse().unwrap())
+            .insert_header(("x-forwarded-for", "198.51.100.1"))
+            .to_http_request();
+
+        assert_eq!(proxies().client_ip("203.0.113.7".parse().unwrap(), &req), "203.0.113.7".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn client_ip_takes_the_last_untrusted_forwarded_address() {
+        let req = TestRequest::default()
+            .insert_header(("x-forwarded-for", "198.51.100.1, 203.0.113.7, 10.0.0.2"))
+            .to_http_request();
+
+        assert_eq!(proxies().client_ip("10.0.0.2".parse().unwrap(), &req), "203.0.113.7".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn client_ip_ignores_the_forged_entries_left_of_the_client() {
+        let req = TestRequest::default()
+            .insert_header(("x-forwarded-for", "garbage, 203.0.113.7"))
+            .to_http_request();
+
+        assert_eq!(proxies().client_ip("10.0.0.2".parse().unwrap(), &req), "203.0.113.7".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn client_ip_stops_at_the_invalid_entries() {
+        let req = TestRequest::default()
+            .insert_header(("x-forwarded-for", "203.0.113.7, garbage"))
+            .to_http_request();
+
+        assert_eq!(proxies().client_ip("10.0.0.2".parse().unwrap(), &req), "10.0.0.2".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn trusted_proxies_must_be_ip_addresses() {
+        assert!(TrustedProxies::from_config(&["proxy.local".into()]).is_err());
+    }
+
+    #[test]
+    fn orders_the_roles_by_privilege() {
+        assert!(Role::User < Role::Moderator);
+        assert!(Role::Moderator < Role::Admin);
+        assert_eq!([Role::Admin, Role::User, Role::Moderator].iter().max(), Some(&Role::Admin));
+    }
+
+    #[test]
+    fn parses_the_stored_roles() {
+        for role in [Role::User, Role::Moderator, Role::Admin] {
+            assert_eq!(role.as_str().parse::<Role>(), Ok(role));
+        }
+        assert!("root".parse::<Role>().is_err());
+    }
+}
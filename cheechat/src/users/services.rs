@@ -1,14 +1,25 @@
+use actix::Addr;
 use actix_session::Session;
-use actix_web::{get, post, web, Error, HttpResponse};
+use actix_web::web::Path;
+use actix_web::{delete, get, patch, post, web, Error, HttpRequest, HttpResponse};
 use bcrypt::{hash, verify};
+use chrono::Utc;
 use sqlx::postgres::PgPool;
 
+use crate::audit;
+use crate::audit::AuditEvent;
+use crate::chat::{ChatServer, MessageCipher, RevokeSessions};
 use crate::errors::ApiError;
-use crate::users::models::{GetUser, UserInfo};
+use crate::webhooks;
+use crate::webhooks::WebhookEvent;
+use crate::users::models::{ChangePassword, ClientInfo, DeleteAccount, DisableTwoFactor, GetUser, LoginResponse, RecoveryCodes, SessionInfo, TwoFactorCode, TwoFactorSetup, UpdateProfile, UserInfo};
 
-use super::{authenticate_user, db};
+use super::{authenticate_user, db, lockout, two_factor, unusable_password_hash, AuthUser, Credential};
 use super::{models, RegisterUser};
 
+/// The time in seconds a user has to provide the second factor code after the password.
+const PENDING_LOGIN_TTL: i64 = 300;
+
 /// Retrieves all the users.
 #[get("/get-users")]
 pub async fn get_users(db_pool: web::Data<PgPool>) -> Result<HttpResponse, Error> {
@@ -19,70 +30,479 @@ pub async fn get_users(db_pool: web::Data<PgPool>) -> Result<HttpResponse, Error
 }
 
 /// Registers a new user.
+#[utoipa::path(
+    tag = "users",
+    request_body = RegisterUser,
+    responses((status = 200, description = "The user is registered")),
+    security(()),
+)]
 #[post("/register")]
 pub async fn register(
     user: web::Json<RegisterUser>,
     db_pool: web::Data<PgPool>,
+    cipher: web::Data<MessageCipher>,
 ) -> Result<HttpResponse, Error> {
 
     let mut user: RegisterUser = user.into_inner();
     user.password = hash(user.password, 10).unwrap();
+    let username = user.username.clone();
 
-    let _ = db::add_user(db_pool.get_ref(), user).await?;
+    let user_id = db::add_user(db_pool.get_ref(), user).await?;
+    webhooks::dispatch(db_pool.get_ref(), cipher.get_ref(), WebhookEvent::UserRegistered { user_id, username }).await;
 
     Ok(HttpResponse::Ok().finish())
 }
 
 /// Handles login by adding a new session based on the user id if the credentials are correct.
+/// Users with two-factor authentication have to complete the login with the second factor code.
+///
+/// The failed attempts are counted per account and IP address, which get locked progressively.
+#[utoipa::path(
+    tag = "users",
+    request_body = models::Credentials,
+    responses(
+        (status = 200, description = "The session cookie is set, unless the second factor is required", body = LoginResponse),
+        (status = 401, description = "Invalid credentials"),
+        (status = 403, description = "The account is suspended"),
+        (status = 429, description = "Too many failed attempts, retry after the `Retry-After` seconds"),
+    ),
+    security(()),
+)]
 #[post("/login")]
-pub async fn login(db_pool: web::Data<PgPool>, credentials: web::Json<models::Credentials>, session: Session) -> Result<HttpResponse, Error> {
+pub async fn login(db_pool: web::Data<PgPool>, credentials: web::Json<models::Credentials>, session: Session, req: HttpRequest) -> Result<HttpResponse, Error> {
     let credentials = credentials.into_inner();
+    let client = ClientInfo::from_request(&req);
+
+    if let Some(ip_key) = lockout::ip_key(&client) {
+        lockout::ensure_unlocked(db_pool.get_ref(), &ip_key).await?;
+    }
 
-    let user = db::get_user(db_pool.get_ref(), &credentials.username).await?;
+    let Some(user) = db::find_user(db_pool.get_ref(), &credentials.username).await? else {
+        record_failed_login(db_pool.get_ref(), None, &client).await?;
+        return Err(ApiError::AuthError.into());
+    };
+    lockout::ensure_unlocked(db_pool.get_ref(), &lockout::account_key(user.id)).await?;
 
     if !verify(credentials.password, &user.password).unwrap() {
+        record_failed_login(db_pool.get_ref(), Some(user.id), &client).await?;
         return Err(ApiError::AuthError.into());
     }
+    if user.suspended_at.is_some() {
+        return Err(ApiError::Forbidden.into());
+    }
+
+    if user.totp_enabled {
+        let expires_at = Utc::now().timestamp() + PENDING_LOGIN_TTL;
+        session.insert("pending_login", (user.id, expires_at))?;
+        return Ok(HttpResponse::Ok().json(LoginResponse { two_factor_required: true }));
+    }
+
+    start_session(db_pool.get_ref(), user.id, &session, &client).await?;
+
+    Ok(HttpResponse::Ok().json(LoginResponse { two_factor_required: false }))
+}
+
+/// Completes the login of a user with two-factor authentication.
+#[utoipa::path(
+    tag = "users",
+    request_body = TwoFactorCode,
+    responses(
+        (status = 200, description = "The session cookie is set", body = LoginResponse),
+        (status = 401, description = "Invalid code, or no pending login"),
+        (status = 403, description = "The account is suspended"),
+        (status = 429, description = "Too many failed attempts, retry after the `Retry-After` seconds"),
+    ),
+    security(()),
+)]
+#[post("/login/2fa")]
+pub async fn login_second_factor(db_pool: web::Data<PgPool>, request: web::Json<TwoFactorCode>, session: Session, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let client = ClientInfo::from_request(&req);
+
+    let (user_id, expires_at) = session.get::<(i64, i64)>("pending_login")?
+        .ok_or(ApiError::AuthError)?;
+    if expires_at < Utc::now().timestamp() {
+        session.remove("pending_login");
+        return Err(ApiError::AuthError.into());
+    }
+    if let Some(ip_key) = lockout::ip_key(&client) {
+        lockout::ensure_unlocked(db_pool.get_ref(), &ip_key).await?;
+    }
+    lockout::ensure_unlocked(db_pool.get_ref(), &lockout::account_key(user_id)).await?;
+
+    let user = db::get_user_by_id(db_pool.get_ref(), user_id).await?
+        .ok_or(ApiError::AuthError)?;
+    if !two_factor::verify_second_factor(db_pool.get_ref(), &user, &request.code).await? {
+        record_failed_login(db_pool.get_ref(), Some(user.id), &client).await?;
+        return Err(ApiError::AuthError.into());
+    }
+
+    session.remove("pending_login");
+    // The account may have been suspended or deleted since the password step
+    if user.deleted_at.is_some() {
+        return Err(ApiError::AuthError.into());
+    }
+    if user.suspended_at.is_some() {
+        return Err(ApiError::Forbidden.into());
+    }
+    start_session(db_pool.get_ref(), user.id, &session, &client).await?;
+
+    Ok(HttpResponse::Ok().json(LoginResponse { two_factor_required: false }))
+}
+
+/// Tracks a new login session for the user and stores it in the session.
+async fn start_session(db_pool: &PgPool, user_id: i64, session: &Session, client: &ClientInfo) -> Result<(), Error> {
+    lockout::reset(db_pool, &lockout::account_key(user_id)).await?;
 
-    let id = user.id.to_string();
-    session.insert("user_id", &id)?;
+    // Track the login session, so the user can review and revoke it later
+    let session_id = db::add_user_session(db_pool, user_id, client).await?;
+    audit::add_event(db_pool, user_id, AuditEvent::Login, client, None).await?;
+
+    session.insert("user_id", user_id.to_string())?;
+    session.insert("session_id", session_id)?;
     session.renew();
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(())
 }
 
+/// Counts the failed login attempt for the IP address and the account, if known, and audits it.
+async fn record_failed_login(db_pool: &PgPool, user_id: Option<i64>, client: &ClientInfo) -> Result<(), ApiError> {
+    if let Some(ip_key) = lockout::ip_key(client) {
+        lockout::record_failure(db_pool, &ip_key, &lockout::IP_POLICY).await?;
+    }
+
+    if let Some(user_id) = user_id {
+        audit::add_event(db_pool, user_id, AuditEvent::LoginFailed, client, None).await?;
+        if lockout::record_failure(db_pool, &lockout::account_key(user_id), &lockout::ACCOUNT_POLICY).await? {
+            audit::add_event(db_pool, user_id, AuditEvent::AccountLocked, client, None).await?;
+        }
+    }
+
+    Ok(())
+}
 
-/// Handles logout
+/// Handles logout by revoking the current login session.
+#[utoipa::path(
+    tag = "users",
+    responses((status = 200, description = "The outcome of the logout", body = String, content_type = "text/plain")),
+    security(()),
+)]
 #[post("/logout")]
-pub async fn logout(session: Session) -> actix_web::Result<String> {
-    let id: Option<String> = session.get("user_id")?;
-    if let Some(x) = id {
+pub async fn logout(session: Session, db_pool: web::Data<PgPool>, chat_server: web::Data<Addr<ChatServer>>, req: HttpRequest) -> actix_web::Result<String> {
+    if let Ok((user_id, session_id)) = authenticate_user(&session) {
         session.purge();
-        Ok(format!("Logged out: {x}"))
+        if db::revoke_user_session(db_pool.get_ref(), user_id, session_id).await? {
+            audit::add_event(db_pool.get_ref(), user_id, AuditEvent::Logout, &ClientInfo::from_request(&req), None).await?;
+        }
+        chat_server.do_send(RevokeSessions {
+            user_id,
+            credentials: Some(vec![Credential::Session(session_id)]),
+            reason: "Logged out".into(),
+        });
+        Ok(format!("Logged out: {user_id}"))
     } else {
         Ok("Could not log out anonymous user".into())
     }
 }
 
 /// Gets a user with details based on the provided username
+#[utoipa::path(
+    tag = "users",
+    params(GetUser),
+    responses(
+        (status = 200, body = UserInfo),
+        (status = 404, description = "No such user"),
+    ),
+)]
 #[get("/get-user")]
-pub async fn get_user(db_pool: web::Data<PgPool>, query_params: web::Query<GetUser>, session: Session) -> Result<HttpResponse, Error> {
-    let _ = authenticate_user(session).await?;
-
+pub async fn get_user(db_pool: web::Data<PgPool>, query_params: web::Query<GetUser>, _: AuthUser) -> Result<HttpResponse, Error> {
     let username = query_params.username.clone();
-    println!("user name to search {:}", username);
     let user = db::get_user(db_pool.get_ref(), username.as_str()).await?;
 
     Ok(HttpResponse::Ok().json(UserInfo::from_user(user)))
 }
 
 /// Gets current user
+#[utoipa::path(
+    tag = "users",
+    responses((status = 200, description = "The id of the current user", body = i64)),
+)]
 #[get("/get-current-user")]
-pub async fn get_current_user_id(session: Session) -> Result<HttpResponse, Error>{
-    let user_id = authenticate_user(session).await?;
+pub async fn get_current_user_id(auth_user: AuthUser) -> Result<HttpResponse, Error>{
+    Ok(HttpResponse::Ok().json(auth_user.id))
+}
+
+/// Lists the active login sessions of the current user.
+#[utoipa::path(
+    tag = "users",
+    responses((status = 200, body = Vec<SessionInfo>)),
+)]
+#[get("/users/me/sessions")]
+pub async fn get_sessions(auth_user: AuthUser, db_pool: web::Data<PgPool>) -> Result<HttpResponse, Error> {
+    let sessions = db::get_user_sessions(db_pool.get_ref(), auth_user.id).await?;
+
+    let sessions: Vec<_> = sessions.into_iter()
+        .map(|session| SessionInfo::from_session(session, auth_user.login_session_id()))
+        .collect();
+    Ok(HttpResponse::Ok().json(sessions))
+}
 
-    Ok(HttpResponse::Ok().json(user_id))
+/// Revokes a login session of the current user and terminates its websocket connections.
+#[utoipa::path(
+    tag = "users",
+    params(("session_id" = i64, Path, description = "The id of the login session")),
+    responses(
+        (status = 204, description = "The session is revoked"),
+        (status = 404, description = "No such session"),
+    ),
+)]
+#[delete("/users/me/sessions/{session_id}")]
+pub async fn revoke_session(
+    path: Path<i64>,
+    auth_user: AuthUser,
+    session: Session,
+    db_pool: web::Data<PgPool>,
+    chat_server: web::Data<Addr<ChatServer>>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let session_id = path.into_inner();
+
+    if !db::revoke_user_session(db_pool.get_ref(), auth_user.id, session_id).await? {
+        return Err(ApiError::NotFound.into());
+    }
+    let details = format!("session {session_id}");
+    audit::add_event(db_pool.get_ref(), auth_user.id, AuditEvent::SessionRevoked, &ClientInfo::from_request(&req), Some(&details)).await?;
+    if Some(session_id) == auth_user.login_session_id() {
+        session.purge();
+    }
+
+    chat_server.do_send(RevokeSessions {
+        user_id: auth_user.id,
+        credentials: Some(vec![Credential::Session(session_id)]),
+        reason: "Session revoked".into(),
+    });
+
+    Ok(HttpResponse::NoContent().finish())
 }
 
+/// Revokes all the login sessions of the current user (logout everywhere)
+/// and terminates their websocket connections.
+#[utoipa::path(
+    tag = "users",
+    responses((status = 204, description = "The sessions are revoked")),
+)]
+#[delete("/users/me/sessions")]
+pub async fn revoke_sessions(
+    auth_user: AuthUser,
+    session: Session,
+    db_pool: web::Data<PgPool>,
+    chat_server: web::Data<Addr<ChatServer>>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    db::revoke_user_sessions(db_pool.get_ref(), auth_user.id, None).await?;
+    audit::add_event(db_pool.get_ref(), auth_user.id, AuditEvent::SessionRevoked, &ClientInfo::from_request(&req), Some("all sessions")).await?;
+    session.purge();
+
+    chat_server.do_send(RevokeSessions {
+        user_id: auth_user.id,
+        credentials: None,
+        reason: "Session revoked".into(),
+    });
 
+    Ok(HttpResponse::NoContent().finish())
+}
 
+/// Starts the two-factor authentication enrollment by generating a new secret.
+/// The two-factor authentication is enabled once confirmed with a code of the authenticator app.
+#[utoipa::path(
+    tag = "users",
+    responses(
+        (status = 200, body = TwoFactorSetup),
+        (status = 400, description = "Two-factor authentication is already enabled", body = String, content_type = "text/plain"),
+    ),
+)]
+#[post("/users/me/2fa/setup")]
+pub async fn setup_two_factor(auth_user: AuthUser, db_pool: web::Data<PgPool>) -> Result<HttpResponse, Error> {
+    let user = db::get_user(db_pool.get_ref(), &auth_user.user.username).await?;
+    if user.totp_enabled {
+        return Err(ApiError::BadRequest("Two-factor authentication is already enabled".into()).into());
+    }
+
+    let secret = two_factor::generate_secret();
+    let totp = two_factor::build_totp(&secret, &user.username)?;
+    db::set_totp_secret(db_pool.get_ref(), user.id, Some(&secret)).await?;
+
+    Ok(HttpResponse::Ok().json(TwoFactorSetup {
+        secret,
+        provisioning_uri: totp.get_url(),
+    }))
+}
+
+/// Confirms the two-factor authentication enrollment and returns the one-time recovery codes.
+#[utoipa::path(
+    tag = "users",
+    request_body = TwoFactorCode,
+    responses(
+        (status = 200, body = RecoveryCodes),
+        (status = 400, description = "Invalid code, or no pending enrollment", body = String, content_type = "text/plain"),
+    ),
+)]
+#[post("/users/me/2fa/confirm")]
+pub async fn confirm_two_factor(auth_user: AuthUser, request: web::Json<TwoFactorCode>, db_pool: web::Data<PgPool>, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let user = db::get_user(db_pool.get_ref(), &auth_user.user.username).await?;
+    if user.totp_enabled || user.totp_secret.is_none() {
+        return Err(ApiError::BadRequest("There is no pending two-factor enrollment".into()).into());
+    }
+    if !two_factor::verify_totp(db_pool.get_ref(), &user, &request.code).await? {
+        return Err(ApiError::BadRequest("Invalid two-factor code".into()).into());
+    }
+
+    db::enable_totp(db_pool.get_ref(), user.id).await?;
+    audit::add_event(db_pool.get_ref(), user.id, AuditEvent::TwoFactorEnabled, &ClientInfo::from_request(&req), None).await?;
+    let recovery_codes = two_factor::regenerate_recovery_codes(db_pool.get_ref(), user.id).await?;
+
+    Ok(HttpResponse::Ok().json(RecoveryCodes { recovery_codes }))
+}
+
+/// Disables the two-factor authentication after re-authenticating with the password and a second factor code.
+#[utoipa::path(
+    tag = "users",
+    request_body = DisableTwoFactor,
+    responses(
+        (status = 204, description = "Two-factor authentication is disabled"),
+        (status = 401, description = "Invalid password or code"),
+    ),
+)]
+#[post("/users/me/2fa/disable")]
+pub async fn disable_two_factor(auth_user: AuthUser, request: web::Json<DisableTwoFactor>, db_pool: web::Data<PgPool>, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let request = request.into_inner();
+    let user = db::get_user(db_pool.get_ref(), &auth_user.user.username).await?;
+
+    if !verify(request.password, &user.password).unwrap() {
+        return Err(ApiError::AuthError.into());
+    }
+    if !two_factor::verify_second_factor(db_pool.get_ref(), &user, &request.code).await? {
+        return Err(ApiError::AuthError.into());
+    }
+
+    db::set_totp_secret(db_pool.get_ref(), user.id, None).await?;
+    db::set_recovery_codes(db_pool.get_ref(), user.id, &[]).await?;
+    audit::add_event(db_pool.get_ref(), user.id, AuditEvent::TwoFactorDisabled, &ClientInfo::from_request(&req), None).await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Gets the profile of the current user
+#[utoipa::path(
+    tag = "users",
+    responses((status = 200, body = UserInfo)),
+)]
+#[get("/users/me")]
+pub async fn get_profile(auth_user: AuthUser) -> Result<HttpResponse, Error> {
+    Ok(HttpResponse::Ok().json(auth_user.user))
+}
+
+/// Updates the profile fields of the current user
+#[utoipa::path(
+    tag = "users",
+    request_body = UpdateProfile,
+    responses(
+        (status = 200, body = UserInfo),
+        (status = 400, description = "Invalid field, or the username is taken", body = String, content_type = "text/plain"),
+    ),
+)]
+#[patch("/users/me")]
+pub async fn update_profile(auth_user: AuthUser, request: web::Json<UpdateProfile>, db_pool: web::Data<PgPool>) -> Result<HttpResponse, Error> {
+    let profile = request.into_inner();
+    profile.validate().map_err(ApiError::BadRequest)?;
+
+    let user = db::update_profile(db_pool.get_ref(), auth_user.id, &profile)
+        .await
+        .map_err(|err| match err {
+            ApiError::DbError(sqlx::Error::Database(ref db_err)) if db_err.is_unique_violation() => {
+                ApiError::BadRequest("The username is already taken".into())
+            }
+            err => err,
+        })?;
+
+    Ok(HttpResponse::Ok().json(UserInfo::from_user(user)))
+}
+
+/// Changes the password of the current user and logs out the other sessions.
+#[utoipa::path(
+    tag = "users",
+    request_body = ChangePassword,
+    responses(
+        (status = 204, description = "The password is changed and the other sessions are revoked"),
+        (status = 400, description = "The new password is empty", body = String, content_type = "text/plain"),
+        (status = 401, description = "Invalid current password"),
+    ),
+)]
+#[post("/users/me/password")]
+pub async fn change_password(
+    auth_user: AuthUser,
+    request: web::Json<ChangePassword>,
+    db_pool: web::Data<PgPool>,
+    chat_server: web::Data<Addr<ChatServer>>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let request = request.into_inner();
+    let user = db::get_user(db_pool.get_ref(), &auth_user.user.username).await?;
+
+    if !verify(request.current_password, &user.password).unwrap() {
+        return Err(ApiError::AuthError.into());
+    }
+    if request.new_password.is_empty() {
+        return Err(ApiError::BadRequest("The new password can't be empty".into()).into());
+    }
+
+    db::update_password(db_pool.get_ref(), user.id, &hash(request.new_password, 10).unwrap()).await?;
+    audit::add_event(db_pool.get_ref(), user.id, AuditEvent::PasswordChanged, &ClientInfo::from_request(&req), None).await?;
+
+    let revoked = db::revoke_user_sessions(db_pool.get_ref(), user.id, auth_user.login_session_id()).await?;
+    chat_server.do_send(RevokeSessions {
+        user_id: user.id,
+        credentials: Some(revoked.into_iter().map(Credential::Session).collect()),
+        reason: "Password changed".into(),
+    });
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Deletes the account of the current user after re-authenticating with the password.
+/// The account gets anonymized, so the chats of the other participants are kept.
+#[utoipa::path(
+    tag = "users",
+    request_body = DeleteAccount,
+    responses(
+        (status = 204, description = "The account is anonymized"),
+        (status = 401, description = "Invalid password"),
+    ),
+)]
+#[delete("/users/me")]
+pub async fn delete_account(
+    auth_user: AuthUser,
+    request: web::Json<DeleteAccount>,
+    session: Session,
+    db_pool: web::Data<PgPool>,
+    chat_server: web::Data<Addr<ChatServer>>,
+) -> Result<HttpResponse, Error> {
+    let user = db::get_user(db_pool.get_ref(), &auth_user.user.username).await?;
+
+    if !verify(&request.password, &user.password).unwrap() {
+        return Err(ApiError::AuthError.into());
+    }
+
+    let user_ids = db::anonymize_user(db_pool.get_ref(), user.id, &unusable_password_hash()).await?;
+    session.purge();
+
+    // The bots of the user got deleted too
+    for user_id in user_ids {
+        chat_server.do_send(RevokeSessions {
+            user_id,
+            credentials: None,
+            reason: "Account deleted".into(),
+        });
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,120 @@
+use actix_web::web;
+use bcrypt::{hash, verify};
+use chrono::Utc;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sqlx::postgres::PgPool;
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::errors::ApiError;
+use crate::users::db;
+use crate::users::models::User;
+
+const ISSUER: &str = "Cheechat";
+/// The lifetime of a code of the authenticator app.
+const STEP_SECS: u64 = 30;
+/// The number of steps accepted either side of the current one, for the clock drift of the phones.
+const SKEW_STEPS: u64 = 1;
+const RECOVERY_CODES: usize = 10;
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+/// Generates a new base32 encoded TOTP secret.
+pub fn generate_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+/// Builds the TOTP generator for the secret of the user.
+pub fn build_totp(secret: &str, username: &str) -> Result<TOTP, ApiError> {
+    let secret = Secret::Encoded(secret.to_owned())
+        .to_bytes()
+        .map_err(|_| ApiError::BadRequest("Invalid two-factor secret".into()))?;
+
+    TOTP::new(Algorithm::SHA1, 6, SKEW_STEPS as u8, STEP_SECS, secret, Some(ISSUER.into()), username.to_owned())
+        .map_err(|err| ApiError::BadRequest(err.to_string()))
+}
+
+/// Checks the code of the authenticator app against the secret of the user.
+///
+/// Every code is accepted once: the time step of the last accepted code is stored,
+/// and the codes of that step or of an earlier one are refused.
+pub async fn verify_totp(pool: &PgPool, user: &User, code: &str) -> Result<bool, ApiError> {
+    let Some(secret) = user.totp_secret.as_deref() else {
+        return Ok(false);
+    };
+
+    let totp = build_totp(secret, &user.username)?;
+    let Some(step) = matching_step(&totp, code.trim(), Utc::now().timestamp() as u64) else {
+        return Ok(false);
+    };
+    db::use_totp_step(pool, user.id, step as i64).await
+}
+
+/// The time step the code was generated for, among the ones around the given time.
+fn matching_step(totp: &TOTP, code: &str, time: u64) -> Option<u64> {
+    let current = time / STEP_SECS;
+    (current.saturating_sub(SKEW_STEPS)..=current + SKEW_STEPS)
+        .find(|step| codes_match(&totp.generate(step * STEP_SECS), code))
+}
+
+/// Compares the codes in constant time.
+fn codes_match(expected: &str, code: &str) -> bool {
+    expected.len() == code.len()
+        && expected.bytes().zip(code.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Checks the second factor code of an enabled user, either from the authenticator app
+/// or one of the unused recovery codes. The matched recovery code gets consumed.
+pub async fn verify_second_factor(pool: &PgPool, user: &User, code: &str) -> Result<bool, ApiError> {
+    if !user.totp_enabled {
+        return Ok(false);
+    }
+    if verify_totp(pool, user, code).await? {
+        return Ok(true);
+    }
+
+    // The bcrypt checks are slow, they run on the blocking thread pool
+    let code = normalize_recovery_code(code);
+    let recovery_codes = db::get_recovery_codes(pool, user.id).await?;
+    let matched = web::block(move || {
+        recovery_codes.into_iter()
+            .find(|recovery_code| verify(&code, &recovery_code.code_hash).unwrap_or(false))
+            .map(|recovery_code| recovery_code.id)
+    }).await?;
+
+    match matched {
+        Some(recovery_code_id) => db::use_recovery_code(pool, recovery_code_id).await,
+        None => Ok(false),
+    }
+}
+
+/// Generates a new set of recovery codes for the user, stores their hashes
+/// and returns the plain codes to be shown once.
+pub async fn regenerate_recovery_codes(pool: &PgPool, user_id: i64) -> Result<Vec<String>, ApiError> {
+    let codes: Vec<String> = (0..RECOVERY_CODES)
+        .map(|_| {
+            rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(RECOVERY_CODE_LENGTH)
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .collect()
+        })
+        .collect();
+
+    let plain_codes = codes.clone();
+    let code_hashes: Vec<String> = web::block(move || {
+        plain_codes.iter()
+            .map(|code| hash(code, 10).unwrap())
+            .collect()
+    }).await?;
+    db::set_recovery_codes(pool, user_id, &code_hashes).await?;
+
+    Ok(codes)
+}
+
+/// Recovery codes are case insensitive and may be typed with separators.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
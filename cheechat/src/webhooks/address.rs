@@ -0,0 +1,108 @@
+use std::net::{IpAddr, SocketAddr};
+
+use awc::http::Uri;
+
+/// The hosts the webhooks can't target, they always resolve to the server itself.
+const LOCAL_HOSTS: [&str; 2] = ["localhost", "localhost."];
+
+/// Whether the address is reachable from the internet, the webhooks must not reach the internal network:
+/// loopback, private, link-local, unique-local, shared, multicast and reserved addresses are refused.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // The shared address space of the carrier grade NATs
+                || (a == 100 && (64..128).contains(&b))
+                || a == 0
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local()),
+        },
+    }
+}
+
+/// The host and the port of the webhook url, the scheme must be http or https.
+pub fn host_and_port(url: &str) -> Result<(String, u16), String> {
+    let uri: Uri = url.parse().map_err(|_| "The url is invalid".to_owned())?;
+    let default_port = match uri.scheme_str() {
+        Some("https") => 443,
+        Some("http") => 80,
+        _ => return Err("The url must be an http or https url".into()),
+    };
+    let host = uri.host()
+        .filter(|host| !host.is_empty())
+        .ok_or_else(|| "The url must have a host".to_owned())?;
+
+    // The brackets of the ipv6 literals aren't part of the address
+    let host = host.trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase();
+    Ok((host, uri.port_u16().unwrap_or(default_port)))
+}
+
+/// Checks the host of the url without resolving it, the names are only checked at delivery time.
+pub fn check_host(host: &str) -> Result<(), String> {
+    let is_local = LOCAL_HOSTS.contains(&host) || host.ends_with(".localhost") || host.ends_with(".localhost.");
+    match host.parse::<IpAddr>() {
+        Ok(ip) if !is_public(ip) => Err("The url can't target a private address".into()),
+        Err(_) if is_local => Err("The url can't target a private address".into()),
+        _ => Ok(()),
+    }
+}
+
+/// Resolves the host of the url, the delivery is refused if any of its addresses isn't public, so the
+/// request is sent to the returned address and not resolved again.
+pub async fn resolve_public(url: &str) -> Result<SocketAddr, String> {
+    let (host, port) = host_and_port(url)?;
+    check_host(&host)?;
+
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), port))
+        .await
+        .map_err(|_| "The host of the url can't be resolved".to_owned())?
+        .collect();
+    if addrs.iter().any(|addr| !is_public(addr.ip())) {
+        return Err("The url resolves to a private address".into());
+    }
+
+    addrs.into_iter().next().ok_or_else(|| "The host of the url can't be resolved".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_the_internal_addresses() {
+        for ip in ["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0",
+            "::1", "fd00::1", "fe80::1", "::ffff:127.0.0.1", "::"] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["1.1.1.1", "93.184.216.34", "2606:4700:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn checks_the_host_of_the_url() {
+        assert_eq!(host_and_port("https://example.com/hook"), Ok(("example.com".into(), 443)));
+        assert_eq!(host_and_port("http://[::1]:8080/"), Ok(("::1".into(), 8080)));
+        assert!(host_and_port("ftp://example.com").is_err());
+
+        assert!(check_host("example.com").is_ok());
+        assert!(check_host("localhost").is_err());
+        assert!(check_host("api.localhost").is_err());
+        assert!(check_host("::1").is_err());
+        assert!(check_host("192.168.0.10").is_err());
+    }
+}
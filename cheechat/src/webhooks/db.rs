@@ -0,0 +1,324 @@
+use crate::errors::ApiError;
+use crate::chat::{Expiry, MessageCipher, SealContext, SealedMessage, SealedRow};
+use crate::webhooks::models::{PendingDelivery, Webhook, WebhookDelivery};
+use sqlx::postgres::PgPool;
+use tracing::instrument;
+
+/// Adds the webhook of the user
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id, chat_id = chat_id))]
+pub async fn add_webhook(
+    pool: &PgPool,
+    user_id: i64,
+    url: &str,
+    secret: &str,
+    events: &[String],
+    chat_id: Option<i64>,
+) -> Result<Webhook, ApiError> {
+    let webhook = sqlx::query_as!(
+        Webhook,
+        "INSERT INTO cheechat.webhooks (user_id, url, secret, events, chat_id) VALUES ($1, $2, $3, $4, $5) RETURNING *",
+        user_id,
+        url,
+        secret,
+        events,
+        chat_id
+    )
+        .fetch_one(pool)
+        .await?;
+
+    Ok(webhook)
+}
+
+/// Retrieves the webhooks of the user
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id))]
+pub async fn get_webhooks(pool: &PgPool, user_id: i64) -> Result<Vec<Webhook>, ApiError> {
+    let webhooks = sqlx::query_as!(Webhook, "SELECT * FROM cheechat.webhooks WHERE user_id = $1 ORDER BY id", user_id)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(webhooks)
+}
+
+/// Retrieves the webhook of the user based on the id
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id, webhook_id = webhook_id))]
+pub async fn get_webhook(pool: &PgPool, user_id: i64, webhook_id: i64) -> Result<Option<Webhook>, ApiError> {
+    let webhook = sqlx::query_as!(
+        Webhook,
+        "SELECT * FROM cheechat.webhooks WHERE id = $1 AND user_id = $2",
+        webhook_id,
+        user_id
+    )
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(webhook)
+}
+
+/// Deletes the webhook of the user along with its deliveries and returns whether it existed
+#[instrument(target = "cheechat::db", skip_all, fields(user_id = user_id, webhook_id = webhook_id))]
+pub async fn delete_webhook(pool: &PgPool, user_id: i64, webhook_id: i64) -> Result<bool, ApiError> {
+    let row = sqlx::query!(
+        "DELETE FROM cheechat.webhooks WHERE id = $1 AND user_id = $2 RETURNING id",
+        webhook_id,
+        user_id
+    )
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(row.is_some())
+}
+
+/// Retrieves the recent deliveries of the webhook
+#[instrument(target = "cheechat::db", skip_all, fields(webhook_id = webhook_id))]
+pub async fn get_deliveries(pool: &PgPool, webhook_id: i64, limit: i64) -> Result<Vec<WebhookDelivery>, ApiError> {
+    let deliveries = sqlx::query_as!(
+        WebhookDelivery,
+        "SELECT * FROM cheechat.webhook_deliveries WHERE webhook_id = $1 ORDER BY created_at DESC LIMIT $2",
+        webhook_id,
+        limit
+    )
+        .fetch_all(pool)
+        .await?;
+
+    Ok(deliveries)
+}
+
+/// Queues the delivery of the event to every subscribed webhook and returns the number of deliveries.
+///
+/// The chat events go to the webhooks of the participants, either limited to that chat or to all their chats.
+/// The deployment wide events (without a chat) go to the webhooks that are not limited to a chat.
+/// The payload is encrypted for each delivery, bound to its row, so it can't be moved to another webhook.
+#[instrument(target = "cheechat::db", skip_all, fields(chat_id = chat_id))]
+pub async fn add_deliveries(
+    pool: &PgPool,
+    cipher: &MessageCipher,
+    event: &str,
+    chat_id: Option<i64>,
+    payload: &str,
+) -> Result<u64, ApiError> {
+    let mut tx = pool.begin().await?;
+
+    // The ids of the deliveries are reserved first, the payloads are bound to them
+    let targets = sqlx::query!(
+        r#"
+        SELECT
+            webhooks.id AS webhook_id,
+            nextval(pg_get_serial_sequence('cheechat.webhook_deliveries', 'id')) AS "delivery_id!"
+        FROM cheechat.webhooks AS webhooks
+        JOIN cheechat.users AS users ON users.id = webhooks.user_id
+        WHERE webhooks.active
+            AND $1 = ANY(webhooks.events)
+            AND users.deleted_at IS NULL
+            AND users.suspended_at IS NULL
+            AND (
+                ($2::BIGINT IS NULL AND webhooks.chat_id IS NULL)
+                OR webhooks.chat_id = $2
+                OR (webhooks.chat_id IS NULL AND EXISTS (
+                    SELECT 1 FROM cheechat.chats
+                    WHERE chats.id = $2 AND webhooks.user_id IN (chats.user1_id, chats.user2_id)
+                ))
+            )
+        FOR SHARE OF webhooks
+        "#,
+        event,
+        chat_id
+    )
+        .fetch_all(&mut *tx)
+        .await?;
+
+    for target in &targets {
+        let context = SealContext::WebhookDelivery {
+            delivery_id: target.delivery_id,
+            webhook_id: target.webhook_id,
+            event: event.to_owned(),
+        };
+        let sealed = cipher.encrypt(payload, &context)?;
+        sqlx::query!(
+            r#"
+            INSERT INTO cheechat.webhook_deliveries (id, webhook_id, event, payload, key_id, data_key, chat_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+            target.delivery_id,
+            target.webhook_id,
+            event,
+            sealed.message,
+            sealed.key_id,
+            sealed.data_key,
+            chat_id
+        )
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    Ok(targets.len() as u64)
+}
+
+/// Deletes up to the given number of finished deliveries expired by the rule, their payloads are copies of the messages.
+#[instrument(target = "cheechat::db", skip_all)]
+pub async fn delete_expired_deliveries(pool: &PgPool, expiry: Expiry, limit: i64) -> Result<u64, ApiError> {
+    let result = match expiry {
+        Expiry::ChatTtl => sqlx::query!(
+            r#"
+            DELETE FROM cheechat.webhook_deliveries
+            WHERE id IN (
+                SELECT deliveries.id
+                FROM cheechat.chats AS chats
+                JOIN cheechat.webhook_deliveries AS deliveries ON deliveries.chat_id = chats.id
+                    AND deliveries.status <> 'pending'
+                    AND deliveries.created_at <= CURRENT_TIMESTAMP - chats.message_ttl_secs::FLOAT8 * INTERVAL '1 second'
+                WHERE chats.message_ttl_secs IS NOT NULL
+                LIMIT $1
+            )
+            "#,
+            limit
+        )
+            .execute(pool)
+            .await?,
+        Expiry::MaxAge(max_age_secs) => sqlx::query!(
+            r#"
+            DELETE FROM cheechat.webhook_deliveries
+            WHERE id IN (
+                SELECT id FROM cheechat.webhook_deliveries
+                WHERE status <> 'pending' AND created_at < CURRENT_TIMESTAMP - $1::FLOAT8 * INTERVAL '1 second'
+                LIMIT $2
+            )
+            "#,
+            max_age_secs as f64,
+            limit
+        )
+            .execute(pool)
+            .await?,
+    };
+
+    Ok(result.rows_affected())
+}
+
+/// Claims the due deliveries, along with their webhooks, for the given lease.
+///
+/// The claimed deliveries are not due again until the lease expires, so a crashed worker
+/// doesn't lose them and concurrent workers don't deliver them twice.
+#[instrument(target = "cheechat::db", skip_all)]
+pub async fn claim_due_deliveries(pool: &PgPool, limit: i64, lease_secs: i64) -> Result<Vec<PendingDelivery>, ApiError> {
+    let deliveries = sqlx::query_as!(
+        PendingDelivery,
+        r#"
+        UPDATE cheechat.webhook_deliveries AS deliveries
+        SET next_attempt_at = CURRENT_TIMESTAMP + $2::FLOAT8 * INTERVAL '1 second'
+        FROM cheechat.webhooks AS webhooks
+        WHERE deliveries.webhook_id = webhooks.id AND deliveries.id IN (
+            SELECT id FROM cheechat.webhook_deliveries
+            WHERE status = 'pending' AND next_attempt_at <= CURRENT_TIMESTAMP
+            ORDER BY next_attempt_at
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING
+            deliveries.id AS "id!",
+            deliveries.webhook_id AS "webhook_id!",
+            deliveries.event AS "event!",
+            deliveries.payload AS "payload!",
+            deliveries.key_id,
+            deliveries.data_key,
+            deliveries.attempts AS "attempts!",
+            webhooks.url AS "url!",
+            webhooks.secret AS "secret!"
+        "#,
+        limit,
+        lease_secs as f64
+    )
+        .fetch_all(pool)
+        .await?;
+
+    Ok(deliveries)
+}
+
+/// Marks the delivery as delivered.
+#[instrument(target = "cheechat::db", skip_all, fields(delivery_id = delivery_id))]
+pub async fn mark_delivered(pool: &PgPool, delivery_id: i64, response_status: i32) -> Result<(), ApiError> {
+    sqlx::query!(
+        r#"
+        UPDATE cheechat.webhook_deliveries
+        SET status = 'delivered', attempts = attempts + 1, last_response_status = $2, last_error = NULL, delivered_at = CURRENT_TIMESTAMP
+        WHERE id = $1
+        "#,
+        delivery_id,
+        response_status
+    )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Records the failed attempt of the delivery, which is retried after the given delay
+/// or marked as failed for good if there is no retry.
+#[instrument(target = "cheechat::db", skip_all, fields(delivery_id = delivery_id))]
+pub async fn mark_attempt_failed(
+    pool: &PgPool,
+    delivery_id: i64,
+    response_status: Option<i32>,
+    error: &str,
+    retry_in_secs: Option<i64>,
+) -> Result<(), ApiError> {
+    sqlx::query!(
+        r#"
+        UPDATE cheechat.webhook_deliveries SET
+            status = CASE WHEN $4::FLOAT8 IS NULL THEN 'failed' ELSE 'pending' END,
+            attempts = attempts + 1,
+            last_response_status = $2,
+            last_error = $3,
+            next_attempt_at = CURRENT_TIMESTAMP + COALESCE($4, 0) * INTERVAL '1 second'
+        WHERE id = $1
+        "#,
+        delivery_id,
+        response_status,
+        error,
+        retry_in_secs.map(|secs| secs as f64)
+    )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Retrieves the payloads that are not encrypted with the given master key, after the given id
+#[instrument(target = "cheechat::db", skip_all, fields(after_id = after_id))]
+pub async fn get_deliveries_to_reseal(pool: &PgPool, key_id: &str, after_id: i64, limit: i64) -> Result<Vec<SealedRow>, ApiError> {
+    let deliveries = sqlx::query!(
+        r#"
+        SELECT id, webhook_id, event, payload, key_id, data_key FROM cheechat.webhook_deliveries
+        WHERE id > $2 AND key_id IS DISTINCT FROM $1
+        ORDER BY id
+        LIMIT $3
+        "#,
+        key_id,
+        after_id,
+        limit
+    )
+        .map(|row| SealedRow {
+            id: row.id,
+            context: SealContext::WebhookDelivery { delivery_id: row.id, webhook_id: row.webhook_id, event: row.event },
+            sealed: SealedMessage { message: row.payload, key_id: row.key_id, data_key: row.data_key },
+        })
+        .fetch_all(pool)
+        .await?;
+
+    Ok(deliveries)
+}
+
+/// Replaces the stored payload of the delivery, e.g. once re-encrypted with another master key
+#[instrument(target = "cheechat::db", skip_all, fields(delivery_id = delivery_id))]
+pub async fn update_sealed_delivery(pool: &PgPool, delivery_id: i64, sealed: &SealedMessage) -> Result<(), ApiError> {
+    sqlx::query!(
+        "UPDATE cheechat.webhook_deliveries SET payload = $2, key_id = $3, data_key = $4 WHERE id = $1",
+        delivery_id,
+        sealed.message,
+        sealed.key_id,
+        sealed.data_key
+    )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
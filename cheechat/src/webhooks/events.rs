@@ -0,0 +1,21 @@
+use chrono::Utc;
+use sqlx::postgres::PgPool;
+
+use crate::chat::MessageCipher;
+use crate::webhooks::db;
+use crate::webhooks::models::{WebhookEvent, WebhookPayload};
+
+/// Queues the event for the subscribed webhooks, the failures are logged and don't affect the caller.
+///
+/// The payload carries the messages, so it is encrypted at rest like them.
+pub async fn dispatch(pool: &PgPool, cipher: &MessageCipher, event: WebhookEvent) {
+    let payload = WebhookPayload { event: &event, created_at: Utc::now().timestamp() };
+    let payload = serde_json::to_string(&payload).unwrap();
+    let kind = event.kind().as_str();
+
+    match db::add_deliveries(pool, cipher, kind, event.chat_id(), &payload).await {
+        Ok(0) => (),
+        Ok(count) => tracing::debug!("Queued {count} webhook deliveries of {kind}"),
+        Err(err) => tracing::error!("Error queueing the webhook deliveries of {kind}: {err}"),
+    }
+}
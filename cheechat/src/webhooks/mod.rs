@@ -0,0 +1,34 @@
+use actix_web::web;
+use utoipa::OpenApi;
+
+mod address;
+mod events;
+mod services;
+mod worker;
+mod db;
+mod models;
+
+pub use db::*;
+pub use events::dispatch;
+pub use models::*;
+pub use worker::{sign, WebhookWorker};
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(services::create_webhook);
+    cfg.service(services::get_webhooks);
+    cfg.service(services::delete_webhook);
+    cfg.service(services::get_deliveries);
+}
+
+/// The OpenAPI description of the routes.
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        services::create_webhook,
+        services::get_webhooks,
+        services::delete_webhook,
+        services::get_deliveries,
+    ),
+    tags((name = "webhooks", description = "The webhooks notified of the events of the chats, and their delivery log")),
+)]
+pub struct ApiDoc;
@@ -0,0 +1,216 @@
+use serde::{Deserialize, Serialize};
+use sqlx;
+use sqlx::types::time::PrimitiveDateTime;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::webhooks::address;
+
+/// The kinds of events the webhooks can subscribe to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEventKind {
+    MessageCreated,
+    ChatCreated,
+    /// A deployment wide event, only available to the admins.
+    UserRegistered,
+}
+
+impl WebhookEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEventKind::MessageCreated => "message_created",
+            WebhookEventKind::ChatCreated => "chat_created",
+            WebhookEventKind::UserRegistered => "user_registered",
+        }
+    }
+}
+
+/// The events that get delivered to the webhooks, serialized as the `event` and its `data`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", content = "data", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    MessageCreated {
+        chat_id: i64,
+        message_id: i64,
+        sender_id: i64,
+        message: String,
+        sent_at: i64,
+    },
+    ChatCreated {
+        chat_id: i64,
+        user_ids: [i64; 2],
+    },
+    UserRegistered {
+        user_id: i64,
+        username: String,
+    },
+}
+
+impl WebhookEvent {
+    pub fn kind(&self) -> WebhookEventKind {
+        match self {
+            WebhookEvent::MessageCreated { .. } => WebhookEventKind::MessageCreated,
+            WebhookEvent::ChatCreated { .. } => WebhookEventKind::ChatCreated,
+            WebhookEvent::UserRegistered { .. } => WebhookEventKind::UserRegistered,
+        }
+    }
+
+    /// The chat the event belongs to, the events without a chat are deployment wide.
+    pub fn chat_id(&self) -> Option<i64> {
+        match self {
+            WebhookEvent::MessageCreated { chat_id, .. } => Some(*chat_id),
+            WebhookEvent::ChatCreated { chat_id, .. } => Some(*chat_id),
+            WebhookEvent::UserRegistered { .. } => None,
+        }
+    }
+}
+
+/// The JSON body of the webhook requests.
+#[derive(Debug, Serialize)]
+pub struct WebhookPayload<'a> {
+    #[serde(flatten)]
+    pub event: &'a WebhookEvent,
+    pub created_at: i64,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Webhook {
+    pub id: i64,
+    pub user_id: i64,
+    pub url: String,
+    /// The key of the HMAC signature of the payloads.
+    pub secret: String,
+    pub events: Vec<String>,
+    /// The chat the webhook is limited to, or all the chats of the user if not provided.
+    pub chat_id: Option<i64>,
+    pub active: bool,
+    pub created_at: PrimitiveDateTime,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct WebhookInfo {
+    pub id: i64,
+    pub url: String,
+    pub events: Vec<String>,
+    pub chat_id: Option<i64>,
+    pub active: bool,
+    pub created_at: i64,
+}
+
+impl WebhookInfo {
+    pub fn from_webhook(webhook: Webhook) -> Self {
+        Self {
+            id: webhook.id,
+            url: webhook.url,
+            events: webhook.events,
+            chat_id: webhook.chat_id,
+            active: webhook.active,
+            created_at: webhook.created_at.assume_utc().unix_timestamp(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct CreateWebhook {
+    /// The http or https url of a public host.
+    pub url: String,
+    pub events: Vec<WebhookEventKind>,
+    /// Limits the webhook to a chat of the user, or all their chats if not provided.
+    pub chat_id: Option<i64>,
+}
+
+impl CreateWebhook {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.url.chars().count() > 500 {
+            return Err("The url can't be longer than 500 characters".into());
+        }
+        let (host, _) = address::host_and_port(&self.url)?;
+        address::check_host(&host)?;
+        if self.events.is_empty() {
+            return Err("At least one event must be provided".into());
+        }
+
+        Ok(())
+    }
+}
+
+/// The created webhook along with its secret, which is only shown once.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct WebhookCreated {
+    #[serde(flatten)]
+    pub webhook: WebhookInfo,
+    pub secret: String,
+}
+
+/// A queued delivery of an event to a webhook.
+#[derive(Debug, sqlx::FromRow)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event: String,
+    pub payload: String,
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: PrimitiveDateTime,
+    pub last_response_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_at: PrimitiveDateTime,
+    pub delivered_at: Option<PrimitiveDateTime>,
+    /// The master key that wraps the data key of the payload, none for the plain text payloads.
+    pub key_id: Option<String>,
+    pub data_key: Option<String>,
+    /// The chat of the event, if any, so the payload expires along with its messages.
+    pub chat_id: Option<i64>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct DeliveryInfo {
+    pub id: i64,
+    pub event: String,
+    pub status: String,
+    pub attempts: i32,
+    /// When the next attempt is due, for the pending deliveries.
+    pub next_attempt_at: Option<i64>,
+    pub last_response_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub created_at: i64,
+    pub delivered_at: Option<i64>,
+}
+
+impl DeliveryInfo {
+    pub fn from_delivery(delivery: WebhookDelivery) -> Self {
+        Self {
+            id: delivery.id,
+            next_attempt_at: (delivery.status == "pending")
+                .then(|| delivery.next_attempt_at.assume_utc().unix_timestamp()),
+            event: delivery.event,
+            status: delivery.status,
+            attempts: delivery.attempts,
+            last_response_status: delivery.last_response_status,
+            last_error: delivery.last_error,
+            created_at: delivery.created_at.assume_utc().unix_timestamp(),
+            delivered_at: delivery.delivered_at.map(|dt| dt.assume_utc().unix_timestamp()),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeliveriesQuery {
+    pub limit: Option<i64>,
+}
+
+/// A due delivery along with the webhook to send it to.
+#[derive(Debug)]
+pub struct PendingDelivery {
+    pub id: i64,
+    pub webhook_id: i64,
+    pub event: String,
+    /// The payload as it is stored, encrypted at rest if there is a key.
+    pub payload: String,
+    pub key_id: Option<String>,
+    pub data_key: Option<String>,
+    pub attempts: i32,
+    pub url: String,
+    pub secret: String,
+}
@@ -0,0 +1,127 @@
+use actix_web::web::Path;
+use actix_web::{delete, get, post, web, Error, HttpResponse};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sqlx::postgres::PgPool;
+
+use crate::chat;
+use crate::errors::ApiError;
+use crate::users::{AuthUser, Role};
+use crate::webhooks::db;
+use crate::webhooks::models::{CreateWebhook, DeliveriesQuery, DeliveryInfo, WebhookCreated, WebhookEventKind, WebhookInfo};
+
+const DEFAULT_DELIVERIES_LIMIT: i64 = 50;
+const MAX_DELIVERIES_LIMIT: i64 = 200;
+
+/// Registers a webhook for the chats of the current user, the secret is only returned here
+#[utoipa::path(
+    tag = "webhooks",
+    request_body = CreateWebhook,
+    responses(
+        (status = 201, body = WebhookCreated),
+        (status = 400, description = "Invalid url or events", body = String, content_type = "text/plain"),
+        (status = 403, description = "Only the admins may subscribe to `user_registered`"),
+        (status = 404, description = "No such chat, or the user doesn't participate in it"),
+    ),
+)]
+#[post("/webhooks")]
+pub async fn create_webhook(
+    auth_user: AuthUser,
+    request: web::Json<CreateWebhook>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, Error> {
+    let request = request.into_inner();
+    request.validate().map_err(ApiError::BadRequest)?;
+
+    if request.events.contains(&WebhookEventKind::UserRegistered) {
+        auth_user.require_role(Role::Admin)?;
+        if request.chat_id.is_some() {
+            return Err(ApiError::BadRequest("The user_registered event can't be limited to a chat".into()).into());
+        }
+    }
+    if let Some(chat_id) = request.chat_id {
+        // The webhooks can only be limited to the chats of the user
+        let is_participant = chat::get_participated_chat(db_pool.get_ref(), chat_id, auth_user.id)
+            .await?
+            .is_some();
+        if !is_participant {
+            return Err(ApiError::NotFound.into());
+        }
+    }
+
+    let secret: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(40)
+        .map(char::from)
+        .collect();
+    let mut events: Vec<String> = request.events.iter().map(|event| event.as_str().to_owned()).collect();
+    events.sort_unstable();
+    events.dedup();
+
+    let webhook = db::add_webhook(db_pool.get_ref(), auth_user.id, &request.url, &secret, &events, request.chat_id).await?;
+
+    Ok(HttpResponse::Created().json(WebhookCreated {
+        webhook: WebhookInfo::from_webhook(webhook),
+        secret,
+    }))
+}
+
+/// Gets the webhooks of the current user
+#[utoipa::path(
+    tag = "webhooks",
+    responses((status = 200, body = Vec<WebhookInfo>)),
+)]
+#[get("/webhooks")]
+pub async fn get_webhooks(auth_user: AuthUser, db_pool: web::Data<PgPool>) -> Result<HttpResponse, Error> {
+    let webhooks = db::get_webhooks(db_pool.get_ref(), auth_user.id).await?;
+
+    let webhooks: Vec<_> = webhooks.into_iter().map(WebhookInfo::from_webhook).collect();
+    Ok(HttpResponse::Ok().json(webhooks))
+}
+
+/// Deletes the webhook of the current user, the pending deliveries are dropped
+#[utoipa::path(
+    tag = "webhooks",
+    params(("webhook_id" = i64, Path, description = "The id of the webhook")),
+    responses(
+        (status = 204, description = "The webhook is deleted"),
+        (status = 404, description = "No such webhook"),
+    ),
+)]
+#[delete("/webhooks/{webhook_id}")]
+pub async fn delete_webhook(auth_user: AuthUser, path: Path<i64>, db_pool: web::Data<PgPool>) -> Result<HttpResponse, Error> {
+    if !db::delete_webhook(db_pool.get_ref(), auth_user.id, path.into_inner()).await? {
+        return Err(ApiError::NotFound.into());
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Gets the delivery log of the webhook of the current user, most recent first
+#[utoipa::path(
+    tag = "webhooks",
+    params(("webhook_id" = i64, Path, description = "The id of the webhook"), DeliveriesQuery),
+    responses(
+        (status = 200, body = Vec<DeliveryInfo>),
+        (status = 404, description = "No such webhook"),
+    ),
+)]
+#[get("/webhooks/{webhook_id}/deliveries")]
+pub async fn get_deliveries(
+    auth_user: AuthUser,
+    path: Path<i64>,
+    query_params: web::Query<DeliveriesQuery>,
+    db_pool: web::Data<PgPool>,
+) -> Result<HttpResponse, Error> {
+    let webhook = db::get_webhook(db_pool.get_ref(), auth_user.id, path.into_inner())
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    let limit = query_params.limit
+        .unwrap_or(DEFAULT_DELIVERIES_LIMIT)
+        .clamp(1, MAX_DELIVERIES_LIMIT);
+
+    let deliveries = db::get_deliveries(db_pool.get_ref(), webhook.id, limit).await?;
+
+    let deliveries: Vec<_> = deliveries.into_iter().map(DeliveryInfo::from_delivery).collect();
+    Ok(HttpResponse::Ok().json(deliveries))
+}
@@ -0,0 +1,191 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix::{Actor, ActorFutureExt, AsyncContext, Context, ContextFutureSpawner, WrapFuture};
+use awc::error::SendRequestError;
+use awc::Client;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::postgres::PgPool;
+
+use crate::chat::{MessageCipher, SealContext};
+use crate::errors::ApiError;
+use crate::webhooks::{address, db};
+use crate::webhooks::models::PendingDelivery;
+
+/// How often the queue is checked for due deliveries.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// The number of deliveries claimed at once.
+const BATCH_SIZE: i64 = 20;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the claimed deliveries are reserved for the worker, enough to send a whole batch.
+const LEASE_SECS: i64 = 300;
+/// The number of attempts after which a delivery is marked as failed.
+const MAX_ATTEMPTS: i32 = 8;
+const BASE_RETRY_SECS: i64 = 30;
+const MAX_RETRY_SECS: i64 = 6 * 3600;
+
+/// The actor that sends the queued webhook deliveries and retries the failed ones with an exponential backoff.
+///
+/// The queue lives in the database, so the pending deliveries survive the restarts.
+pub struct WebhookWorker {
+    db_pool: PgPool,
+    cipher: Arc<MessageCipher>,
+    /// Whether a batch is being delivered, so the polls don't overlap.
+    busy: bool,
+}
+
+impl WebhookWorker {
+    pub fn new(db_pool: PgPool, cipher: Arc<MessageCipher>) -> Self {
+        Self { db_pool, cipher, busy: false }
+    }
+
+    fn poll(&mut self, ctx: &mut Context<Self>) {
+        if self.busy {
+            return;
+        }
+        self.busy = true;
+
+        let db_pool = self.db_pool.clone();
+        let cipher = self.cipher.clone();
+        async move { deliver_due(&db_pool, &cipher).await }
+            .into_actor(self)
+            .map(|result, act, _| {
+                act.busy = false;
+                if let Err(err) = result {
+                    tracing::error!("Error delivering the webhooks: {err}");
+                }
+            })
+            .spawn(ctx);
+    }
+}
+
+impl Actor for WebhookWorker {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(POLL_INTERVAL, |act, ctx| act.poll(ctx));
+    }
+}
+
+/// Sends the due deliveries and records the outcome of each attempt.
+async fn deliver_due(db_pool: &PgPool, cipher: &MessageCipher) -> Result<(), ApiError> {
+    let deliveries = db::claim_due_deliveries(db_pool, BATCH_SIZE, LEASE_SECS).await?;
+    if deliveries.is_empty() {
+        return Ok(());
+    }
+
+    // The redirects could lead to the internal network, they count as failed attempts
+    let client = Client::builder().timeout(REQUEST_TIMEOUT).disable_redirects().finish();
+    for delivery in deliveries {
+        let context = SealContext::WebhookDelivery {
+            delivery_id: delivery.id,
+            webhook_id: delivery.webhook_id,
+            event: delivery.event.clone(),
+        };
+        let payload = match cipher.decrypt(delivery.payload.clone(), delivery.key_id.as_deref(), delivery.data_key.as_deref(), &context) {
+            Ok(payload) => payload,
+            // The key may be missing from the configuration for now
+            Err(_) => {
+                retry_later(db_pool, &delivery, None, "The payload could not be decrypted").await?;
+                continue;
+            }
+        };
+
+        // The host is resolved once and checked, so the name can't point elsewhere when connecting
+        let addr = match address::resolve_public(&delivery.url).await {
+            Ok(addr) => addr,
+            Err(error) => {
+                retry_later(db_pool, &delivery, None, &error).await?;
+                continue;
+            }
+        };
+
+        let timestamp = Utc::now().timestamp();
+        let response = client.post(&delivery.url)
+            .address(addr)
+            .insert_header(("Content-Type", "application/json"))
+            .insert_header(("X-Cheechat-Event", delivery.event.as_str()))
+            .insert_header(("X-Cheechat-Delivery", delivery.id.to_string()))
+            .insert_header(("X-Cheechat-Timestamp", timestamp.to_string()))
+            .insert_header(("X-Cheechat-Signature", sign(&delivery.secret, timestamp, &payload)))
+            .send_body(payload)
+            .await;
+
+        match response {
+            Ok(response) if response.status().is_success() => {
+                db::mark_delivered(db_pool, delivery.id, response.status().as_u16().into()).await?;
+            }
+            Ok(response) => {
+                let status = response.status();
+                let error = format!("Unexpected response status {status}");
+                retry_later(db_pool, &delivery, Some(status.as_u16().into()), &error).await?;
+            }
+            Err(err) => {
+                // The transport errors may describe the internal network, the users only get their kind
+                tracing::debug!("Error sending the webhook delivery {}: {err}", delivery.id);
+                retry_later(db_pool, &delivery, None, describe_error(&err)).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Records the failed attempt, the delivery is retried with an exponential backoff until it runs out of attempts.
+async fn retry_later(db_pool: &PgPool, delivery: &PendingDelivery, status: Option<i32>, error: &str) -> Result<(), ApiError> {
+    let attempt = delivery.attempts + 1;
+    let retry_in_secs = retry_delay_secs(attempt);
+
+    tracing::warn!("Webhook delivery {} failed (attempt {attempt}): {error}", delivery.id);
+    db::mark_attempt_failed(db_pool, delivery.id, status, error, retry_in_secs).await
+}
+
+/// The delay before the next attempt, doubled after each attempt up to [`MAX_RETRY_SECS`],
+/// or none once the delivery ran out of attempts.
+fn retry_delay_secs(attempt: i32) -> Option<i64> {
+    (attempt < MAX_ATTEMPTS).then(|| (BASE_RETRY_SECS << (attempt - 1).min(20)).min(MAX_RETRY_SECS))
+}
+
+/// The error shown in the delivery log.
+fn describe_error(err: &SendRequestError) -> &'static str {
+    match err {
+        SendRequestError::Timeout => "The request timed out",
+        SendRequestError::Connect(_) => "The connection failed",
+        _ => "The request failed",
+    }
+}
+
+/// Signs the payload with the secret of the webhook: `sha256=` followed by the hex encoded
+/// HMAC-SHA256 of `{timestamp}.{payload}`, so the receivers can verify the origin and reject replays.
+pub fn sign(secret: &str, timestamp: i64, payload: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(payload.as_bytes());
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_the_timestamp_and_the_payload() {
+        assert_eq!(
+            sign("secret", 1700000000, r#"{"event":"chat_created"}"#),
+            "sha256=2bad9675304008d1cea79325b8341b552678d46077ba3eb85caa4499688a3fb7",
+        );
+    }
+
+    #[test]
+    fn caps_the_backoff() {
+        assert_eq!(retry_delay_secs(1), Some(BASE_RETRY_SECS));
+        assert_eq!(retry_delay_secs(2), Some(2 * BASE_RETRY_SECS));
+        assert!((1..MAX_ATTEMPTS).all(|attempt| retry_delay_secs(attempt) <= Some(MAX_RETRY_SECS)));
+        assert_eq!(retry_delay_secs(MAX_ATTEMPTS), None);
+    }
+}
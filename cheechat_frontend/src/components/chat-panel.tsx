@@ -26,6 +26,8 @@ type Message = {
     sender: 'user' | 'other';
     content: string;
     timestamp: number;
+    // Why the server rejected the message, it was not sent
+    rejected?: string;
 }
 
 const generateMessageId = (): string => {
@@ -37,6 +39,8 @@ export default function ChatPanel({ chat_id, current_user_id, otherUser, onClose
     const [newMessage, setNewMessage] = useState('')
     const [wsReady, setWsReady] = useState(false)
     const ws = useRef<WebSocket | null>(null)
+    // The local echo of the last message sent, none for the commands whose output comes back from the server
+    const pendingEchoId = useRef<string | null>(null)
     const scrollAreaRef = useRef<HTMLDivElement>(null)
 
     useEffect(() => {
@@ -50,8 +54,68 @@ export default function ChatPanel({ chat_id, current_user_id, otherUser, onClose
 
         ws.current.onmessage = (event) => {
             const data = JSON.parse(event.data)
+            if (data.type === 'message_deleted') {
+                setMessages((prevMessages) => prevMessages.filter((message) => message.id !== String(data.message_id)))
+                return
+            }
+            if (data.type === 'messages_expired') {
+                const expired = new Set(data.message_ids.map(String))
+                setMessages((prevMessages) => prevMessages.filter((message) => !expired.has(message.id)))
+                return
+            }
+            if (data.type === 'message_filtered') {
+                // The others see the stored content, e.g. with masked words, the local echo shows it too
+                const echoId = pendingEchoId.current
+                pendingEchoId.current = null
+                if (echoId !== null) {
+                    setMessages((prevMessages) => prevMessages.map((message) => message.id === echoId
+                        ? { ...message, id: String(data.message_id), content: data.message }
+                        : message))
+                    return
+                }
+                // The output of a command, e.g. `/me`, the sender has no local echo of it
+                const filtered: Message = {
+                    id: String(data.message_id),
+                    sender: 'user',
+                    content: data.message,
+                    timestamp: Date.now()
+                }
+                setMessages((prevMessages) => [...prevMessages, filtered])
+                return
+            }
+            if (data.type === 'message_rejected') {
+                const echoId = pendingEchoId.current
+                pendingEchoId.current = null
+                if (echoId !== null) {
+                    setMessages((prevMessages) => prevMessages.map((message) => message.id === echoId
+                        ? { ...message, rejected: data.reason }
+                        : message))
+                    return
+                }
+                const rejection: Message = {
+                    id: generateMessageId(),
+                    sender: 'other',
+                    content: `Not sent: ${data.reason}`,
+                    timestamp: Date.now()
+                }
+                setMessages((prevMessages) => [...prevMessages, rejection])
+                return
+            }
+            if (data.type === 'command_reply') {
+                const reply: Message = {
+                    id: generateMessageId(),
+                    sender: 'other',
+                    content: data.message,
+                    timestamp: Date.now()
+                }
+                setMessages((prevMessages) => [...prevMessages, reply])
+                return
+            }
+            if (data.type !== 'message') {
+                return
+            }
             const message: Message = {
-                id: generateMessageId(),
+                id: String(data.id),
                 sender: data.sender_id === current_user_id ? 'user' : 'other',
                 content: data.message,
                 timestamp: new Date(data.sent_at).getTime()
@@ -84,13 +148,19 @@ export default function ChatPanel({ chat_id, current_user_id, otherUser, onClose
     const handleSendMessage = (e: React.FormEvent) => {
         e.preventDefault()
         if (newMessage.trim() && wsReady && ws.current) {
+            // The slash commands are handled by the server, their replies come back as command_reply
+            // and their messages as message_filtered, a double slash sends the text with a single one
+            const isCommand = newMessage.startsWith('/') && !newMessage.startsWith('//')
             const message: Message = {
                 id: generateMessageId(),
                 sender: 'user',
-                content: newMessage,
+                content: newMessage.startsWith('//') ? newMessage.slice(1) : newMessage,
                 timestamp: Date.now()
             }
-            setMessages((prevMessages) => [...prevMessages, message])
+            pendingEchoId.current = isCommand ? null : message.id
+            if (!isCommand) {
+                setMessages((prevMessages) => [...prevMessages, message])
+            }
             ws.current.send(newMessage)
             setNewMessage('')
         }
@@ -131,7 +201,7 @@ export default function ChatPanel({ chat_id, current_user_id, otherUser, onClose
                                     message.sender === 'user'
                                         ? 'bg-[#8b8b7a] text-white'
                                         : 'bg-[#5f5f55] text-white'
-                                }`}
+                                } ${message.rejected ? 'opacity-50' : ''}`}
                                 style={{
                                     wordBreak: 'break-word',
                                     overflowWrap: 'break-word',
@@ -141,6 +211,9 @@ export default function ChatPanel({ chat_id, current_user_id, otherUser, onClose
                                 <p className="text-xs opacity-75 mt-1">
                                     {new Date(message.timestamp).toLocaleTimeString()}
                                 </p>
+                                {message.rejected && (
+                                    <p className="text-xs mt-1">Not sent: {message.rejected}</p>
+                                )}
                             </div>
                         </div>
                     ))}